@@ -8,15 +8,22 @@ extern crate phf;
 extern crate piston_window;
 extern crate rand;
 extern crate fps_counter;
+extern crate zip;
+extern crate flate2;
+#[cfg(feature = "audio")]
+extern crate cpal;
 
 mod nes;
 
 use std::env;
+use std::process;
+use std::time::{Duration,Instant};
 use crate::nes::*;
 use std::path::Path;
 use piston_window::*;
 use nes::cpu::*;
 use nes::disasm::*;
+use nes::input::{Input,Zapper};
 use opengl_graphics::OpenGL;
 use log::Level;
 use failure::Error;
@@ -25,6 +32,12 @@ use gfx_glyph::{Section, GlyphBrushBuilder,GlyphBrush, Scale};
 use gfx_device_gl::{Resources,Factory};
 
 const BG_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+// how often battery backed RAM is written to the .sav file
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+// position and scale of the NES screen in the window
+const SCREEN_X: f64 = 300.0;
+const SCREEN_Y: f64 = 40.0;
+const SCREEN_SCALE: f64 = 3.0;
 
 // font options
 const FT_SIZE_PX: f32 = 11.0;
@@ -38,15 +51,38 @@ lazy_static! {
 
 fn main() -> Result<(), Error> {
     simple_logger::init_with_level(Level::Info).unwrap();
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let wav_path = take_option(&mut args, "--wav")?;
+    let record_path = take_option(&mut args, "--record")?;
+    let play_path = take_option(&mut args, "--play")?;
+    if record_path.is_some() && play_path.is_some() {
+        bail!("--record and --play can't be used together");
+    }
+    let cycle_accurate = take_flag(&mut args, "--cycle-accurate");
+    let port_kinds = [
+        take_option(&mut args, "--port1")?.map_or(Ok(InputKind::Controller), |kind| kind.parse())?,
+        take_option(&mut args, "--port2")?.map_or(Ok(InputKind::Controller), |kind| kind.parse())?,
+    ];
     if args.len() < 2 {
-        bail!("No cartridge supplied. Usage: ./jane cartridge.nes");
+        bail!("No cartridge supplied. Usage: ./jane [--wav out.wav] [--port1 device] [--port2 device] \
+            [--record movie.fm2 | --play movie.fm2] [--cycle-accurate] cartridge.nes|.zip|.gz");
     } else {
         println!("Loading cartridge: {}", args[1]);
     }
 
     let mut nes = NES::new();
-    let cartridge = Cartridge::new(Path::new(&args[1]))?;
+    if cycle_accurate {
+        println!("Running the CPU cycle by cycle");
+        nes.cpu.mode = ExecMode::Cycle;
+    }
+    let cartridge = match Cartridge::new(Path::new(&args[1])) {
+        Ok(cartridge) => cartridge,
+        Err(e) => {
+            eprintln!("Can't load {}: {}", args[1], e);
+            process::exit(1);
+        }
+    };
+    println!("{}", cartridge.info());
     nes.insert_cartridge(cartridge);
     nes.start();
     if args.len() > 2 {
@@ -54,6 +90,26 @@ fn main() -> Result<(), Error> {
         println!("Setting PC to {:#06x}", pc);
         nes.cpu.regs.pc = pc;
     }
+    setup_audio(&mut nes, wav_path)?;
+    for (port, &kind) in port_kinds.iter().enumerate() {
+        if kind != InputKind::Controller {
+            println!("Port {}: {}", port + 1, kind);
+        }
+        nes.connect_input(port, kind);
+    }
+
+    // movies start at power on
+    if let Some(path) = &play_path {
+        let movie = Movie::load(Path::new(path))?;
+        println!("Playing movie {} ({} frames)", path, movie.frames.len());
+        nes.start_movie(movie, MovieMode::Play);
+    } else if let Some(path) = &record_path {
+        println!("Recording movie to {}", path);
+        let mut movie = Movie::new(nes.inputs[0].kind(), nes.inputs[1].kind());
+        movie.rom_filename = Path::new(&args[1]).file_stem()
+            .map_or(String::new(), |name| name.to_string_lossy().to_string());
+        nes.start_movie(movie, MovieMode::Record);
+    }
 
     // disassemble instructions
     let disasm = Disasm::disassemble(&nes.bus, 0xC000, 0xFFFF).unwrap();
@@ -61,8 +117,8 @@ fn main() -> Result<(), Error> {
     // Prepare window and drawing resources
 
     // debugger + scaled nes resolution + border
-    let window_width = 300 + 256 * 3 + 5;
-    let window_height = 40 + 240 * 3 + 5;
+    let window_width = (SCREEN_X + 256.0 * SCREEN_SCALE) as u32 + 5;
+    let window_height = (SCREEN_Y + 240.0 * SCREEN_SCALE) as u32 + 5;
     let mut window: PistonWindow = WindowSettings::new("xXx NESemu xXx", [window_width, window_height])
         .exit_on_esc(true).graphics_api(OpenGL::V3_2).build().unwrap();
     let mut event_settings = EventSettings::new();
@@ -111,16 +167,26 @@ fn main() -> Result<(), Error> {
     
     // Main loop
     let mut run = false;
+    let mut last_save = Instant::now();
     while let Some(event) = events.next(&mut window) {
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            if let Err(e) = nes.flush_save() {
+                warn!("Saving failed: {}", e);
+            }
+            last_save = Instant::now();
+        }
         if let Some(_) = event.render_args() {
             // Run enough clocks to render the next frame
             // if run { nes.clock_frame(); }
-            if run { nes.clock_frame(); }
+            if run {
+                let result = nes.clock_frame();
+                run = handle_step_result(result);
+            }
 
             {
                 let mut ppu = nes.ppu.borrow_mut();
                 let ppu_bus = nes.ppu_bus.borrow();
-                // main_texture.update(&mut texture_ctx, &ppu.canvas_main).unwrap();
+                main_texture.update(&mut texture_ctx, &ppu.canvas_main).unwrap();
                 pattern_table_textures[0].update(&mut texture_ctx, &ppu.get_pattern_table(&*ppu_bus, 0, 0)).unwrap();
                 pattern_table_textures[1].update(&mut texture_ctx, &ppu.get_pattern_table(&*ppu_bus, 1, 0)).unwrap();
                 palette_textures[0].update(&mut texture_ctx, &ppu.get_palette(&*ppu_bus, 0)).unwrap();
@@ -135,7 +201,7 @@ fn main() -> Result<(), Error> {
             window.draw_2d(&event, |c, g, d| {
                 clear(BG_COLOR, g);
                 texture_ctx.encoder.flush(d);
-                let transform = c.transform.trans(300.0, 40.0).scale(3.0, 3.0);
+                let transform = c.transform.trans(SCREEN_X, SCREEN_Y).scale(SCREEN_SCALE, SCREEN_SCALE);
                 image(&main_texture, transform, g);
 
                 let mut transform = c.transform.trans(10.0, 480.0).scale(7.0, 7.0);
@@ -172,24 +238,210 @@ fn main() -> Result<(), Error> {
                     ..Section::default() 
                 });
 
+                // halted cpu
+                if let Some(halt) = nes.cpu.halted() {
+                    glyphs.queue(Section {
+                        text: &format!("{}. F12 to reset", halt),
+                        scale: *FT_SCALE,
+                        screen_position: (520.0, 10.0 + FT_SIZE_PX),
+                        color: FT_COLOR_RED,
+                        ..Section::default()
+                    });
+                }
             });
             render_debug(&mut window, &event, &mut glyphs, &nes, &disasm);
         }
         if let Some(Button::Keyboard(key)) = event.press_args() {
-            match key {
-                Key::C => nes.clock(),  // advance one clock
-                Key::S => { nes.clock_instruction() }
-                Key::F => { nes.clock_frame() }
-                Key::L => { nes.clock_scanline() }
-                Key::R => nes.reset(),
-                Key::Space => run = !run,
-                _ => { }
+            handle_input_key(&nes, key, true);
+            // debugger keys
+            let result = match key {
+                Key::F5 => { run = !run; StepResult::Running }
+                Key::F6 => nes.clock(),  // advance one clock
+                Key::F7 => { nes.clock_instruction() }
+                Key::F8 => { nes.clock_scanline() }
+                Key::F9 => { nes.clock_frame() }
+                Key::F12 => { nes.reset(); StepResult::Running }
+                _ => StepResult::Running
+            };
+            if !handle_step_result(result) {
+                run = false;
             }
         }     
+        if let Some(Button::Keyboard(key)) = event.release_args() {
+            handle_input_key(&nes, key, false);
+        }
+        // zapper: aim with the mouse, left button pulls the trigger
+        if let Some([x, y]) = event.mouse_cursor_args() {
+            let aim = (((x - SCREEN_X) / SCREEN_SCALE) as i32, ((y - SCREEN_Y) / SCREEN_SCALE) as i32);
+            for_each_zapper(&nes, |zapper| zapper.aim_at(aim.0, aim.1));
+        }
+        if let Some(Button::Mouse(MouseButton::Left)) = event.press_args() {
+            for_each_zapper(&nes, |zapper| zapper.trigger = true);
+        }
+        if let Some(Button::Mouse(MouseButton::Left)) = event.release_args() {
+            for_each_zapper(&nes, |zapper| zapper.trigger = false);
+        }
+    }
+    nes.audio.flush();
+    nes.flush_save()?;
+    if let (Some(path), Some(movie)) = (&record_path, nes.stop_movie()) {
+        println!("Saving movie {} ({} frames)", path, movie.frames.len());
+        movie.save(Path::new(path))?;
+    }
+    Ok(())
+}
+
+// Remove "name value" from the command line arguments. Returns the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, Error> {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(value))
+        },
+        Some(_) => bail!("Missing value for {}", name),
+        None => Ok(None),
+    }
+}
+
+// Remove a flag without value from the command line arguments. Returns
+// whether it was there
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    }
+}
+
+// Connect the audio outputs: the sound card if jane is built with the
+// audio feature, and a wav file if requested. Without a sound card the
+// wav file is written at the default sample rate
+fn setup_audio(nes: &mut NES, wav_path: Option<String>) -> Result<(), Error> {
+    #[cfg(feature = "audio")]
+    match nes::audio::LiveSink::new() {
+        Ok(sink) => {
+            nes.audio = Audio::new(sink.sample_rate());
+            nes.audio.add_sink(Box::new(sink));
+        },
+        Err(e) => warn!("No live audio output: {}", e),
+    }
+    if let Some(path) = wav_path {
+        println!("Writing audio to {}", path);
+        let sink = nes::audio::WavSink::create(Path::new(&path), nes.audio.sample_rate())?;
+        nes.audio.add_sink(Box::new(sink));
     }
     Ok(())
 }
 
+// Feed a key press or release into the connected input devices
+fn handle_input_key(nes: &NES, key: Key, pressed: bool) {
+    for (port, input) in nes.inputs.iter().enumerate() {
+        match input {
+            Input::Controller(controller) => {
+                if let Some((player, button)) = controller_button(key) {
+                    if player == port {
+                        controller.borrow_mut().set_button(button, pressed);
+                    }
+                }
+            },
+            // port 1 has players 1 and 3, port 2 players 2 and 4
+            Input::FourScore(four_score) => {
+                if let Some((player, button)) = controller_button(key) {
+                    if player % 2 == port {
+                        four_score.borrow_mut().controllers[player / 2].set_button(button, pressed);
+                    }
+                }
+            },
+            Input::PowerPad(power_pad) => {
+                if let Some(button) = power_pad_button(key) {
+                    power_pad.borrow_mut().set_button(button, pressed);
+                }
+            },
+            Input::Zapper(_) | Input::None => { },
+        }
+    }
+}
+
+fn for_each_zapper<F: Fn(&mut Zapper)>(nes: &NES, f: F) {
+    for input in nes.inputs.iter() {
+        if let Input::Zapper(zapper) = input {
+            f(&mut zapper.borrow_mut());
+        }
+    }
+}
+
+// Keyboard layout of the controllers: player (0-3) and button of a key
+fn controller_button(key: Key) -> Option<(usize, Buttons)> {
+    let mapping = match key {
+        // player 1
+        Key::X => (0, Buttons::A),
+        Key::Z => (0, Buttons::B),
+        Key::RShift => (0, Buttons::SELECT),
+        Key::Return => (0, Buttons::START),
+        Key::Up => (0, Buttons::UP),
+        Key::Down => (0, Buttons::DOWN),
+        Key::Left => (0, Buttons::LEFT),
+        Key::Right => (0, Buttons::RIGHT),
+        // player 2
+        Key::H => (1, Buttons::A),
+        Key::G => (1, Buttons::B),
+        Key::T => (1, Buttons::SELECT),
+        Key::Y => (1, Buttons::START),
+        Key::W => (1, Buttons::UP),
+        Key::S => (1, Buttons::DOWN),
+        Key::A => (1, Buttons::LEFT),
+        Key::D => (1, Buttons::RIGHT),
+        // player 3 (four score)
+        Key::NumPad3 => (2, Buttons::A),
+        Key::NumPad1 => (2, Buttons::B),
+        Key::NumPad7 => (2, Buttons::SELECT),
+        Key::NumPad9 => (2, Buttons::START),
+        Key::NumPad8 => (2, Buttons::UP),
+        Key::NumPad2 => (2, Buttons::DOWN),
+        Key::NumPad4 => (2, Buttons::LEFT),
+        Key::NumPad6 => (2, Buttons::RIGHT),
+        // player 4 (four score)
+        Key::M => (3, Buttons::A),
+        Key::N => (3, Buttons::B),
+        Key::D7 => (3, Buttons::SELECT),
+        Key::D8 => (3, Buttons::START),
+        Key::I => (3, Buttons::UP),
+        Key::K => (3, Buttons::DOWN),
+        Key::J => (3, Buttons::LEFT),
+        Key::L => (3, Buttons::RIGHT),
+        _ => return None,
+    };
+    Some(mapping)
+}
+
+// Keyboard layout of the power pad: the 4x3 buttons of side B on
+// U-P, J-; and M-/. Only the player 3 and 4 keys overlap, and they are
+// used with a four score in both ports
+fn power_pad_button(key: Key) -> Option<usize> {
+    let button = match key {
+        Key::U => 1, Key::I => 2, Key::O => 3, Key::P => 4,
+        Key::J => 5, Key::K => 6, Key::L => 7, Key::Semicolon => 8,
+        Key::M => 9, Key::Comma => 10, Key::Period => 11, Key::Slash => 12,
+        _ => return None,
+    };
+    Some(button)
+}
+
+// Reports a CPU halt. Returns false if the emulation can not continue
+// until the NES is reset
+fn handle_step_result(result: StepResult) -> bool {
+    match result {
+        StepResult::Running => true,
+        StepResult::Halted(halt) => {
+            error!("{}", halt);
+            false
+        }
+    }
+}
+
 fn render_debug(window: &mut PistonWindow, event: &Event,
     glyphs: &mut GlyphBrush<Resources, Factory>,
     nes: &NES, disasm: &Disasm) {
@@ -197,9 +449,9 @@ fn render_debug(window: &mut PistonWindow, event: &Event,
     
     window.draw_2d(event, |_c, _g, _d| {
         let debug_offset = [10.0, 10.0];
-        render_cpu(glyphs, &nes.cpu, debug_offset);
+        render_cpu(glyphs, &nes.cpu, &nes.dma, debug_offset);
         render_disasm(glyphs, disasm, nes.cpu.regs.pc,
-            [debug_offset[0], debug_offset[1] + (8.0 * (FT_LINE_DISTANCE+FT_SIZE_PX))]);
+            [debug_offset[0], debug_offset[1] + (9.0 * (FT_LINE_DISTANCE+FT_SIZE_PX))]);
         render_ppu(glyphs, &nes.ppu.borrow(), [debug_offset[0], debug_offset[1] + (25.0 * (FT_LINE_DISTANCE+FT_SIZE_PX))])
         // render_memory(glyphs, nes,
         //     [debug_offset[0] + 400.0, debug_offset[1]]);
@@ -234,7 +486,7 @@ fn render_flags(glyphs: &mut GlyphBrush<Resources, Factory>, name: &str,
         } 
 }
 
-fn render_cpu(glyphs: &mut GlyphBrush<Resources, Factory>, cpu: &CPU, offset: [f32; 2]) {
+fn render_cpu(glyphs: &mut GlyphBrush<Resources, Factory>, cpu: &CPU, dma: &OamDma, offset: [f32; 2]) {
         render_flags(glyphs,
             "Flags",
             vec!["N", "V", "-", "B", "D", "I", "Z", "C"],
@@ -257,6 +509,30 @@ fn render_cpu(glyphs: &mut GlyphBrush<Resources, Factory>, cpu: &CPU, offset: [f
             ..Section::default()
         });
 
+        // interrupt lines: latched NMI and the devices asserting the IRQ line
+        let irq_sources = cpu.irq.sources();
+        render_flags(glyphs,
+            "Intr",
+            vec!["N", "M", "F", "D"],
+            vec![
+                cpu.is_nmi_latched(),
+                irq_sources.contains(IrqSource::MAPPER),
+                irq_sources.contains(IrqSource::FRAME_COUNTER),
+                irq_sources.contains(IrqSource::DMC),
+            ],
+            [offset[0], offset[1] + FT_LINE_DISTANCE + FT_SIZE_PX]);
+        // the interrupt the CPU services after the current instruction
+        glyphs.queue(Section {
+            text: &match cpu.pending_interrupt() {
+                Some(interrupt) => format!("({:?})", interrupt),
+                None => "(-)".to_string(),
+            },
+            scale: *FT_SCALE,
+            screen_position: (offset[0]+200.0, offset[1] + FT_LINE_DISTANCE + 2.0*FT_SIZE_PX),
+            color: FT_COLOR_WHITE,
+            ..Section::default()
+        });
+
         let cpu_register_texts = [
             &format!("A: {0:#x} ({0})", cpu.regs.a),
             &format!("X: {0:#x} ({0})", cpu.regs.x),
@@ -264,6 +540,10 @@ fn render_cpu(glyphs: &mut GlyphBrush<Resources, Factory>, cpu: &CPU, offset: [f
             &format!("Stack P: {0:#x} ({0})", cpu.regs.sp),
             &format!("Program P: {:#x}", cpu.regs.pc),
             &format!("#CPU Cycles: {}", cpu.cycles),
+            &match dma.progress() {
+                Some((page, cycle, cycles)) => format!("DMA ${:02X}00: {}/{}", page, cycle, cycles),
+                None => "DMA: -".to_string(),
+            },
         ]; 
 
         for (i, &text) in cpu_register_texts.iter().enumerate() {
@@ -341,8 +621,8 @@ fn render_ppu(glyphs: &mut GlyphBrush<Resources, Factory>, ppu: &PPU, offset: [f
     let ppu_register_texts = [
             &format!("OAM Addr: {0:#x}", ppu.regs.oam_addr),
             &format!("OAM Data: {0:#x}", ppu.regs.oam_data),
-            &format!("Scroll: {0:#x}", ppu.regs.scroll),
-            &format!("Addr: {0:#x}", ppu.regs.addr),
+            &format!("V: {:#06x} T: {:#06x}", ppu.vram_addr.0, ppu.tram_addr.0),
+            &format!("Fine X: {}", ppu.fine_x),
             &format!("Data: {:#x}", ppu.regs.data),
             &format!("DMA: {:#x}", ppu.regs.dma),
         ]; 
@@ -401,7 +681,7 @@ fn render_memory(glyphs: &mut GlyphBrush<Resources, Factory>, nes: &NES, offset:
         position_y.1 += FT_LINE_DISTANCE + FT_SIZE_PX;
         let mut line = format!("{:#06x}:", page);
         (0u16..16u16).map(|offset| offset + page)
-            .map(|addr| nes.bus.readb(addr))
+            .map(|addr| nes.bus.peekb(addr))
             .map(|val| format!(" {:02x}", val))
             .for_each(|s| line.push_str(&s));
         glyphs.queue(Section {
@@ -418,7 +698,7 @@ fn render_memory(glyphs: &mut GlyphBrush<Resources, Factory>, nes: &NES, offset:
         position_y.1 += FT_LINE_DISTANCE + FT_SIZE_PX;
         let mut line = format!("{:#06x}:", page);
         (0u16..16u16).map(|offset| offset + page)
-            .map(|addr| nes.bus.readb(addr))
+            .map(|addr| nes.bus.peekb(addr))
             .map(|val| format!(" {:02x}", val))
             .for_each(|s| line.push_str(&s));
         glyphs.queue(Section {
@@ -1,11 +1,17 @@
 use core::cell::RefCell;
 use std::rc::Rc;
-pub use crate::nes::cartridge::Cartridge;
+pub use crate::nes::cartridge::{Cartridge,CartridgeError};
 pub use crate::nes::ppu::PPU;
-pub use crate::nes::cpu::CPU;
+pub use crate::nes::cpu::{CPU,Halt};
 pub use crate::nes::types::*;
 pub use crate::nes::bus::*;
 pub use crate::nes::ppubus::*;
+pub use crate::nes::irq::*;
+pub use crate::nes::dma::OamDma;
+pub use crate::nes::apu::APU;
+pub use crate::nes::audio::Audio;
+pub use crate::nes::input::{Input,InputKind,Buttons};
+pub use crate::nes::movie::{Movie,MovieMode,MovieSession};
 
 
 #[allow(non_snake_case)]
@@ -17,8 +23,34 @@ pub mod cartridge;
 pub mod mappers;
 pub mod ppu;
 pub mod ppubus;
+pub mod irq;
+pub mod dma;
+pub mod apu;
+pub mod audio;
+pub mod input;
+pub mod movie;
+#[cfg(test)]
+mod nestest;
 
 
+// Outcome of clocking the NES
+#[must_use]
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum StepResult {
+    Running,
+    // The CPU is jammed. Nothing happens until the NES is reset
+    Halted(Halt),
+}
+
+impl StepResult {
+    pub fn is_halted(&self) -> bool {
+        match self {
+            StepResult::Halted(_) => true,
+            StepResult::Running => false,
+        }
+    }
+}
+
 // The NES class connects all elements of the NES together. It acts
 // as the mediator between the different components and hold the RAM 
 pub struct NES {
@@ -26,6 +58,16 @@ pub struct NES {
     pub bus: Bus,
     pub ppu: Rc<RefCell<PPU>>,
     pub ppu_bus: Rc<RefCell<PPUBus>>,
+    pub apu: Rc<RefCell<APU>>,
+    pub audio: Audio,
+    pub dma: OamDma,
+    // devices in the controller ports
+    pub inputs: [Input; 2],
+    // movie being recorded or played back
+    pub movie: Option<MovieSession>,
+    pub cartridge: Option<Rc<RefCell<Cartridge>>>,
+    // CPU cycles left of a DMC sample fetch
+    dmc_stall: u8,
     pub clock_count: u64,
 }
 
@@ -38,22 +80,54 @@ impl NES {
         // instance, which is conceptually not nice.
         let ppu = Rc::new(RefCell::new(PPU::new()));
         let ppu_bus = Rc::new(RefCell::new(PPUBus::new()));
-        NES {
-            cpu: CPU::new(),
-            bus: Bus::new(ppu.clone(), ppu_bus.clone()),
+        let cpu = CPU::new();
+        let apu = Rc::new(RefCell::new(APU::new(cpu.irq.clone())));
+        let mut nes = NES {
+            cpu,
+            bus: Bus::new(ppu.clone(), ppu_bus.clone(), apu.clone()),
             ppu: ppu.clone(),
             ppu_bus: ppu_bus.clone(),
+            apu,
+            audio: Audio::new(audio::DEFAULT_SAMPLE_RATE),
+            dma: OamDma::new(),
+            inputs: [Input::None, Input::None],
+            movie: None,
+            cartridge: None,
+            dmc_stall: 0,
             clock_count: 0,
-        }
+        };
+        // standard controllers by default
+        nes.connect_input(0, InputKind::Controller);
+        nes.connect_input(1, InputKind::Controller);
+        nes
+    }
+
+    // Plug a new device of the given kind into port 0 or 1
+    pub fn connect_input(&mut self, port: usize, kind: InputKind) {
+        let input = Input::new(kind, port, &self.ppu);
+        self.bus.connect_input(port, input.device());
+        self.inputs[port] = input;
     }
 
     // Insert a cartridge into the NES. This inserts the cartridge bus
     // into the NES address range
     pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.connect_cartridge(Rc::new(RefCell::new(cartridge)));
+    }
+
+    fn connect_cartridge(&mut self, cart: Rc<RefCell<Cartridge>>) {
         // both buses need to be connected to the cartridge
-        let cart = Rc::new(RefCell::new(cartridge));
         self.bus.insert_cartridge(cart.clone());
-        self.ppu_bus.borrow_mut().insert_cartridge(cart.clone())
+        self.ppu_bus.borrow_mut().insert_cartridge(cart.clone());
+        self.cartridge = Some(cart);
+    }
+
+    // Write battery backed cartridge RAM to its save file
+    pub fn flush_save(&mut self) -> Result<(), CartridgeError> {
+        match &self.cartridge {
+            Some(cartridge) => cartridge.borrow_mut().flush_sav(),
+            None => Ok(()),
+        }
     }
 
     // Initializes the NES CPU programm pointer
@@ -61,58 +135,199 @@ impl NES {
         self.cpu.find_pc_addr(&self.bus);
     }
 
+    // Turn the NES off and on again. The cartridge and the devices in the
+    // ports stay, as does the CPU execution mode. Only battery backed RAM
+    // keeps its content
+    pub fn power_cycle(&mut self) {
+        if let Some(session) = &mut self.movie {
+            session.command(movie::COMMAND_POWER);
+        }
+        let mut nes = NES::new();
+        nes.cpu.mode = self.cpu.mode;
+        std::mem::swap(&mut nes.audio, &mut self.audio);
+        nes.movie = self.movie.take();
+        for (port, input) in self.inputs.iter().enumerate() {
+            nes.connect_input(port, input.kind());
+        }
+        if let Some(cartridge) = self.cartridge.take() {
+            cartridge.borrow_mut().power_on();
+            nes.connect_cartridge(cartridge);
+            nes.start();
+        }
+        *self = nes;
+    }
+
+    // Record a movie or play one back. Movies start at power on with
+    // empty battery backed RAM, so the NES is power cycled first. The
+    // ports are set up with the devices of the movie
+    pub fn start_movie(&mut self, movie: Movie, mode: MovieMode) {
+        if mode == MovieMode::Play {
+            for (port, &kind) in movie.port_kinds().iter().enumerate() {
+                self.connect_input(port, kind);
+            }
+        }
+        self.movie = None;
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().clear_battery_ram();
+        }
+        self.power_cycle();
+        self.movie = Some(MovieSession::new(movie, mode));
+    }
+
+    // Stop recording or playback. Returns the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(|session| session.movie)
+    }
+
     // Reset the CPU
     pub fn reset(&mut self) {
+        if let Some(session) = &mut self.movie {
+            session.command(movie::COMMAND_RESET);
+        }
         self.clock_count = 0;
+        self.dma = OamDma::new();
+        self.dmc_stall = 0;
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().reset();
+        }
         self.cpu.reset(&self.bus);
         self.ppu.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
     }
 
-    // A single clock on the NES
-    pub fn clock(&mut self) {
+    // A single clock on the NES. Does nothing once the CPU is halted
+    pub fn clock(&mut self) -> StepResult {
+        if let Some(halt) = self.cpu.halted() {
+            return StepResult::Halted(halt);
+        }
         self.clock_count += 1;
         if self.clock_count % 3 == 0 {
-            self.cpu.clock(&mut self.bus);
+            self.clock_cpu();
         }
         let mut ppu = self.ppu.borrow_mut();
         ppu.clock(&mut *self.ppu_bus.borrow_mut());
         if ppu.nmi {
             ppu.nmi = false;
-            self.cpu.nmi(&mut self.bus);
+            self.cpu.nmi();
             debug!("NMI triggered by PPU.")
         }
         if self.clock_count % 100000 == 0 {
             info!("clock {}", self.clock_count);
         }
+        match self.cpu.halted() {
+            Some(halt) => StepResult::Halted(halt),
+            None => StepResult::Running,
+        }
+    }
+
+
+    // A single CPU cycle, clocks the APU alongside. An OAM DMA halts the
+    // CPU once the current instruction is done and runs in its place. DMC
+    // sample fetches halt it right away
+    fn clock_cpu(&mut self) {
+        if self.dmc_stall > 0 {
+            self.dmc_stall -= 1;
+            self.cpu.stall();
+        } else {
+            self.clock_dma_or_cpu();
+        }
+
+        self.apu.borrow_mut().clock();
+        if let Some(cartridge) = &self.cartridge {
+            let irq = cartridge.borrow_mut().clock();
+            self.cpu.irq.set(IrqSource::MAPPER, irq);
+        }
+        let fetch_addr = self.apu.borrow().dmc_fetch_addr();
+        if let Some(addr) = fetch_addr {
+            let data = self.bus.readb(addr);
+            self.apu.borrow_mut().dmc_fill(data);
+            self.dmc_stall = apu::DMC_STALL_CYCLES;
+        }
+        if self.audio.is_enabled() {
+            let mut level = self.apu.borrow().output();
+            if let Some(cartridge) = &self.cartridge {
+                level += cartridge.borrow().audio_output();
+            }
+            self.audio.clock(level);
+        }
     }
 
+    fn clock_dma_or_cpu(&mut self) {
+        if !self.cpu.is_ahead() && !self.dma.is_active() {
+            if let Some(page) = self.bus.take_oam_dma() {
+                self.dma.start(page, self.cpu.cycles % 2 == 1);
+            }
+        }
+        if self.dma.is_active() && !self.cpu.is_ahead() {
+            self.dma.clock(&mut self.bus);
+            self.cpu.stall();
+        } else {
+            self.cpu.clock(&mut self.bus);
+        }
+    }
 
-    // clock until the next cpu instruction is run
-    pub fn clock_instruction(&mut self) {
+    // clock until the next cpu instruction is run. All clock_* functions
+    // stop early when the CPU halts
+    pub fn clock_instruction(&mut self) -> StepResult {
         if !self.cpu.is_ahead() {
             while !self.cpu.is_ahead() {
-                self.clock();
+                let result = self.clock();
+                if result.is_halted() {
+                    return result;
+                }
             }
         } 
         while self.cpu.is_ahead() {
-            self.clock();
+            let result = self.clock();
+            if result.is_halted() {
+                return result;
+            }
         }
+        StepResult::Running
     }
 
-    // clock until the next frame is ready
-    pub fn clock_frame(&mut self) {
+    // clock until the next frame is ready. Movie input is recorded or
+    // applied before the frame starts
+    pub fn clock_frame(&mut self) -> StepResult {
+        self.movie_frame();
         while !self.ppu.borrow().frame_ready {
-            self.clock();
+            let result = self.clock();
+            if result.is_halted() {
+                return result;
+            }
         }
         self.ppu.borrow_mut().frame_ready = false;
+        StepResult::Running
+    }
+
+    fn movie_frame(&mut self) {
+        let mut session = match self.movie.take() {
+            Some(session) => session,
+            None => return,
+        };
+        if session.is_finished() {
+            info!("Movie finished after {} frames", session.frame);
+            return
+        }
+        let commands = session.next_frame(&self.inputs);
+        self.movie = Some(session);
+        if commands & movie::COMMAND_POWER > 0 {
+            self.power_cycle();
+        } else if commands & movie::COMMAND_RESET > 0 {
+            self.reset();
+        }
     }
 
     // clock until the next scanline is done
-    pub fn clock_scanline(&mut self) {
+    pub fn clock_scanline(&mut self) -> StepResult {
         let current_line = self.ppu.borrow().scanline;
         while self.ppu.borrow().scanline == current_line {
-            self.clock();
+            let result = self.clock();
+            if result.is_halted() {
+                return result;
+            }
         }
+        StepResult::Running
     }
 
     
@@ -0,0 +1,261 @@
+use crate::nes::irq::{IrqLine,IrqSource};
+use crate::nes::types::*;
+use crate::nes::apu::pulse::{Pulse,Channel};
+use crate::nes::apu::triangle::Triangle;
+use crate::nes::apu::noise::Noise;
+use crate::nes::apu::dmc::DMC;
+use crate::nes::apu::frame_counter::{FrameCounter,FrameClock};
+
+pub mod units;
+pub mod pulse;
+pub mod triangle;
+pub mod noise;
+pub mod dmc;
+pub mod frame_counter;
+pub mod mixer;
+
+pub const STATUS_ADDR: Addr = 0x4015;
+pub const FRAME_COUNTER_ADDR: Addr = 0x4017;
+
+// CPU cycles the DMC stalls the CPU for a sample fetch
+pub const DMC_STALL_CYCLES: u8 = 4;
+
+// Audio processing unit of the 2A03. Clocked once per CPU cycle. The
+// channels produce their raw output levels, mixing is done elsewhere.
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: DMC,
+    frame_counter: FrameCounter,
+    irq: IrqLine,
+    pub cycles: u64,
+}
+
+impl APU {
+    // irq: clone of the CPU IRQ line. Asserted by the frame counter and
+    // the DMC
+    pub fn new(irq: IrqLine) -> Self {
+        APU {
+            pulse1: Pulse::new(Channel::One),
+            pulse2: Pulse::new(Channel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: DMC::new(),
+            frame_counter: FrameCounter::new(),
+            irq,
+            cycles: 0,
+        }
+    }
+
+    // Silences all channels. The frame counter restarts with its last mode
+    pub fn reset(&mut self) {
+        self.writeb(STATUS_ADDR, 0x00);
+        self.frame_counter.irq = false;
+        self.dmc.irq = false;
+        self.update_irq();
+    }
+
+    // One CPU cycle
+    pub fn clock(&mut self) {
+        let frame_clock = self.frame_counter.clock();
+        self.clock_frame(frame_clock);
+
+        // the pulse timers run at half the CPU clock
+        if self.cycles & 0x01 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        self.update_irq();
+        self.cycles += 1;
+    }
+
+    fn clock_frame(&mut self, frame_clock: FrameClock) {
+        if frame_clock == FrameClock::None {
+            return
+        }
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+        if frame_clock == FrameClock::Half {
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+    }
+
+    fn update_irq(&self) {
+        self.irq.set(IrqSource::FRAME_COUNTER, self.frame_counter.irq);
+        self.irq.set(IrqSource::DMC, self.dmc.irq);
+    }
+
+    // Write to $4000-$4013, $4015 or $4017
+    pub fn writeb(&mut self, addr: Addr, data: Byte) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.writeb(addr & 0x03, data),
+            0x4004..=0x4007 => self.pulse2.writeb(addr & 0x03, data),
+            0x4008..=0x400B => self.triangle.writeb(addr & 0x03, data),
+            0x400C..=0x400F => self.noise.writeb(addr & 0x03, data),
+            0x4010..=0x4013 => self.dmc.writeb(addr & 0x03, data),
+            // ---D NT21: enable channels
+            STATUS_ADDR => {
+                self.pulse1.length.set_enabled(data & 0x01 > 0);
+                self.pulse2.length.set_enabled(data & 0x02 > 0);
+                self.triangle.length.set_enabled(data & 0x04 > 0);
+                self.noise.length.set_enabled(data & 0x08 > 0);
+                self.dmc.set_enabled(data & 0x10 > 0);
+            },
+            FRAME_COUNTER_ADDR => {
+                let frame_clock = self.frame_counter.writeb(data, self.cycles & 0x01 == 1);
+                self.clock_frame(frame_clock);
+            },
+            _ => { }
+        }
+        self.update_irq();
+    }
+
+    // Read $4015: IF-D NT21. DMC and frame irq, DMC active and length
+    // counter status. Reading clears the frame irq. Bit 5 is open bus
+    pub fn read_status(&mut self) -> Byte {
+        let mut status = 0x00;
+        status |= self.pulse1.length.is_active() as Byte;
+        status |= (self.pulse2.length.is_active() as Byte) << 1;
+        status |= (self.triangle.length.is_active() as Byte) << 2;
+        status |= (self.noise.length.is_active() as Byte) << 3;
+        status |= ((self.dmc.bytes_remaining > 0) as Byte) << 4;
+        status |= (self.frame_counter.irq as Byte) << 6;
+        status |= (self.dmc.irq as Byte) << 7;
+
+        self.frame_counter.irq = false;
+        self.update_irq();
+        status
+    }
+
+    // Mixed output of all channels, between 0.0 and about 1.0
+    pub fn output(&self) -> f32 {
+        mixer::mix(self.pulse1.output(), self.pulse2.output(), self.triangle.output(),
+            self.noise.output(), self.dmc.output())
+    }
+
+    // Address of a sample byte the DMC needs. The NES reads it through
+    // the CPU bus and hands it over with dmc_fill
+    pub fn dmc_fetch_addr(&self) -> Option<Addr> {
+        self.dmc.fetch_addr()
+    }
+
+    pub fn dmc_fill(&mut self, data: Byte) {
+        self.dmc.fill(data);
+        self.update_irq();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nes::*;
+
+    fn run_cycles(nes: &mut NES, cycles: u64) {
+        let end = nes.cpu.cycles + cycles;
+        while nes.cpu.cycles < end {
+            let _ = nes.clock();
+        }
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let irq = IrqLine::new();
+        let mut apu = APU::new(irq.clone());
+        for _ in 0..29827 {
+            apu.clock();
+        }
+        assert!(!irq.is_asserted());
+        apu.clock();
+        assert_eq!(irq.sources(), IrqSource::FRAME_COUNTER);
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        // reading $4015 acknowledges the irq
+        assert!(!irq.is_asserted());
+        assert_eq!(apu.read_status() & 0x40, 0x00);
+
+        // no irq in 5-step mode
+        apu.writeb(apu::FRAME_COUNTER_ADDR, 0x80);
+        for _ in 0..2 * 37282 {
+            apu.clock();
+        }
+        assert!(!irq.is_asserted());
+    }
+
+    #[test]
+    fn test_status_length() {
+        let mut apu = APU::new(IrqLine::new());
+        apu.writeb(0x4003, 0x08);
+        assert_eq!(apu.read_status(), 0x00, "disabled channels are not loaded");
+
+        apu.writeb(apu::STATUS_ADDR, 0x0F);
+        apu.writeb(0x4003, 0x18);  // length 2
+        apu.writeb(0x4007, 0x08);
+        apu.writeb(0x400B, 0x08);
+        apu.writeb(0x400F, 0x08);
+        assert_eq!(apu.read_status(), 0x0F);
+
+        // runs out after two half frames in 5-step mode, the others halt
+        apu.writeb(0x4004, 0x20);
+        apu.writeb(0x4008, 0x80);
+        apu.writeb(0x400C, 0x20);
+        apu.writeb(apu::FRAME_COUNTER_ADDR, 0x80);
+        for _ in 0..14916 {
+            apu.clock();
+        }
+        assert_eq!(apu.read_status(), 0x0E);
+
+        apu.writeb(apu::STATUS_ADDR, 0x00);
+        assert_eq!(apu.read_status(), 0x00);
+    }
+
+    #[test]
+    fn test_status_open_bus() {
+        let mut nes = NES::new();
+        nes.bus.writeb(0x0000, 0xFF);
+        assert_eq!(nes.bus.readb(0x0000), 0xFF);
+        assert_eq!(nes.bus.readb(apu::STATUS_ADDR), 0x20);
+    }
+
+    #[test]
+    fn test_dmc_fetch() {
+        let mut nes = NES::new();
+        // keep the cpu busy with JMP $0000
+        nes.bus.writeb(0x0000, 0x4C);
+        nes.bus.writeb(0x0001, 0x00);
+        nes.bus.writeb(0x0002, 0x00);
+        nes.cpu.regs.pc = 0x0000;
+        // play a sample of 17 bytes at the fastest rate. $C000 is
+        // unmapped without a cartridge
+        {
+            let mut apu = nes.apu.borrow_mut();
+            apu.writeb(0x4010, 0x8F);
+            apu.writeb(0x4013, 0x01);
+            apu.writeb(apu::STATUS_ADDR, 0x10);
+            assert_eq!(apu.read_status(), 0x10);
+        }
+
+        // every fetch stalls the cpu
+        run_cycles(&mut nes, 1);
+        assert_eq!(nes.dmc_stall, apu::DMC_STALL_CYCLES);
+        assert_eq!(nes.apu.borrow().dmc.bytes_remaining, 16);
+        run_cycles(&mut nes, apu::DMC_STALL_CYCLES as u64);
+        assert_eq!(nes.dmc_stall, 0);
+
+        run_cycles(&mut nes, 17 * 8 * 54);
+        assert_eq!(nes.apu.borrow_mut().read_status(), 0x80);
+        assert!(nes.cpu.irq.sources().contains(IrqSource::DMC));
+
+        // enabling or disabling acknowledges the irq
+        nes.apu.borrow_mut().writeb(apu::STATUS_ADDR, 0x00);
+        assert!(!nes.cpu.irq.is_asserted());
+    }
+}
@@ -0,0 +1,180 @@
+use crate::nes::types::*;
+
+// Timer periods in CPU cycles (NTSC)
+const RATE_TABLE: [Word; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// Delta modulation channel at $4010-$4013. Plays 1 bit delta encoded
+// samples from CPU memory. The sample bytes are fetched by the NES through
+// the CPU bus, which stalls the CPU.
+pub struct DMC {
+    pub irq_enabled: bool,
+    pub irq: bool,
+    looping: bool,
+    timer: Word,
+    period: Word,
+    // memory reader
+    sample_addr: Addr,
+    sample_length: Word,
+    current_addr: Addr,
+    pub bytes_remaining: Word,
+    buffer: Option<Byte>,
+    // output unit
+    shift: Byte,
+    bits_remaining: Byte,
+    silence: bool,
+    level: Byte,
+}
+
+impl DMC {
+    pub fn new() -> Self {
+        DMC {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer: 0,
+            period: RATE_TABLE[0],
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            level: 0,
+        }
+    }
+
+    // write to register 0-3 of the channel
+    pub fn writeb(&mut self, register: Addr, data: Byte) {
+        match register {
+            // IL-- RRRR: irq enable, loop, rate. Disabling the irq
+            // clears the flag
+            0 => {
+                self.irq_enabled = data & 0x80 > 0;
+                self.looping = data & 0x40 > 0;
+                self.period = RATE_TABLE[(data & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            },
+            // -DDD DDDD: direct load of the output level
+            1 => self.level = data & 0x7F,
+            // sample address $C000 + A * 64
+            2 => self.sample_addr = 0xC000 | (data as Addr) << 6,
+            // sample length L * 16 + 1
+            3 => self.sample_length = (data as Word) << 4 | 0x0001,
+            _ => { }
+        }
+    }
+
+    // $4015 enable bit. Enabling restarts the sample only if it is done,
+    // disabling stops it after the current byte
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Address of the next sample byte if the memory reader needs one
+    pub fn fetch_addr(&self) -> Option<Addr> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    // Sample byte fetched from fetch_addr()
+    pub fn fill(&mut self, data: Byte) {
+        self.buffer = Some(data);
+        // the address wraps around to $8000
+        self.current_addr = if self.current_addr == 0xFFFF { 0x8000 } else { self.current_addr + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Timer clock, every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 > 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        // start a new output cycle with the sample buffer
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.shift = data;
+                    self.silence = false;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> Byte {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_playback() {
+        let mut dmc = DMC::new();
+        dmc.writeb(0, 0x8F);  // irq, fastest rate
+        dmc.writeb(1, 0x40);
+        dmc.writeb(2, 0xFF);  // $FFC0
+        dmc.writeb(3, 0x04);  // 65 bytes
+        dmc.set_enabled(true);
+
+        // reads wrap to $8000 and the irq fires after the last byte
+        let mut addrs = vec![];
+        while let Some(addr) = dmc.fetch_addr() {
+            addrs.push(addr);
+            dmc.fill(0xFF);
+            for _ in 0..8 * 54 {
+                dmc.clock_timer();
+            }
+        }
+        assert_eq!(addrs.len(), 65);
+        assert_eq!(addrs[63], 0xFFFF);
+        assert_eq!(addrs[64], 0x8000);
+        assert!(dmc.irq);
+        // the level only moves in steps of 2 and stays below 128
+        assert_eq!(dmc.output(), 126);
+    }
+}
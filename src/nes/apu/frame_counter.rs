@@ -0,0 +1,123 @@
+use crate::nes::types::*;
+
+// Frame counter steps in CPU cycles (NTSC)
+const QUARTER_FRAMES: [u16; 3] = [7457, 14913, 22371];
+const HALF_FRAME: u16 = 14913;
+const FOUR_STEP_LAST: u16 = 29829;
+const FIVE_STEP_LAST: u16 = 37281;
+
+// Which units a frame counter step clocks
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum FrameClock {
+    None,
+    Quarter,
+    // half frames also clock the quarter frame units
+    Half,
+}
+
+// The frame counter at $4017 clocks the envelopes, linear counter (quarter
+// frames), length counters and sweeps (half frames) about 240 times a
+// second. In 4-step mode it raises an IRQ at the end of each sequence.
+pub struct FrameCounter {
+    cycle: u16,
+    five_step: bool,
+    irq_inhibit: bool,
+    pub irq: bool,
+    // cycles until a $4017 write resets the sequence
+    reset_delay: Option<Byte>,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter { cycle: 0, five_step: false, irq_inhibit: false, irq: false, reset_delay: None }
+    }
+
+    // MI-- ----: 5-step mode, irq inhibit. The sequence restarts 3 or 4
+    // CPU cycles after the write, depending on odd_cycle.
+    // Returns the frame clock of the write: 5-step mode clocks all units
+    // immediately
+    pub fn writeb(&mut self, data: Byte, odd_cycle: bool) -> FrameClock {
+        self.five_step = data & 0x80 > 0;
+        self.irq_inhibit = data & 0x40 > 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.reset_delay = Some(if odd_cycle { 4 } else { 3 });
+        if self.five_step { FrameClock::Half } else { FrameClock::None }
+    }
+
+    // One CPU cycle
+    pub fn clock(&mut self) -> FrameClock {
+        match self.reset_delay {
+            Some(1) => {
+                self.reset_delay = None;
+                self.cycle = 0;
+                return FrameClock::None
+            },
+            Some(delay) => self.reset_delay = Some(delay - 1),
+            None => { },
+        }
+        self.cycle += 1;
+
+        if !self.five_step && (FOUR_STEP_LAST - 1..=FOUR_STEP_LAST + 1).contains(&self.cycle) && !self.irq_inhibit {
+            self.irq = true;
+        }
+        let last = if self.five_step { FIVE_STEP_LAST } else { FOUR_STEP_LAST };
+        if self.cycle == last + 1 {
+            self.cycle = 0;
+        }
+        if self.cycle == HALF_FRAME || self.cycle == last {
+            FrameClock::Half
+        } else if QUARTER_FRAMES.contains(&self.cycle) {
+            FrameClock::Quarter
+        } else {
+            FrameClock::None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cycles of all non-empty frame clocks of one sequence
+    fn sequence(counter: &mut FrameCounter, cycles: u16) -> Vec<(u16, FrameClock)> {
+        (1..=cycles).map(|c| (c, counter.clock()))
+            .filter(|&(_, clock)| clock != FrameClock::None)
+            .collect()
+    }
+
+    #[test]
+    fn test_four_step() {
+        let mut counter = FrameCounter::new();
+        assert_eq!(sequence(&mut counter, 29830), vec![
+            (7457, FrameClock::Quarter), (14913, FrameClock::Half),
+            (22371, FrameClock::Quarter), (29829, FrameClock::Half),
+        ]);
+        assert!(counter.irq);
+        // the sequence restarts
+        assert_eq!(sequence(&mut counter, 7457).len(), 1);
+    }
+
+    #[test]
+    fn test_five_step() {
+        let mut counter = FrameCounter::new();
+        assert_eq!(counter.writeb(0x80, false), FrameClock::Half);
+        assert_eq!(sequence(&mut counter, 37284), vec![
+            (7460, FrameClock::Quarter), (14916, FrameClock::Half),
+            (22374, FrameClock::Quarter), (37284, FrameClock::Half),
+        ]);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut counter = FrameCounter::new();
+        sequence(&mut counter, 29830);
+        assert!(counter.irq);
+        counter.writeb(0x40, true);
+        assert!(!counter.irq);
+        sequence(&mut counter, 29834);
+        assert!(!counter.irq);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::nes::types::*;
+
+// The channels are mixed by resistor networks, which is not linear. The
+// output is approximated with two lookup tables (see nesdev wiki, APU
+// Mixer):
+//   pulse_out = 95.52 / (8128 / (pulse1 + pulse2) + 100)
+//   tnd_out = 163.67 / (24329 / (3 * triangle + 2 * noise + dmc) + 100)
+lazy_static! {
+    static ref PULSE_TABLE: [f32; 31] = {
+        let mut table = [0.0; 31];
+        for (n, value) in table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        table
+    };
+    static ref TND_TABLE: [f32; 203] = {
+        let mut table = [0.0; 203];
+        for (n, value) in table.iter_mut().enumerate().skip(1) {
+            *value = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        table
+    };
+}
+
+// Mix the raw channel outputs (0-15 for pulse, triangle and noise, 0-127
+// for the DMC) to an amplitude between 0.0 and about 1.0
+pub fn mix(pulse1: Byte, pulse2: Byte, triangle: Byte, noise: Byte, dmc: Byte) -> f32 {
+    let pulse = PULSE_TABLE[(pulse1 + pulse2) as usize];
+    let tnd = TND_TABLE[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+    pulse + tnd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+        assert!((mix(15, 15, 15, 15, 127) - 1.0).abs() < 0.01);
+        // nonlinear: two channels are less than twice as loud as one
+        assert!(mix(15, 15, 0, 0, 0) < 2.0 * mix(15, 0, 0, 0, 0));
+        assert!((mix(15, 0, 0, 0, 0) - 0.1494).abs() < 0.001);
+    }
+}
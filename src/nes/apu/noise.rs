@@ -0,0 +1,109 @@
+use crate::nes::types::*;
+use super::units::{Envelope,LengthCounter};
+
+// Timer periods in CPU cycles (NTSC)
+const PERIOD_TABLE: [Word; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// Noise channel at $400C-$400F. Pseudo random bits from a 15 bit linear
+// feedback shift register
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    shift: Word,
+    // short mode: feedback from bit 6 instead of bit 1, which gives a
+    // 93 step metallic sounding sequence
+    short_mode: bool,
+    timer: Word,
+    period: Word,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            shift: 1,
+            short_mode: false,
+            timer: 0,
+            period: PERIOD_TABLE[0],
+        }
+    }
+
+    // write to register 0-3 of the channel
+    pub fn writeb(&mut self, register: Addr, data: Byte) {
+        match register {
+            // --LC VVVV: length halt/envelope loop, envelope
+            0 => {
+                self.length.halt = data & 0x20 > 0;
+                self.envelope.write(data);
+            },
+            // M--- PPPP: mode, period
+            2 => {
+                self.short_mode = data & 0x80 > 0;
+                self.period = PERIOD_TABLE[(data & 0x0F) as usize];
+            },
+            // LLLL L---: length counter load. Restarts the envelope
+            3 => {
+                self.length.load(data);
+                self.envelope.restart();
+            },
+            _ => { }
+        }
+    }
+
+    // Timer clock, every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = self.shift >> 1 | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> Byte {
+        if !self.length.is_active() || self.shift & 0x01 > 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // number of shifts until the register repeats
+    fn sequence_length(short_mode: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.writeb(2, if short_mode { 0x80 } else { 0x00 });
+        let start = noise.shift;
+        for i in 1..40000 {
+            noise.timer = 0;
+            noise.clock_timer();
+            if noise.shift == start {
+                return i
+            }
+        }
+        0
+    }
+
+    #[test]
+    fn test_lfsr_sequence() {
+        assert_eq!(sequence_length(false), 32767);
+        assert_eq!(sequence_length(true), 93);
+    }
+}
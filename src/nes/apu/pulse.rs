@@ -0,0 +1,191 @@
+use crate::nes::types::*;
+use super::units::{Envelope,LengthCounter};
+
+// Duty cycle waveforms: 12.5%, 25%, 50% and 25% negated
+const DUTY_TABLE: [[Byte; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// Which pulse channel. They only differ in how the sweep unit negates
+#[derive(Clone,Copy,PartialEq)]
+pub enum Channel {
+    One,
+    Two,
+    // the two extra pulses of the MMC5 have no sweep unit
+    Mmc5,
+}
+
+// Pulse (square wave) channel at $4000-$4003 and $4004-$4007
+pub struct Pulse {
+    channel: Channel,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    duty: Byte,
+    step: usize,
+    timer: Word,
+    period: Word,
+    // sweep unit
+    sweep_enabled: bool,
+    sweep_period: Byte,
+    sweep_negate: bool,
+    sweep_shift: Byte,
+    sweep_divider: Byte,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(channel: Channel) -> Self {
+        Pulse {
+            channel,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            duty: 0,
+            step: 0,
+            timer: 0,
+            period: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    // write to register 0-3 of the channel
+    pub fn writeb(&mut self, register: Addr, data: Byte) {
+        match register {
+            // DDLC VVVV: duty, length halt/envelope loop, envelope
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 > 0;
+                self.envelope.write(data);
+            },
+            // EPPP NSSS: sweep enable, period, negate, shift
+            1 => {
+                self.sweep_enabled = data & 0x80 > 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 > 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            },
+            // timer low
+            2 => self.period = self.period & 0xFF00 | data as Word,
+            // LLLL LHHH: length counter load, timer high. Restarts the
+            // envelope and the waveform
+            3 => {
+                self.period = self.period & 0x00FF | ((data & 0x07) as Word) << 8;
+                self.length.load(data);
+                self.envelope.restart();
+                self.step = 0;
+            },
+            _ => { }
+        }
+    }
+
+    // Timer clock, every second CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    // Period the sweep unit moves to. Pulse 1 negates with one's
+    // complement, so it subtracts one more than pulse 2
+    fn sweep_target(&self) -> Word {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.channel == Channel::One {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    // The sweep unit mutes the channel for very high and very low
+    // periods, even if sweeping is disabled
+    fn is_muted(&self) -> bool {
+        if self.channel == Channel::Mmc5 {
+            return false
+        }
+        self.period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    pub fn output(&self) -> Byte {
+        if !self.length.is_active() || self.is_muted() || DUTY_TABLE[self.duty as usize][self.step] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let mut pulse = Pulse::new(Channel::One);
+        pulse.length.set_enabled(true);
+        pulse.writeb(2, 0x00);
+        pulse.writeb(3, 0x01);  // period 0x100
+        pulse.writeb(1, 0x89);  // enabled, period 0, negate, shift 1
+        pulse.clock_half_frame();
+        assert_eq!(pulse.period, 0x100 - 0x80 - 1);
+
+        let mut pulse = Pulse::new(Channel::Two);
+        pulse.writeb(2, 0x00);
+        pulse.writeb(3, 0x01);
+        pulse.writeb(1, 0x89);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.period, 0x100 - 0x80);
+    }
+
+    #[test]
+    fn test_sweep_mute() {
+        let mut pulse = Pulse::new(Channel::One);
+        pulse.length.set_enabled(true);
+        pulse.writeb(0, 0xDF);  // constant volume 15, 75% duty
+        pulse.writeb(2, 0x00);
+        pulse.writeb(3, 0x0D);  // period 0x500
+
+        // with shift 0 the target period is twice the period and
+        // overflows. This mutes even with the sweep disabled
+        assert_eq!(pulse.output(), 0);
+        pulse.writeb(1, 0x08);
+        assert_eq!(pulse.output(), 15);
+        pulse.writeb(1, 0x01);
+        assert_eq!(pulse.output(), 15);
+
+        // too high frequency
+        pulse.writeb(2, 0x07);
+        pulse.writeb(3, 0x08);
+        assert_eq!(pulse.output(), 0);
+    }
+}
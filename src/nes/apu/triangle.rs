@@ -0,0 +1,117 @@
+use crate::nes::types::*;
+use super::units::LengthCounter;
+
+// 32 step triangle waveform
+const SEQUENCE: [Byte; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// Triangle channel at $4008-$400B. Has no volume control, but a linear
+// counter in addition to the length counter
+pub struct Triangle {
+    pub length: LengthCounter,
+    step: usize,
+    timer: Word,
+    period: Word,
+    // linear counter
+    control: bool,
+    linear_reload_value: Byte,
+    linear_counter: Byte,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            length: LengthCounter::new(),
+            step: 0,
+            timer: 0,
+            period: 0,
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+        }
+    }
+
+    // write to register 0-3 of the channel
+    pub fn writeb(&mut self, register: Addr, data: Byte) {
+        match register {
+            // CRRR RRRR: length halt/linear control, linear reload value
+            0 => {
+                self.control = data & 0x80 > 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0x7F;
+            },
+            2 => self.period = self.period & 0xFF00 | data as Word,
+            // LLLL LHHH: length counter load, timer high
+            3 => {
+                self.period = self.period & 0x00FF | ((data & 0x07) as Word) << 8;
+                self.length.load(data);
+                self.linear_reload = true;
+            },
+            _ => { }
+        }
+    }
+
+    // Timer clock, every CPU cycle. The waveform only advances while
+    // both counters are non-zero
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.is_active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    // The triangle is not silenced by its counters, it just stops at
+    // the current step
+    pub fn output(&self) -> Byte {
+        SEQUENCE[self.step]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear_counter() {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.writeb(0, 0x02);  // linear counter 2
+        triangle.writeb(2, 0x00);
+        triangle.writeb(3, 0x08);  // period 0
+        triangle.clock_quarter_frame();
+
+        triangle.clock_timer();
+        triangle.clock_timer();
+        assert_eq!(triangle.step, 2);
+
+        // stops when the linear counter runs out
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.step, 2);
+        assert_eq!(triangle.output(), 13);
+    }
+}
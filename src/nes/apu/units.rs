@@ -0,0 +1,151 @@
+use crate::nes::types::*;
+
+// Length counter load values, indexed by bits 7-3 of the fourth register
+// of a channel
+const LENGTH_TABLE: [Byte; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Silences a channel after a given time. Clocked by half frames
+pub struct LengthCounter {
+    pub counter: Byte,
+    pub halt: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter { counter: 0, halt: false, enabled: false }
+    }
+
+    // $4015 enable bit. Disabling clears the counter
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    // load from the table index in bits 7-3 of data. Ignored while the
+    // channel is disabled
+    pub fn load(&mut self, data: Byte) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+// Volume envelope of the pulse and noise channels: either a constant
+// volume or a decaying saw from 15 to 0. Clocked by quarter frames
+pub struct Envelope {
+    start: bool,
+    divider: Byte,
+    decay: Byte,
+    pub looping: bool,
+    pub constant: bool,
+    pub volume: Byte,  // constant volume or divider period
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope { start: false, divider: 0, decay: 0, looping: false, constant: false, volume: 0 }
+    }
+
+    // --LC VVVV of the first channel register
+    pub fn write(&mut self, data: Byte) {
+        self.looping = data & 0x20 > 0;
+        self.constant = data & 0x10 > 0;
+        self.volume = data & 0x0F;
+    }
+
+    // restart the decay on the next clock
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> Byte {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_counter() {
+        let mut length = LengthCounter::new();
+        length.load(0x08);
+        assert_eq!(length.counter, 0, "disabled counters are not loaded");
+
+        length.set_enabled(true);
+        length.load(0x08);
+        assert_eq!(length.counter, 254);
+        length.clock();
+        assert_eq!(length.counter, 253);
+        length.halt = true;
+        length.clock();
+        assert_eq!(length.counter, 253);
+
+        length.set_enabled(false);
+        assert!(!length.is_active());
+    }
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut envelope = Envelope::new();
+        envelope.write(0x01);  // period 2
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+
+        // decays to 0 and stays there unless it loops
+        for _ in 0..40 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.looping = true;
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+
+        envelope.write(0x17);
+        assert_eq!(envelope.output(), 7);
+    }
+}
@@ -0,0 +1,109 @@
+use failure::Error;
+use crate::nes::audio::resampler::Resampler;
+
+pub mod resampler;
+#[cfg(any(feature = "audio", test))]
+pub mod ring_buffer;
+pub mod wav;
+#[cfg(feature = "audio")]
+pub mod live;
+
+pub use crate::nes::audio::wav::WavSink;
+#[cfg(feature = "audio")]
+pub use crate::nes::audio::live::LiveSink;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// output samples collected before they are handed to the sinks
+const CHUNK_SIZE: usize = 256;
+
+// Receives the audio samples of the NES: mono, between -1.0 and 1.0, at
+// the sample rate of the audio pipeline
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error>;
+}
+
+// Audio pipeline: takes the mixed APU output once per CPU cycle,
+// resamples it and passes the samples on to all sinks. Does nothing
+// without sinks
+pub struct Audio {
+    resampler: Resampler,
+    samples: Vec<f32>,
+    sinks: Vec<Box<dyn AudioSink>>,
+}
+
+impl Audio {
+    pub fn new(sample_rate: u32) -> Self {
+        Audio {
+            resampler: Resampler::new(sample_rate),
+            samples: Vec::with_capacity(2 * CHUNK_SIZE),
+            sinks: vec![],
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.sinks.is_empty()
+    }
+
+    // One CPU cycle of APU output
+    pub fn clock(&mut self, level: f32) {
+        self.resampler.clock(level, &mut self.samples);
+        if self.samples.len() >= CHUNK_SIZE {
+            self.flush();
+        }
+    }
+
+    // Hand the collected samples to the sinks. A failing sink is removed,
+    // the emulation goes on without it
+    pub fn flush(&mut self) {
+        let samples = &self.samples;
+        self.sinks.retain_mut(|sink| match sink.write(samples) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Audio output failed, disabling it: {}", e);
+                false
+            }
+        });
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use core::cell::RefCell;
+
+    // collects all samples
+    struct TestSink(Rc<RefCell<Vec<f32>>>);
+
+    impl AudioSink for TestSink {
+        fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+            self.0.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_audio_sinks() {
+        let samples = Rc::new(RefCell::new(vec![]));
+        let mut audio = Audio::new(48000);
+        assert!(!audio.is_enabled());
+        audio.add_sink(Box::new(TestSink(samples.clone())));
+        assert!(audio.is_enabled());
+
+        // a frame of cpu cycles
+        for _ in 0..29781 {
+            audio.clock(0.5);
+        }
+        audio.flush();
+        assert_eq!(samples.borrow().len(), 798);
+    }
+}
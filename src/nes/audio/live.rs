@@ -0,0 +1,67 @@
+use std::sync::{Arc,Mutex};
+use failure::{Error,err_msg};
+use cpal::traits::{DeviceTrait,HostTrait,StreamTrait};
+use cpal::{Sample,SampleFormat,StreamConfig};
+use crate::nes::audio::AudioSink;
+use crate::nes::audio::ring_buffer::RingBuffer;
+
+// samples buffered for the sound card, in seconds
+const LATENCY: f32 = 0.1;
+
+// Plays the samples on the default output device. The device pulls the
+// samples from a ring buffer on its own thread
+pub struct LiveSink {
+    buffer: Arc<Mutex<RingBuffer>>,
+    sample_rate: u32,
+    // playback stops when the stream is dropped
+    _stream: cpal::Stream,
+}
+
+impl LiveSink {
+    pub fn new() -> Result<Self, Error> {
+        let device = cpal::default_host().default_output_device()
+            .ok_or_else(|| err_msg("No audio output device found"))?;
+        let supported = device.default_output_config()?;
+        let config = supported.config();
+        let sample_rate = config.sample_rate.0;
+        let buffer = Arc::new(Mutex::new(RingBuffer::new((sample_rate as f32 * LATENCY) as usize)));
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, buffer.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, buffer.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, buffer.clone())?,
+        };
+        stream.play()?;
+        info!("Audio output: {} Hz, {} channels", sample_rate, config.channels);
+        Ok(LiveSink { buffer, sample_rate, _stream: stream })
+    }
+
+    // sample rate of the device. The audio pipeline must resample to it
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+// Output stream that plays the mono samples on all channels. Plays
+// silence when the emulation falls behind
+fn build_stream<T: Sample>(device: &cpal::Device, config: &StreamConfig,
+    buffer: Arc<Mutex<RingBuffer>>) -> Result<cpal::Stream, Error> {
+    let channels = config.channels as usize;
+    let stream = device.build_output_stream(config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut buffer = buffer.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                let sample = T::from(&buffer.pop().unwrap_or(0.0));
+                frame.iter_mut().for_each(|s| *s = sample);
+            }
+        },
+        |e| error!("Audio stream error: {}", e))?;
+    Ok(stream)
+}
+
+impl AudioSink for LiveSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let mut buffer = self.buffer.lock().unwrap();
+        samples.iter().for_each(|&sample| buffer.push(sample));
+        Ok(())
+    }
+}
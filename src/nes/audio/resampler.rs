@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// NTSC CPU clock in Hz. The APU produces one sample per CPU cycle
+pub const CPU_CLOCK_RATE: f64 = 1_789_773.0;
+
+// Length of the band-limited step in output samples and the number of
+// sub-sample positions it is precomputed for
+const TAPS: usize = 16;
+const PHASES: usize = 64;
+// Cutoff relative to the output nyquist frequency
+const CUTOFF: f64 = 0.9;
+// The NES has a high-pass filter at about 90 Hz that removes the DC
+// offset of the mixed signal
+const HIGH_PASS_HZ: f64 = 90.0;
+
+// Band-limited resampler from the CPU clock rate down to an audio sample
+// rate. Instead of filtering millions of input samples a second, every
+// change of the input level is added to the output as a band-limited step
+// (a windowed sinc impulse that is summed up). The APU output changes
+// rarely, which makes this cheap. Also see blargg's blip_buf.
+pub struct Resampler {
+    sample_rate: u32,
+    // output samples per input sample
+    ratio: f64,
+    // position of the current input sample in output samples, relative to
+    // the front of deltas
+    time: f64,
+    level: f32,
+    // pending sample deltas. The output is their running sum
+    deltas: VecDeque<f32>,
+    sum: f32,
+    // impulse responses for each phase, each summing up to 1
+    kernel: Vec<[f32; TAPS]>,
+    // high-pass filter state
+    high_pass: f32,
+    last_sum: f32,
+    last_out: f32,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        Resampler {
+            sample_rate,
+            ratio: sample_rate as f64 / CPU_CLOCK_RATE,
+            time: 0.0,
+            level: 0.0,
+            deltas: VecDeque::from(vec![0.0; TAPS + 1]),
+            sum: 0.0,
+            kernel: Resampler::kernel(),
+            high_pass: (1.0 / (1.0 + 2.0 * PI * HIGH_PASS_HZ / sample_rate as f64)) as f32,
+            last_sum: 0.0,
+            last_out: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Blackman windowed sinc, sampled at TAPS points for every phase
+    fn kernel() -> Vec<[f32; TAPS]> {
+        (0..PHASES).map(|phase| {
+            let offset = phase as f64 / PHASES as f64;
+            let mut impulse = [0.0; TAPS];
+            for (i, tap) in impulse.iter_mut().enumerate() {
+                let x = i as f64 - (TAPS / 2) as f64 + 1.0 - offset;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * CUTOFF * x).sin() / (PI * CUTOFF * x) };
+                let n = (x + (TAPS / 2) as f64) / TAPS as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+                *tap = (sinc * window) as f32;
+            }
+            let total: f32 = impulse.iter().sum();
+            impulse.iter_mut().for_each(|tap| *tap /= total);
+            impulse
+        }).collect()
+    }
+
+    // One input sample. Finished output samples are appended to out
+    pub fn clock(&mut self, level: f32, out: &mut Vec<f32>) {
+        if level != self.level {
+            let delta = level - self.level;
+            self.level = level;
+            // time is always below 1.0 here, the step starts in the next
+            // output sample
+            let phase = ((self.time * PHASES as f64) as usize).min(PHASES - 1);
+            for (i, tap) in self.kernel[phase].iter().enumerate() {
+                self.deltas[i] += delta * tap;
+            }
+        }
+
+        self.time += self.ratio;
+        while self.time >= 1.0 {
+            self.time -= 1.0;
+            self.sum += self.deltas.pop_front().unwrap_or(0.0);
+            self.deltas.push_back(0.0);
+
+            let out_sample = self.high_pass * (self.last_out + self.sum - self.last_sum);
+            self.last_sum = self.sum;
+            self.last_out = out_sample;
+            out.push(out_sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // resample a square wave of the given frequency for one second
+    fn square(sample_rate: u32, frequency: f64) -> Vec<f32> {
+        let mut resampler = Resampler::new(sample_rate);
+        let mut out = vec![];
+        let half_period = (CPU_CLOCK_RATE / frequency / 2.0) as u64;
+        for cycle in 0..CPU_CLOCK_RATE as u64 {
+            let level = if (cycle / half_period) & 0x01 == 0 { 0.5 } else { -0.5 };
+            resampler.clock(level, &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        for &rate in [44100, 48000].iter() {
+            let out = square(rate, 440.0);
+            assert!((out.len() as i64 - rate as i64).abs() <= 1, "{}", out.len());
+        }
+    }
+
+    #[test]
+    fn test_band_limited() {
+        // audible frequencies pass. The high-pass filter makes the edges
+        // of the square wave overshoot
+        let out = square(44100, 440.0);
+        let peak = out[1000..].iter().fold(0.0f32, |max, s| max.max(s.abs()));
+        assert!(peak > 0.45 && peak < 0.9, "{}", peak);
+
+        // frequencies above nyquist do not alias back
+        let out = square(44100, 100_000.0);
+        let peak = out[1000..].iter().fold(0.0f32, |max, s| max.max(s.abs()));
+        assert!(peak < 0.05, "{}", peak);
+    }
+}
@@ -0,0 +1,62 @@
+// Fixed size sample queue between the emulation and an audio device.
+// When the emulation runs ahead the oldest samples are dropped, when it
+// falls behind the reader runs out of samples.
+pub struct RingBuffer {
+    samples: Vec<f32>,
+    // index of the oldest sample and number of stored samples
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer { samples: vec![0.0; capacity], start: 0, len: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let capacity = self.capacity();
+        let end = (self.start + self.len) % capacity;
+        self.samples[end] = sample;
+        if self.len == capacity {
+            self.start = (self.start + 1) % capacity;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<f32> {
+        if self.len == 0 {
+            return None
+        }
+        let sample = self.samples[self.start];
+        self.start = (self.start + 1) % self.capacity();
+        self.len -= 1;
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut buffer = RingBuffer::new(4);
+        for i in 0..3 {
+            buffer.push(i as f32);
+        }
+        assert_eq!(buffer.pop(), Some(0.0));
+        assert_eq!(buffer.pop(), Some(1.0));
+
+        // wraps around and drops the oldest samples when full
+        for i in 3..8 {
+            buffer.push(i as f32);
+        }
+        let samples: Vec<f32> = std::iter::from_fn(|| buffer.pop()).collect();
+        assert_eq!(samples, vec![4.0, 5.0, 6.0, 7.0]);
+    }
+}
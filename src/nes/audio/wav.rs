@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::{BufWriter,Seek,SeekFrom,Write};
+use std::path::Path;
+use failure::Error;
+use crate::nes::audio::AudioSink;
+
+// size of the RIFF header up to the sample data
+const HEADER_SIZE: u32 = 44;
+
+// Writes the samples to a mono 16 bit PCM wav file. Works without a
+// sound card, e.g. to capture the audio of headless runs
+pub struct WavSink {
+    file: BufWriter<File>,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl WavSink {
+    pub fn create(path: &Path, sample_rate: u32) -> Result<Self, Error> {
+        let mut sink = WavSink {
+            file: BufWriter::new(File::create(path)?),
+            sample_rate,
+            data_size: 0,
+        };
+        sink.write_header()?;
+        Ok(sink)
+    }
+
+    // The sizes in the header are only known at the end. They are
+    // rewritten on every finish
+    fn write_header(&mut self) -> Result<(), Error> {
+        let channels: u16 = 1;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+        let f = &mut self.file;
+        f.write_all(b"RIFF")?;
+        f.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        f.write_all(b"WAVE")?;
+        f.write_all(b"fmt ")?;
+        f.write_all(&16u32.to_le_bytes())?;
+        f.write_all(&1u16.to_le_bytes())?;  // PCM
+        f.write_all(&channels.to_le_bytes())?;
+        f.write_all(&self.sample_rate.to_le_bytes())?;
+        f.write_all(&(self.sample_rate * block_align as u32).to_le_bytes())?;
+        f.write_all(&block_align.to_le_bytes())?;
+        f.write_all(&bits_per_sample.to_le_bytes())?;
+        f.write_all(b"data")?;
+        f.write_all(&self.data_size.to_le_bytes())?;
+        Ok(())
+    }
+
+    // Update the header and flush everything to disk
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.data_size += 2 * samples.len() as u32;
+        Ok(())
+    }
+}
+
+impl Drop for WavSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            error!("Failed to finish wav file: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_wav_sink() {
+        let path = env::temp_dir().join("jane_test_wav_sink.wav");
+        {
+            let mut sink = WavSink::create(&path, 44100).unwrap();
+            sink.write(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        }
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(data[4..8], 44u32.to_le_bytes());
+        assert_eq!(data[24..28], 44100u32.to_le_bytes());
+        assert_eq!(data[40..44], 8u32.to_le_bytes());
+        // samples are clamped
+        assert_eq!(data[46..48], i16::MAX.to_le_bytes());
+        assert_eq!(data[50..52], i16::MAX.to_le_bytes());
+    }
+}
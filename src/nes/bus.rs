@@ -1,7 +1,9 @@
 use crate::nes::ppu::PPU;
 use crate::nes::ppubus::PPUBus;
+use crate::nes::apu::{self,APU};
+use crate::nes::input::InputDevice;
 use std::rc::Rc;
-use core::cell::RefCell;
+use core::cell::{Cell,RefCell};
 use crate::nes::cartridge::Cartridge;
 use crate::nes::types::*;
 
@@ -11,6 +13,12 @@ pub const RAM_PHYS_RANGE: [Addr; 2] = [0x0000, 0x07ff];
 pub const PPU_ADDR_RANGE: [Addr; 2] = [0x2000, 0x3fff];
 pub const PPU_PHYS_RANGE: [Addr; 2] = [0x2000, 0x2007];
 pub const CART_ADDR_RANGE: [Addr; 2] = [0x4020, 0xffff];
+pub const APU_ADDR_RANGE: [Addr; 2] = [0x4000, 0x4013];
+pub const OAM_DMA_ADDR: Addr = 0x4014;
+// strobe of both ports on write, port 1 on read
+pub const CONTROLLER1_ADDR: Addr = 0x4016;
+// port 2 on read. Writes go to the APU frame counter
+pub const CONTROLLER2_ADDR: Addr = 0x4017;
 
 // NES memory: Contains data from RAM, cartridge...
 pub struct Bus {
@@ -18,21 +26,79 @@ pub struct Bus {
     cartridge: Option<Rc<RefCell<Cartridge>>>,
     ppu: Rc<RefCell<PPU>>,
     ppu_bus: Rc<RefCell<PPUBus>>,
+    apu: Rc<RefCell<APU>>,
+    // devices in the two controller ports
+    ports: [Option<Rc<RefCell<dyn InputDevice>>>; 2],
+    // page written to 0x4014. Picked up by the NES to start the OAM DMA
+    oam_dma: Option<Byte>,
+    // Last value on the CPU data bus. Reads of unmapped addresses return
+    // it (open bus). Unlike the PPU I/O latch there is no decay to make
+    // optional: the CPU reads or writes the bus on every cycle, DMA
+    // included, so the value is refreshed long before it could fade.
+    open_bus: Cell<Byte>,
 }
 
 impl Bus {
-    pub fn new(ppu: Rc<RefCell<PPU>>, ppu_bus: Rc<RefCell<PPUBus>>) -> Self {
+    pub fn new(ppu: Rc<RefCell<PPU>>, ppu_bus: Rc<RefCell<PPUBus>>, apu: Rc<RefCell<APU>>) -> Self {
         Bus {
             ram: [0; RAM_SIZE],
             cartridge: None,
             ppu: ppu,
             ppu_bus: ppu_bus,
+            apu,
+            ports: [None, None],
+            oam_dma: None,
+            open_bus: Cell::new(0x00),
         }
     }
 
     pub fn insert_cartridge(&mut self, c: Rc<RefCell<Cartridge>>) {
         self.cartridge = Some(c);
     }
+
+    // Plug a device into port 0 or 1. None leaves the port empty
+    pub fn connect_input(&mut self, port: usize, device: Option<Rc<RefCell<dyn InputDevice>>>) {
+        self.ports[port] = device;
+    }
+
+    // Page of a requested OAM DMA, if 0x4014 was written since the last call
+    pub fn take_oam_dma(&mut self) -> Option<Byte> {
+        self.oam_dma.take()
+    }
+
+    // Read from the device mapped to addr. None if nothing answers
+    fn read_mapped(&self, addr: Addr) -> Option<Byte> {
+        if let Some(cartridge) = &self.cartridge {
+            if CART_ADDR_RANGE[0] <= addr && addr <= CART_ADDR_RANGE[1] {
+                return cartridge.borrow_mut().readb(addr)
+            } 
+        }
+        if RAM_ADDR_RANGE[0] <= addr && addr <= RAM_ADDR_RANGE[1] {
+            // Ram is 3x mirrored after 0x07ff
+            return Some(self.ram[(addr & RAM_PHYS_RANGE[1]) as usize])
+        }
+        if PPU_ADDR_RANGE[0] <= addr && addr <= PPU_ADDR_RANGE[1] {
+            let mut ppu = self.ppu.borrow_mut();
+            let ppu_bus = self.ppu_bus.borrow();
+            return Some(ppu.readb(&*ppu_bus, addr & PPU_PHYS_RANGE[1]));
+        }
+        if addr == apu::STATUS_ADDR {
+            // bit 5 is not driven
+            let status = self.apu.borrow_mut().read_status();
+            return Some(status | self.open_bus.get() & 0x20);
+        }
+        if addr == CONTROLLER1_ADDR || addr == CONTROLLER2_ADDR {
+            // only D0-D4 are driven, the rest is open bus. An empty
+            // port reads 0
+            let port = (addr - CONTROLLER1_ADDR) as usize;
+            let data = match &self.ports[port] {
+                Some(device) => device.borrow_mut().read() & 0x1F,
+                None => 0x00,
+            };
+            return Some(data | self.open_bus.get() & 0xE0);
+        }
+        None
+    }
 }
 
 pub trait Memory {
@@ -43,6 +109,15 @@ pub trait Memory {
         let hi = self.readb(addr+1);
         (hi as Word) << 8 | lo as Word
     }
+    // Read without side effects, for traces and debug views
+    fn peekb(&self, addr: Addr) -> Byte {
+        self.readb(addr)
+    }
+    fn peekw(&self, addr: Addr) -> Word {
+        let lo = self.peekb(addr);
+        let hi = self.peekb(addr+1);
+        (hi as Word) << 8 | lo as Word
+    }
     fn writew(&mut self, addr: Addr, data: Word) {
         self.writeb(addr, data as Byte);
         self.writeb(addr + 1, (data >> 8) as Byte);
@@ -51,24 +126,29 @@ pub trait Memory {
 
 impl Memory for Bus {
     fn readb(&self, addr: Addr) -> Byte {
-        if let Some(cartridge) = &self.cartridge {
-            if CART_ADDR_RANGE[0] <= addr && addr <= CART_ADDR_RANGE[1] {
-                return cartridge.borrow().readb(addr).unwrap()  // does not fail.
-            } 
-        }
+        let data = self.read_mapped(addr).unwrap_or_else(|| self.open_bus.get());
+        self.open_bus.set(data);
+        data
+    }
+
+    // Only RAM and the cartridge are peeked. Registers that change when
+    // read (PPU, APU status, controllers) show the open bus value
+    fn peekb(&self, addr: Addr) -> Byte {
         if RAM_ADDR_RANGE[0] <= addr && addr <= RAM_ADDR_RANGE[1] {
-            // Ram is 3x mirrored after 0x07ff
             return self.ram[(addr & RAM_PHYS_RANGE[1]) as usize]
         }
-        if PPU_ADDR_RANGE[0] <= addr && addr <= PPU_ADDR_RANGE[1] {
-            let mut ppu = self.ppu.borrow_mut();
-            let ppu_bus = self.ppu_bus.borrow();
-            return ppu.readb(&*ppu_bus, addr & PPU_PHYS_RANGE[1]);
+        if let Some(cartridge) = &self.cartridge {
+            if CART_ADDR_RANGE[0] <= addr && addr <= CART_ADDR_RANGE[1] {
+                if let Some(data) = cartridge.borrow().peekb(addr) {
+                    return data
+                }
+            }
         }
-        0x0000  // generic response
+        self.open_bus.get()
     }
 
     fn writeb(&mut self, addr: Addr, data: Byte) {
+        self.open_bus.set(data);
         if let Some(cartridge) = &mut self.cartridge {
             if CART_ADDR_RANGE[0] <= addr && addr <= CART_ADDR_RANGE[1] {
                 cartridge.borrow_mut().writeb(addr, data);
@@ -83,5 +163,91 @@ impl Memory for Bus {
             let mut ppu_bus = self.ppu_bus.borrow_mut();
             ppu.writeb(&mut *ppu_bus, addr & PPU_PHYS_RANGE[1], data);
         }
+        if PPU_ADDR_RANGE[0] <= addr && addr <= PPU_ADDR_RANGE[1] {
+            if let Some(cartridge) = &self.cartridge {
+                cartridge.borrow_mut().ppu_register_write(addr & PPU_PHYS_RANGE[1], data);
+            }
+        }
+        if (APU_ADDR_RANGE[0] <= addr && addr <= APU_ADDR_RANGE[1])
+            || addr == apu::STATUS_ADDR || addr == apu::FRAME_COUNTER_ADDR {
+            self.apu.borrow_mut().writeb(addr, data);
+        }
+        if addr == CONTROLLER1_ADDR {
+            self.ports.iter().flatten().for_each(|device| device.borrow_mut().write(data));
+        }
+        if addr == OAM_DMA_ADDR {
+            self.ppu.borrow_mut().regs.dma = data;
+            self.oam_dma = Some(data);
+        }
     } 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::input::{Controller,Buttons};
+
+    fn test_bus() -> Bus {
+        let apu = APU::new(crate::nes::irq::IrqLine::new());
+        Bus::new(Rc::new(RefCell::new(PPU::new())), Rc::new(RefCell::new(PPUBus::new())), Rc::new(RefCell::new(apu)))
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = test_bus();
+        bus.writeb(0x0010, 0x42);
+        // unmapped reads return the last value on the bus
+        assert_eq!(bus.readb(0x5000), 0x42);
+        assert_eq!(bus.readb(0x0011), 0x00);
+        assert_eq!(bus.readb(0x5000), 0x00);
+        bus.writeb(0x4018, 0x99);
+        assert_eq!(bus.readb(0x4018), 0x99);
+
+        // write-only ppu registers return the ppu latch, which is also
+        // refreshed by writes
+        bus.writeb(0x2000, 0x00);
+        bus.writeb(0x2001, 0x1E);
+        assert_eq!(bus.readb(0x2000), 0x1E);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut bus = test_bus();
+        let controller = Rc::new(RefCell::new(Controller::new()));
+        controller.borrow_mut().set_button(Buttons::A, true);
+        bus.connect_input(0, Some(controller.clone()));
+        bus.writeb(CONTROLLER1_ADDR, 0x01);
+        bus.writeb(CONTROLLER1_ADDR, 0x00);
+        bus.writeb(0x0810, 0x42);
+
+        // peeks neither latch the open bus nor shift the controller
+        assert_eq!(bus.readb(0x0011), 0x00);
+        assert_eq!(bus.peekb(0x0010), 0x42);
+        assert_eq!(bus.peekw(0x0010), 0x0042);
+        assert_eq!(bus.peekb(0x5000), 0x00);
+        assert_eq!(bus.peekb(CONTROLLER1_ADDR), 0x00);
+        assert_eq!(bus.readb(0x5000), 0x00);
+        assert_eq!(bus.readb(CONTROLLER1_ADDR), 0x01);
+    }
+
+    #[test]
+    fn test_controller_ports() {
+        let mut bus = test_bus();
+        let controllers = [Rc::new(RefCell::new(Controller::new())), Rc::new(RefCell::new(Controller::new()))];
+        controllers[0].borrow_mut().set_button(Buttons::A, true);
+        controllers[1].borrow_mut().set_button(Buttons::B, true);
+        for (port, controller) in controllers.iter().enumerate() {
+            bus.connect_input(port, Some(controller.clone()));
+        }
+        bus.writeb(CONTROLLER1_ADDR, 0x01);
+        bus.writeb(CONTROLLER1_ADDR, 0x40);
+
+        // upper bits are open bus: the high byte of the address on lda $4016
+        bus.open_bus.set(0x40);
+        assert_eq!(bus.readb(CONTROLLER1_ADDR), 0x41);
+        bus.open_bus.set(0x40);
+        assert_eq!(bus.readb(CONTROLLER2_ADDR), 0x40);
+        bus.open_bus.set(0x40);
+        assert_eq!(bus.readb(CONTROLLER2_ADDR), 0x41);
+    }
+}
@@ -1,267 +1,598 @@
 use crate::nes::mappers::*;
-use failure::Error;
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs;
 use crate::nes::types::*;
-use std::path::Path;
-use std::io::SeekFrom;
-
-#[derive(Debug)]
-struct Header {
-    prg_rom_chunks: Byte,  // 16K chunks
-    chr_rom_chunks: Byte,  // 8K chunks
-    mapper1: Byte,
-    mapper2: Byte,
-    prg_ram_size: Byte,
-    tv1: Byte,
-    tv2: Byte
-}
-
-impl Header {
-    // parse the 16 Byte header of the file
-    fn new(f: &mut File) -> Result<Self, Error> {
-        f.seek(SeekFrom::Start(0))?;
-
-        // Byte 0-3 are the "NES" format header and just say NES
-        f.seek(SeekFrom::Start(4))?;
-
-        // Byte 4 and 5 are prg and chr rom sizes
-        let mut rom_sizes = [0; 2];  // prg size in 16K and chr size in 8K
-        f.read_exact(&mut rom_sizes)?;
-
-        // Byte 6-10 are various flags
-        let mut flags = [0; 5];
-        f.read_exact(&mut flags)?;
-
-        // Byte 11-15 are unused
-        f.seek(SeekFrom::Current(5))?;
-
-        Ok(Header {
-            prg_rom_chunks: rom_sizes[0],
-            chr_rom_chunks: rom_sizes[1],
-            mapper1: flags[0],
-            mapper2: flags[1],
-            prg_ram_size: flags[2],
-            tv1: flags[3],
-            tv2: flags[4],
-        })
-    }
+use std::path::{Path,PathBuf};
+use std::io::{Cursor,SeekFrom};
+use crate::nes::cartridge::header::*;
 
-    pub fn has_trainer(&self) -> bool {
-        self.mapper1 & (1 << 2) != 0
-    }
+pub mod header;
+pub mod error;
+pub mod archive;
 
-    pub fn get_mapper_id(&self) -> Byte {
-        let hi = (self.mapper2 >> 4) << 4;
-        let lo = self.mapper1 >> 4;
-        hi | lo
-    }
+pub use crate::nes::cartridge::header::CartridgeInfo;
+pub use crate::nes::cartridge::error::CartridgeError;
 
-    pub fn get_mirror_mode(&self) -> MirrorMode {
-        if (self.mapper1 & 0x01) == 0 {
-            MirrorMode::HORIZONTAL
-        } else {
-            MirrorMode::VERTICAL
-        }
-    }
+// Cartridges bigger than this are broken headers
+pub const MAX_ROM_SIZE: usize = 64 * 1024 * 1024;
 
-}
+// the trainer is mapped to $7000-$71FF
+const TRAINER_ADDR: Addr = 0x7000;
+const MIN_TRAINER_RAM_SIZE: usize = 8192;
+const CHR_RAM_SIZE: usize = 8192;
+const FOUR_SCREEN_VRAM_SIZE: usize = 4096;
 
 // Nametable mirroring mode
 #[derive(PartialEq,Debug,Copy,Clone)]
 pub enum MirrorMode {
-    HORIZONTAL,
-    VERTICAL,
-} 
+    Horizontal,
+    Vertical,
+    // all four nametables show the first or the second 1K of VRAM
+    SingleScreenLower,
+    SingleScreenUpper,
+    // 2K extra VRAM on the cartridge
+    FourScreen,
+    // the mapper picks the 1K of VRAM (0 or 1) of each nametable
+    Custom([Byte; 4]),
+}
 
 pub struct Cartridge {
     prg_rom: Vec<Byte>,
-    chr_rom: Vec<Byte>,
+    // CHR ROM or CHR RAM
+    chr: Vec<Byte>,
+    chr_ram: bool,
     mapper: Box<dyn Mapper>,
+    // the mapper is built anew from it at power on
+    layout: Layout,
     mirror: MirrorMode,
+    info: CartridgeInfo,
+    prg_ram: Vec<Byte>,
+    // nametables of four screen boards
+    vram: Vec<Byte>,
+    trainer: Option<Vec<Byte>>,
+    // battery backed PRG RAM is kept in this file
+    sav_path: Option<PathBuf>,
+    // PRG RAM changed since the last save
+    prg_ram_dirty: bool,
 }
 
 impl Cartridge {
-    pub fn new(path: &Path) -> Result<Self, Error> {
-        let mut f = File::open(path)?;
-        let header = Header::new(&mut f)?;
+    // Load a .nes file or a .zip/.gz archive containing one. Battery
+    // backed RAM is loaded from a .sav file next to it
+    pub fn new(path: &Path) -> Result<Self, CartridgeError> {
+        let bytes = fs::read(path)?;
+        let mut cartridge = match archive::extract(&bytes)? {
+            Some(rom) => Cartridge::from_bytes(&rom)?,
+            None => Cartridge::from_bytes(&bytes)?,
+        };
+        if cartridge.info.battery {
+            let sav_path = path.with_extension("sav");
+            if sav_path.exists() {
+                cartridge.load_sav(&sav_path)?;
+                // the trainer goes on top of the saved RAM
+                cartridge.power_on();
+            }
+            cartridge.sav_path = Some(sav_path);
+        }
+        Ok(cartridge)
+    }
+
+    // iNES file in memory
+    pub fn from_bytes(bytes: &[Byte]) -> Result<Self, CartridgeError> {
+        Cartridge::from_reader(&mut Cursor::new(bytes))
+    }
+
+    pub fn from_reader<R: Read + Seek>(f: &mut R) -> Result<Self, CartridgeError> {
+        let header = Header::new(f)?;
         debug!("{:?}", header);
+        let info = header.info();
+        // the mappers switch PRG ROM in 8K banks at the smallest
+        if info.prg_rom_size == 0 || info.prg_rom_size % 0x2000 != 0 || info.prg_rom_size > MAX_ROM_SIZE {
+            return Err(CartridgeError::InvalidSize { what: "PRG ROM", size: info.prg_rom_size })
+        }
+        if info.chr_rom_size > MAX_ROM_SIZE {
+            return Err(CartridgeError::InvalidSize { what: "CHR ROM", size: info.chr_rom_size })
+        }
 
-        f.seek(SeekFrom::Start(16))?;
+        f.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
 
-        if header.has_trainer() {
-            f.seek(SeekFrom::Current(512))?;
+        let trainer = if info.trainer {
+            let trainer = read_rom(f, TRAINER_SIZE)?;
+            if trainer.len() < TRAINER_SIZE {
+                return Err(CartridgeError::TruncatedTrainer(trainer.len()))
+            }
+            Some(trainer)
+        } else {
+            None
+        };
+
+        let prg_rom = read_rom(f, info.prg_rom_size)?;
+        if prg_rom.len() < info.prg_rom_size {
+            return Err(CartridgeError::TruncatedPrgRom { expected: info.prg_rom_size, found: prg_rom.len() })
+        }
+        let chr_rom = read_rom(f, info.chr_rom_size)?;
+        if chr_rom.len() < info.chr_rom_size {
+            return Err(CartridgeError::TruncatedChrRom { expected: info.chr_rom_size, found: chr_rom.len() })
         }
 
-        let mut prg_rom = vec!(0; header.prg_rom_chunks as usize * 16384);
-        f.read_exact(&mut prg_rom)?;
-        let mut chr_rom = vec!(0; header.chr_rom_chunks as usize * 8192);
-        f.read_exact(&mut chr_rom)?;
+        let mut prg_ram_size = info.prg_ram_size + info.prg_nvram_size;
+        if trainer.is_some() {
+            prg_ram_size = prg_ram_size.max(MIN_TRAINER_RAM_SIZE);
+        }
 
-        let mapper = match header.get_mapper_id() {
-            0 => { Mapper0::new(header.prg_rom_chunks, header.chr_rom_chunks) }
-            id => bail!("Mapper {:04} not supported", id)
+        // boards without CHR ROM have RAM instead
+        let chr_ram = chr_rom.is_empty();
+        let chr = if chr_ram {
+            vec![0; (info.chr_ram_size + info.chr_nvram_size).max(CHR_RAM_SIZE)]
+        } else {
+            chr_rom
         };
 
-        let mirror = header.get_mirror_mode();
+        let layout = Layout {
+            prg_rom_size: prg_rom.len(),
+            prg_ram_size,
+            chr_size: chr.len(),
+            submapper: info.submapper,
+        };
+        let mapper = match new_mapper(info.mapper, layout) {
+            Some(mapper) => mapper,
+            None => return Err(CartridgeError::UnsupportedMapper { id: info.mapper, name: mapper_name(info.mapper) }),
+        };
 
-        debug!("Cartrige loaded. mapper: {:?}", &mapper);
-        Ok(Cartridge {
+        let mirror = info.mirror;
+        let vram_size = if mirror == MirrorMode::FourScreen { FOUR_SCREEN_VRAM_SIZE } else { 0 };
+
+        debug!("Cartrige loaded. mapper: {}, {:?}", mapper_name(info.mapper), &info);
+        let mut cartridge = Cartridge {
             prg_rom: prg_rom,
-            chr_rom: chr_rom,
-            mapper: Box::new(mapper),
+            chr,
+            chr_ram,
+            mapper,
+            layout,
             mirror: mirror,
-        })
+            info,
+            prg_ram: vec![0; prg_ram_size],
+            vram: vec![0; vram_size],
+            trainer,
+            sav_path: None,
+            prg_ram_dirty: false,
+        };
+        cartridge.power_on();
+        Ok(cartridge)
     }
 
     pub fn dummy(mirror: MirrorMode) -> Self {
+        let layout = Layout { prg_rom_size: 16384, prg_ram_size: 0, chr_size: 8192, submapper: 0 };
         Cartridge {
             prg_rom: vec![0; 16384],
-            chr_rom: vec![0; 8192],
-            mapper: Box::new(Mapper0::new(1, 1)),
+            chr: vec![0; 8192],
+            chr_ram: false,
+            mapper: Box::new(Nrom::new(layout)),
+            layout,
             mirror: mirror,
+            info: Header::parse(&[b'N', b'E', b'S', 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).info(),
+            prg_ram: vec![],
+            vram: vec![],
+            trainer: None,
+            sav_path: None,
+            prg_ram_dirty: false,
+        }
+    }
+
+    // Put the mapper into its power on state, clear the PRG RAM and copy
+    // the trainer into it. Battery backed RAM keeps its content
+    pub fn power_on(&mut self) {
+        if let Some(mapper) = new_mapper(self.info.mapper, self.layout) {
+            self.mapper = mapper;
+        }
+        if !self.info.battery {
+            self.prg_ram.iter_mut().for_each(|b| *b = 0);
+        }
+        if let Some(trainer) = &self.trainer {
+            let start = (TRAINER_ADDR - 0x6000) as usize;
+            self.prg_ram[start..start + TRAINER_SIZE].copy_from_slice(trainer);
+        }
+    }
+
+    // Start with empty battery backed RAM, like FCEUX does for movies. The
+    // .sav file is left alone from now on, it doesn't belong to this run
+    pub fn clear_battery_ram(&mut self) {
+        if self.info.battery {
+            self.prg_ram.iter_mut().for_each(|b| *b = 0);
+            self.sav_path = None;
+            self.prg_ram_dirty = false;
         }
     }
 
-    pub fn readb(&self, addr: Addr) -> Option<Byte> {
-        if let Some(mapped_addr) = self.mapper.map_read_addr(addr) {
-            return Some(self.prg_rom[mapped_addr as usize])
+    // Reset button
+    pub fn reset(&mut self) {
+        self.mapper.reset();
+    }
+
+    // One CPU cycle. Returns true if the mapper asserts the IRQ line
+    pub fn clock(&mut self) -> bool {
+        self.mapper.clock();
+        self.mapper.irq()
+    }
+
+    // Fill the PRG RAM from a save file. Files of the wrong size are
+    // loaded as far as they fit
+    pub fn load_sav(&mut self, path: &Path) -> Result<(), CartridgeError> {
+        let sav = fs::read(path)?;
+        if sav.len() != self.prg_ram.len() {
+            warn!("{} has {} bytes, expected {}", path.display(), sav.len(), self.prg_ram.len());
+        }
+        let len = sav.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&sav[..len]);
+        Ok(())
+    }
+
+    // Write battery backed PRG RAM to the .sav file if it changed
+    pub fn flush_sav(&mut self) -> Result<(), CartridgeError> {
+        if let (Some(path), true) = (&self.sav_path, self.prg_ram_dirty) {
+            fs::write(path, &self.prg_ram)?;
+            self.prg_ram_dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn readb(&mut self, addr: Addr) -> Option<Byte> {
+        let mapped = self.mapper.read(addr);
+        self.read_mapped(mapped)
+    }
+
+    // Read without side effects on the mapper, for debug views
+    pub fn peekb(&self, addr: Addr) -> Option<Byte> {
+        self.read_mapped(self.mapper.peek(addr))
+    }
+
+    fn read_mapped(&self, mapped: Mapped) -> Option<Byte> {
+        match mapped {
+            Mapped::PrgRom(index) => Some(self.prg_rom[index % self.prg_rom.len()]),
+            Mapped::PrgRam(index) => Some(self.prg_ram[index % self.prg_ram.len()]),
+            Mapped::Chr(index) => Some(self.chr[index % self.chr.len()]),
+            Mapped::Data(data) => Some(data),
+            Mapped::None => None,
         }
-        None
     }
 
+    // Write to the cartridge. Returns true if something on the cartridge
+    // took the write
     pub fn writeb(&mut self, addr: Addr, data: Byte) -> bool {
-        if let Some(mapped_addr) = self.mapper.map_write_addr(addr) {
-            self.prg_rom[mapped_addr as usize] = data;
-            return true;
+        let data = if self.mapper.bus_conflicts() {
+            match self.mapper.peek(addr) {
+                Mapped::PrgRom(index) => data & self.prg_rom[index % self.prg_rom.len()],
+                _ => data,
+            }
+        } else {
+            data
+        };
+        match self.mapper.write(addr, data) {
+            Mapped::PrgRam(index) => {
+                let index = index % self.prg_ram.len();
+                self.prg_ram_dirty |= self.prg_ram[index] != data;
+                self.prg_ram[index] = data;
+                true
+            },
+            Mapped::Chr(index) if self.chr_ram => {
+                let index = index % self.chr.len();
+                self.chr[index] = data;
+                true
+            },
+            Mapped::None => false,
+            _ => true,
         }
-        false
     }
 
     // Read from cartridge if the cartridge has readable VRAM/VROM
     pub fn readb_ppu(&self, addr: Addr) -> Option<Byte> {
-        if let Some(mapped_addr) = self.mapper.map_read_addr_ppu(addr) {
-            return Some(self.chr_rom[mapped_addr as usize])
+        match self.mapper.read_ppu(addr) {
+            Mapped::Chr(index) => Some(self.chr[index % self.chr.len()]),
+            Mapped::PrgRom(index) => Some(self.prg_rom[index % self.prg_rom.len()]),
+            Mapped::PrgRam(index) => Some(self.prg_ram[index % self.prg_ram.len()]),
+            Mapped::Data(data) => Some(data),
+            Mapped::None => self.vram_index(addr).map(|index| self.vram[index]),
         }
-        None
     }
 
     // Let the cartridge handle the ppu write. Returns true if cartridge
     // handled the write, false otherwise
     pub fn writeb_ppu(&mut self, addr: Addr, data: Byte) -> bool {
-        if let Some(mapped_addr) = self.mapper.map_write_addr_ppu(addr) {
-            self.chr_rom[mapped_addr as usize] = data;
-            return true;
+        match self.mapper.write_ppu(addr, data) {
+            Mapped::Chr(index) => {
+                if self.chr_ram {
+                    let index = index % self.chr.len();
+                    self.chr[index] = data;
+                }
+                true
+            },
+            Mapped::None => match self.vram_index(addr) {
+                Some(index) => {
+                    self.vram[index] = data;
+                    true
+                },
+                None => false,
+            },
+            _ => true,
+        }
+    }
+
+    // The PPU accessed addr. Lets the mapper watch the PPU address bus
+    pub fn ppu_bus(&mut self, addr: Addr) {
+        self.mapper.ppu_bus(addr);
+    }
+
+    // The CPU wrote a PPU register
+    pub fn ppu_register_write(&mut self, addr: Addr, data: Byte) {
+        self.mapper.ppu_register_write(addr, data);
+    }
+
+    // Expansion audio of the mapper
+    pub fn audio_output(&self) -> f32 {
+        self.mapper.audio_output()
+    }
+
+    // Index of a nametable address in the four screen VRAM
+    fn vram_index(&self, addr: Addr) -> Option<usize> {
+        match addr {
+            0x2000..=0x3EFF if !self.vram.is_empty() => Some((addr & 0x0FFF) as usize),
+            _ => None,
         }
-        false
     }
 
-    // get cartrige mirror mode
-    // TODO can be changed by mapper
+    // what the header says about the cartridge
+    pub fn info(&self) -> &CartridgeInfo {
+        &self.info
+    }
+
+    // get cartrige mirror mode. Mappers can switch it at runtime
     pub fn get_mirror_mode(&self) -> MirrorMode {
-        self.mirror
+        // the VRAM of four screen boards overrides the mapper
+        if self.mirror == MirrorMode::FourScreen {
+            return self.mirror
+        }
+        self.mapper.mirror_mode().unwrap_or(self.mirror)
     }
 }
 
-
+// read up to size bytes. Less if the file ends early
+fn read_rom<R: Read>(f: &mut R, size: usize) -> Result<Vec<Byte>, CartridgeError> {
+    let mut rom = Vec::with_capacity(size);
+    f.take(size as u64).read_to_end(&mut rom)?;
+    Ok(rom)
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nes::{NES,Memory};
+    use std::env;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
 
     #[test]
-    fn test_header_get_mapper_id() {
-        let header = Header {
-                prg_rom_chunks: 1,
-                chr_rom_chunks: 1,
-                mapper1: 0x00,
-                mapper2: 0x00,
-                prg_ram_size: 0x00,
-                tv1: 0x00,
-                tv2: 0x00
-        };
-        assert_eq!(0, header.get_mapper_id());
-
-        let header = Header {
-                prg_rom_chunks: 1,
-                chr_rom_chunks: 1,
-                mapper1: 0x10,
-                mapper2: 0x00,
-                prg_ram_size: 0x00,
-                tv1: 0x00,
-                tv2: 0x00
-        };
-        assert_eq!(1, header.get_mapper_id());
+    fn test_cartridge_new() {
+       let path = Path::new("test_roms/nestest.nes");
+       let cartridge = Cartridge::new(&path).unwrap();
+       assert_eq!(cartridge.info().mapper, 0);
+       assert_eq!(cartridge.info().prg_rom_size, 16384);
+       assert_eq!(cartridge.info().timing, Timing::Ntsc);
+    }
 
+    fn load(bytes: &[Byte]) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_bytes(bytes)
+    }
 
-        let header = Header {
-                prg_rom_chunks: 1,
-                chr_rom_chunks: 1,
-                mapper1: 0xff,
-                mapper2: 0xff,
-                prg_ram_size: 0x00,
-                tv1: 0x00,
-                tv2: 0x00
-        };
-        assert_eq!(255, header.get_mapper_id());
+    fn rom(flags6: Byte, prg_chunks: Byte, chr_chunks: Byte, len: usize) -> Vec<Byte> {
+        let mut rom = vec![0; len];
+        rom[0..4].copy_from_slice(header::MAGIC);
+        rom[4] = prg_chunks;
+        rom[5] = chr_chunks;
+        rom[6] = flags6;
+        rom
     }
 
     #[test]
-    fn test_header_get_mirror_mode() {
-        let header = Header {
-                prg_rom_chunks: 1,
-                chr_rom_chunks: 1,
-                mapper1: 0x00,
-                mapper2: 0x00,
-                prg_ram_size: 0x00,
-                tv1: 0x00,
-                tv2: 0x00
-        };
-        assert_eq!(header.get_mirror_mode(), MirrorMode::HORIZONTAL);
-        let header = Header {
-                prg_rom_chunks: 1,
-                chr_rom_chunks: 1,
-                mapper1: 0x01,
-                mapper2: 0x00,
-                prg_ram_size: 0x00,
-                tv1: 0x00,
-                tv2: 0x00
-        };
-        assert_eq!(header.get_mirror_mode(), MirrorMode::VERTICAL);
+    fn test_cartridge_errors() {
+        match load(b"PK\x03\x04 not a rom") {
+            Err(CartridgeError::BadMagic(magic)) => assert_eq!(&magic, b"PK\x03\x04"),
+            _ => panic!("expected bad magic"),
+        }
+        assert!(matches!(load(b"NES\x1A\x01"),
+            Err(CartridgeError::TruncatedHeader(5))));
+        assert!(matches!(load(&rom(0x00, 2, 1, 16 + 20000)),
+            Err(CartridgeError::TruncatedPrgRom { expected: 32768, found: 20000 })));
+        assert!(matches!(load(&rom(0x00, 1, 1, 16 + 16384 + 100)),
+            Err(CartridgeError::TruncatedChrRom { expected: 8192, found: 100 })));
+        assert!(matches!(load(&rom(0x00, 0, 1, 16 + 8192)),
+            Err(CartridgeError::InvalidSize { what: "PRG ROM", size: 0 })));
+        let err = load(&rom(0xB0, 1, 1, 16 + 16384 + 8192)).err().unwrap();
+        assert_eq!(err.to_string(), "Mapper 11 (Color Dreams) is not supported");
+        assert!(load(&rom(0x00, 1, 1, 16 + 16384 + 8192)).is_ok());
+        // NES 2.0 exponent form, 1 byte
+        let mut one_byte = rom(0x00, 0x00, 0, 16 + 1);
+        one_byte[7] = 0x08;
+        one_byte[9] = 0x0F;
+        assert!(matches!(load(&one_byte),
+            Err(CartridgeError::InvalidSize { what: "PRG ROM", size: 1 })));
+    }
 
+    #[test]
+    fn test_small_prg_rom() {
+        // NES 2.0 header with 8K PRG ROM in exponent form and CHR RAM
+        let mut rom = rom(0x00, 0x34, 0, 16 + 0x2000);
+        rom[9] = 0x0F;
+        for id in 0..=255 {
+            rom[6] = id << 4;
+            rom[7] = 0x08 | id & 0xF0;
+            if let Ok(mut cartridge) = load(&rom) {
+                for addr in 0x4020..=0xFFFF {
+                    cartridge.readb(addr);
+                }
+            }
+        }
     }
 
     #[test]
-    fn test_header_has_trainer() {
-        let header = Header {
-                prg_rom_chunks: 1,
-                chr_rom_chunks: 1,
-                mapper1: (1 << 2),
-                mapper2: 0x00,
-                prg_ram_size: 0x00,
-                tv1: 0x00,
-                tv2: 0x00
-        };
-        assert!(header.has_trainer());
-
-        let header = Header {
-                prg_rom_chunks: 1,
-                chr_rom_chunks: 1,
-                mapper1: 0x00,
-                mapper2: 0x00,
-                prg_ram_size: 0x00,
-                tv1: 0x00,
-                tv2: 0x00
-        };
-        assert!(!header.has_trainer());
+    fn test_trainer() {
+        let mut cartridge = Cartridge::new(Path::new("test_roms/trainer.nes")).unwrap();
+        assert!(cartridge.info().trainer);
+        assert_eq!(cartridge.readb(0x7000), Some(0xA9));
+        assert_eq!(cartridge.readb(0x71FF), Some(0x99));
+        assert_eq!(cartridge.readb(0x6FFF), Some(0x00));
+        // the trainer is back after power cycling
+        cartridge.writeb(0x7000, 0x00);
+        cartridge.power_on();
+        assert_eq!(cartridge.readb(0x7000), Some(0xA9));
+
+        // the fixture starts at $7000 and copies 0x42 and $71FF to ram
+        let mut nes = NES::new();
+        nes.insert_cartridge(cartridge);
+        nes.start();
+        for _ in 0..10 {
+            let _ = nes.clock_instruction();
+        }
+        assert_eq!(nes.bus.readb(0x0000), 0x42);
+        assert_eq!(nes.bus.readb(0x0001), 0x99);
 
-        
+        // the trainer comes before the PRG ROM
+        let mut rom = fs::read("test_roms/trainer.nes").unwrap();
+        rom.truncate(16 + 100);
+        assert!(matches!(Cartridge::from_bytes(&rom), Err(CartridgeError::TruncatedTrainer(100))));
     }
 
     #[test]
-    fn test_cartridge_new() {
-       let path = Path::new("test_roms/nestest.nes");
-       Cartridge::new(&path).unwrap();
+    fn test_prg_ram() {
+        let mut cartridge = load(&rom(0x00, 1, 1, 16 + 16384 + 8192)).unwrap();
+        cartridge.writeb(0x6000, 0x12);
+        cartridge.writeb(0x7FFF, 0x34);
+        assert_eq!(cartridge.readb(0x6000), Some(0x12));
+        assert_eq!(cartridge.readb(0x7FFF), Some(0x34));
+        cartridge.power_on();
+        assert_eq!(cartridge.readb(0x6000), Some(0x00));
+
+        // NES 2.0 with 2K PRG RAM is mirrored
+        let mut bytes = rom(0x00, 1, 1, 16 + 16384 + 8192);
+        bytes[7] = 0x08;
+        bytes[10] = 0x05;
+        let mut cartridge = load(&bytes).unwrap();
+        cartridge.writeb(0x6001, 0x56);
+        assert_eq!(cartridge.readb(0x6801), Some(0x56));
+
+        // no PRG RAM at all: open bus
+        bytes[10] = 0x00;
+        assert_eq!(load(&bytes).unwrap().readb(0x6000), None);
+    }
+
+    #[test]
+    fn test_four_screen() {
+        let mut cartridge = load(&rom(0x08, 1, 1, 16 + 16384 + 8192)).unwrap();
+        assert_eq!(cartridge.get_mirror_mode(), MirrorMode::FourScreen);
+        assert!(cartridge.writeb_ppu(0x2C01, 0x44));
+        assert!(cartridge.writeb_ppu(0x2001, 0x33));
+        assert_eq!(cartridge.readb_ppu(0x2C01), Some(0x44));
+        assert_eq!(cartridge.readb_ppu(0x3001), Some(0x33));
+
+        // without four screen VRAM the nametables are left to the console
+        let mut cartridge = load(&rom(0x00, 1, 0, 16 + 16384)).unwrap();
+        assert!(!cartridge.writeb_ppu(0x2001, 0x33));
+        assert_eq!(cartridge.readb_ppu(0x2001), None);
+        // CHR RAM
+        assert!(cartridge.writeb_ppu(0x1001, 0x55));
+        assert_eq!(cartridge.readb_ppu(0x1001), Some(0x55));
+    }
+
+    #[test]
+    fn test_battery_sav() {
+        let path = env::temp_dir().join("jane_test_battery.nes");
+        let sav_path = env::temp_dir().join("jane_test_battery.sav");
+        let _ = fs::remove_file(&sav_path);
+        fs::write(&path, rom(0x02, 1, 1, 16 + 16384 + 8192)).unwrap();
+
+        let mut cartridge = Cartridge::new(&path).unwrap();
+        // nothing written, nothing saved
+        cartridge.flush_sav().unwrap();
+        assert!(!sav_path.exists());
+        cartridge.writeb(0x6010, 0xAB);
+        cartridge.flush_sav().unwrap();
+        assert_eq!(fs::read(&sav_path).unwrap()[0x10], 0xAB);
+
+        // battery RAM survives power cycles and restarts
+        cartridge.power_on();
+        assert_eq!(cartridge.readb(0x6010), Some(0xAB));
+        let mut cartridge = Cartridge::new(&path).unwrap();
+        assert_eq!(cartridge.readb(0x6010), Some(0xAB));
+
+        // movies start with empty RAM and don't touch the .sav file
+        cartridge.clear_battery_ram();
+        assert_eq!(cartridge.readb(0x6010), Some(0x00));
+        cartridge.writeb(0x6010, 0xCD);
+        cartridge.flush_sav().unwrap();
+        assert_eq!(fs::read(&sav_path).unwrap()[0x10], 0xAB);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&sav_path).unwrap();
+    }
+
+    #[test]
+    fn test_battery_sav_with_trainer() {
+        let path = env::temp_dir().join("jane_test_battery_trainer.nes");
+        let sav_path = env::temp_dir().join("jane_test_battery_trainer.sav");
+        let mut bytes = rom(0x06, 1, 1, 16 + TRAINER_SIZE + 16384 + 8192);
+        bytes[16..16 + TRAINER_SIZE].iter_mut().for_each(|b| *b = 0xEE);
+        fs::write(&path, bytes).unwrap();
+        fs::write(&sav_path, vec![0x11; 0x2000]).unwrap();
+
+        let mut cartridge = Cartridge::new(&path).unwrap();
+        assert_eq!(cartridge.readb(0x6000), Some(0x11));
+        assert_eq!(cartridge.readb(0x7000), Some(0xEE));
+        assert_eq!(cartridge.readb(0x71FF), Some(0xEE));
+        assert_eq!(cartridge.readb(0x7200), Some(0x11));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&sav_path).unwrap();
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // UxROM, 8 banks starting with their number
+        let mut bytes = rom(0x20, 8, 0, 16 + 8 * 16384);
+        for bank in 0..8 {
+            bytes[16 + bank * 16384] = bank as Byte;
+        }
+        bytes[16 + 7 * 16384 + 1] = 0x03;
+        let mut cartridge = load(&bytes).unwrap();
+        // the ROM at $C001 holds 0x03
+        cartridge.writeb(0xC001, 0x07);
+        assert_eq!(cartridge.readb(0x8000), Some(0x03));
+
+        // submapper 1 has no conflicts
+        bytes[7] = 0x08;
+        bytes[8] = 0x10;
+        let mut cartridge = load(&bytes).unwrap();
+        cartridge.writeb(0xC001, 0x06);
+        assert_eq!(cartridge.readb(0x8000), Some(0x06));
+    }
+
+    #[test]
+    fn test_truncated_roms() {
+        // every prefix of a valid rom fails cleanly
+        let nestest = fs::read("test_roms/nestest.nes").unwrap();
+        assert!(Cartridge::from_bytes(&nestest).is_ok());
+        for len in (0..nestest.len()).step_by(997) {
+            assert!(Cartridge::from_bytes(&nestest[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_cartridge_from_archive() {
+        let path = env::temp_dir().join("jane_test_cartridge.nes.gz");
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(&fs::read("test_roms/nestest.nes").unwrap()).unwrap();
+        fs::write(&path, gz.finish().unwrap()).unwrap();
+        let cartridge = Cartridge::new(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cartridge.unwrap().info().prg_rom_size, 16384);
     }
 
 }
\ No newline at end of file
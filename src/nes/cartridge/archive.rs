@@ -0,0 +1,95 @@
+// ROMs are often distributed zipped or gzipped. The archive is unpacked
+// in memory and the rom inside is loaded like a plain .nes file.
+use std::io::prelude::*;
+use std::io::Cursor;
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+use crate::nes::types::*;
+use crate::nes::cartridge::error::CartridgeError;
+use crate::nes::cartridge::MAX_ROM_SIZE;
+use crate::nes::cartridge::header::{HEADER_SIZE,TRAINER_SIZE};
+
+const ZIP_MAGIC: &[Byte; 4] = b"PK\x03\x04";
+const GZIP_MAGIC: &[Byte; 2] = &[0x1F, 0x8B];
+// largest rom the loader takes. Archives that unpack to more are rejected
+// before they fill the memory
+const MAX_UNPACKED_SIZE: u64 = (HEADER_SIZE + TRAINER_SIZE + 2 * MAX_ROM_SIZE) as u64;
+
+// The unpacked rom if bytes are a zip or gzip archive, None otherwise
+pub fn extract(bytes: &[Byte]) -> Result<Option<Vec<Byte>>, CartridgeError> {
+    if bytes.starts_with(ZIP_MAGIC) {
+        extract_zip(bytes).map(Some)
+    } else if bytes.starts_with(GZIP_MAGIC) {
+        read_limited(GzDecoder::new(bytes), MAX_UNPACKED_SIZE).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+// the first .nes file in the archive
+fn extract_zip(bytes: &[Byte]) -> Result<Vec<Byte>, CartridgeError> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| CartridgeError::Archive(e.to_string()))?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)
+            .map_err(|e| CartridgeError::Archive(e.to_string()))?;
+        if file.is_file() && file.name().to_lowercase().ends_with(".nes") {
+            return read_limited(&mut file, MAX_UNPACKED_SIZE)
+        }
+    }
+    Err(CartridgeError::NoRomInArchive)
+}
+
+// Unpack at most limit bytes
+fn read_limited<R: Read>(reader: R, limit: u64) -> Result<Vec<Byte>, CartridgeError> {
+    let mut rom = vec![];
+    reader.take(limit + 1).read_to_end(&mut rom)
+        .map_err(|e| CartridgeError::Archive(e.to_string()))?;
+    if rom.len() as u64 > limit {
+        return Err(CartridgeError::Archive(format!("unpacks to more than {} bytes", limit)))
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    #[test]
+    fn test_extract() {
+        let rom = b"NES\x1A some rom".to_vec();
+        assert_eq!(extract(&rom).unwrap(), None);
+
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(&rom).unwrap();
+        assert_eq!(extract(&gz.finish().unwrap()).unwrap(), Some(rom.clone()));
+
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("readme.txt", FileOptions::default()).unwrap();
+        zip.write_all(b"not a rom").unwrap();
+        zip.start_file("Game (U).NES", FileOptions::default()).unwrap();
+        zip.write_all(&rom).unwrap();
+        zip.start_file("game (E).nes", FileOptions::default()).unwrap();
+        zip.write_all(b"NES\x1A another rom").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        assert_eq!(extract(&zip).unwrap(), Some(rom));
+
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("readme.txt", FileOptions::default()).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        assert!(matches!(extract(&zip), Err(CartridgeError::NoRomInArchive)));
+        assert!(matches!(extract(b"PK\x03\x04 broken"), Err(CartridgeError::Archive(_))));
+
+        // size limit
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(&[0; 1000]).unwrap();
+        let gz = gz.finish().unwrap();
+        assert_eq!(read_limited(GzDecoder::new(&gz[..]), 1000).unwrap().len(), 1000);
+        assert!(matches!(read_limited(GzDecoder::new(&gz[..]), 999), Err(CartridgeError::Archive(_))));
+    }
+}
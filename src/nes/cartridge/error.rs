@@ -0,0 +1,55 @@
+use std::fmt;
+use std::io;
+use std::error;
+use crate::nes::types::*;
+
+// Why a cartridge could not be loaded
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    // the file doesn't start with "NES\x1A"
+    BadMagic([Byte; 4]),
+    // bytes found of the 16 byte header
+    TruncatedHeader(usize),
+    // bytes found of the 512 byte trainer
+    TruncatedTrainer(usize),
+    TruncatedPrgRom { expected: usize, found: usize },
+    TruncatedChrRom { expected: usize, found: usize },
+    UnsupportedMapper { id: u16, name: &'static str },
+    InvalidSize { what: &'static str, size: usize },
+    // a zip or gzip file that can't be unpacked
+    Archive(String),
+    NoRomInArchive,
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{}", e),
+            CartridgeError::BadMagic(magic) =>
+                write!(f, "Not an iNES ROM. Expected the file to start with \"NES\\x1A\", found {:02X?}", magic),
+            CartridgeError::TruncatedHeader(found) =>
+                write!(f, "File is too short for an iNES header ({} of 16 bytes)", found),
+            CartridgeError::TruncatedTrainer(found) =>
+                write!(f, "Trainer is truncated ({} of 512 bytes)", found),
+            CartridgeError::TruncatedPrgRom { expected, found } =>
+                write!(f, "PRG ROM is truncated. The header says {} bytes but only {} are in the file", expected, found),
+            CartridgeError::TruncatedChrRom { expected, found } =>
+                write!(f, "CHR ROM is truncated. The header says {} bytes but only {} are in the file", expected, found),
+            CartridgeError::UnsupportedMapper { id, name } =>
+                write!(f, "Mapper {} ({}) is not supported", id, name),
+            CartridgeError::InvalidSize { what, size } =>
+                write!(f, "Invalid {} size of {} bytes in header", what, size),
+            CartridgeError::Archive(e) => write!(f, "Broken archive: {}", e),
+            CartridgeError::NoRomInArchive => write!(f, "No .nes file in archive"),
+        }
+    }
+}
+
+impl error::Error for CartridgeError { }
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
@@ -0,0 +1,410 @@
+use std::fmt;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::cartridge::error::CartridgeError;
+
+pub const HEADER_SIZE: usize = 16;
+pub const MAGIC: &[Byte; 4] = b"NES\x1A";
+pub const TRAINER_SIZE: usize = 512;
+const PRG_ROM_CHUNK_SIZE: usize = 16384;
+const CHR_ROM_CHUNK_SIZE: usize = 8192;
+// iNES 1.0 files assume 8K PRG RAM if byte 8 is 0
+const DEFAULT_PRG_RAM_SIZE: usize = 8192;
+const DEFAULT_CHR_RAM_SIZE: usize = 8192;
+
+// The 16 byte iNES header. In NES 2.0 files bytes 8-15 have a different
+// meaning, see the comments on the fields.
+#[derive(Debug)]
+pub struct Header {
+    prg_rom_chunks: Byte,  // 16K chunks
+    chr_rom_chunks: Byte,  // 8K chunks
+    mapper1: Byte,
+    mapper2: Byte,
+    prg_ram_size: Byte,  // NES 2.0: mapper msb and submapper
+    tv1: Byte,  // NES 2.0: prg/chr rom size msb
+    tv2: Byte,  // NES 2.0: prg ram/nvram shift counts
+    chr_ram_size: Byte,  // NES 2.0: chr ram/nvram shift counts
+    timing: Byte,
+    system_type: Byte,  // vs system or extended console type
+    misc_roms: Byte,
+    expansion_device: Byte,
+}
+
+// CPU/PPU timing of the console the game is made for
+#[derive(PartialEq,Debug,Copy,Clone)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    // runs on both
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(PartialEq,Debug,Copy,Clone)]
+pub enum ConsoleType {
+    Nes,
+    // ppu: vs system ppu type, hardware: protection/hardware type
+    VsSystem { ppu: Byte, hardware: Byte },
+    Playchoice10,
+    // NES 2.0 extended console type (famiclones, VT0x, ...)
+    Extended(Byte),
+}
+
+// Input device the game expects. Only the ones jane knows are named
+#[derive(PartialEq,Debug,Copy,Clone)]
+pub enum ExpansionDevice {
+    Unspecified,
+    StandardControllers,
+    FourScore,
+    Zapper,
+    PowerPad,
+    Other(Byte),
+}
+
+// Everything the header says about the cartridge. Sizes are in bytes
+#[derive(Debug,Clone)]
+pub struct CartridgeInfo {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: Byte,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    // battery backed ram
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirror: MirrorMode,
+    pub four_screen: bool,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console: ConsoleType,
+    pub expansion_device: ExpansionDevice,
+    // number of rom areas after chr rom
+    pub misc_roms: Byte,
+}
+
+impl Header {
+    // parse the 16 Byte header of the file
+    pub fn new<R: Read + Seek>(f: &mut R) -> Result<Self, CartridgeError> {
+        f.seek(SeekFrom::Start(0))?;
+        let mut bytes = vec![];
+        f.take(HEADER_SIZE as u64).read_to_end(&mut bytes)?;
+        if bytes.len() >= 4 && bytes[0..4] != MAGIC[..] {
+            return Err(CartridgeError::BadMagic([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(CartridgeError::TruncatedHeader(bytes.len()))
+        }
+        let mut header = [0; HEADER_SIZE];
+        header.copy_from_slice(&bytes);
+        Ok(Header::parse(&header))
+    }
+
+    pub fn parse(bytes: &[Byte; HEADER_SIZE]) -> Self {
+        // Byte 0-3 are the "NES\x1A" magic, checked by the caller
+        Header {
+            // Byte 4 and 5 are prg and chr rom sizes
+            prg_rom_chunks: bytes[4],
+            chr_rom_chunks: bytes[5],
+            // Byte 6-15 are various flags
+            mapper1: bytes[6],
+            mapper2: bytes[7],
+            prg_ram_size: bytes[8],
+            tv1: bytes[9],
+            tv2: bytes[10],
+            chr_ram_size: bytes[11],
+            timing: bytes[12],
+            system_type: bytes[13],
+            misc_roms: bytes[14],
+            expansion_device: bytes[15],
+        }
+    }
+
+    // NES 2.0 files have 0b10 in bits 2-3 of byte 7
+    pub fn is_nes2(&self) -> bool {
+        self.mapper2 & 0x0C == 0x08
+    }
+
+    // Old dumps have garbage like "DiskDude!" in bytes 7-15. The upper
+    // mapper nibble of those can't be trusted
+    fn is_archaic(&self) -> bool {
+        !self.is_nes2() && (self.timing | self.system_type | self.misc_roms | self.expansion_device) != 0
+    }
+
+    pub fn has_trainer(&self) -> bool {
+        self.mapper1 & (1 << 2) != 0
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.mapper1 & (1 << 1) != 0
+    }
+
+    pub fn get_mapper_id(&self) -> u16 {
+        let lo = (self.mapper1 >> 4) as u16;
+        if self.is_archaic() {
+            return lo
+        }
+        let mid = (self.mapper2 & 0xF0) as u16;
+        if self.is_nes2() {
+            let hi = (self.prg_ram_size & 0x0F) as u16;
+            return hi << 8 | mid | lo
+        }
+        mid | lo
+    }
+
+    pub fn get_submapper(&self) -> Byte {
+        if self.is_nes2() { self.prg_ram_size >> 4 } else { 0 }
+    }
+
+    pub fn get_mirror_mode(&self) -> MirrorMode {
+        if self.mapper1 & 0x08 != 0 {
+            MirrorMode::FourScreen
+        } else if (self.mapper1 & 0x01) == 0 {
+            MirrorMode::Horizontal
+        } else {
+            MirrorMode::Vertical
+        }
+    }
+
+    pub fn get_prg_rom_size(&self) -> usize {
+        if self.is_nes2() {
+            rom_size(self.prg_rom_chunks, self.tv1 & 0x0F, PRG_ROM_CHUNK_SIZE)
+        } else {
+            self.prg_rom_chunks as usize * PRG_ROM_CHUNK_SIZE
+        }
+    }
+
+    pub fn get_chr_rom_size(&self) -> usize {
+        if self.is_nes2() {
+            rom_size(self.chr_rom_chunks, self.tv1 >> 4, CHR_ROM_CHUNK_SIZE)
+        } else {
+            self.chr_rom_chunks as usize * CHR_ROM_CHUNK_SIZE
+        }
+    }
+
+    fn get_timing(&self) -> Timing {
+        if self.is_nes2() {
+            match self.timing & 0x03 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            }
+        } else if !self.is_archaic() && self.tv1 & 0x01 != 0 {
+            Timing::Pal
+        } else {
+            Timing::Ntsc
+        }
+    }
+
+    fn get_console_type(&self) -> ConsoleType {
+        let system_type = if self.is_archaic() { 0 } else { self.system_type };
+        match self.mapper2 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem { ppu: system_type & 0x0F, hardware: system_type >> 4 },
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(system_type & 0x0F),
+        }
+    }
+
+    fn get_expansion_device(&self) -> ExpansionDevice {
+        if !self.is_nes2() {
+            return ExpansionDevice::Unspecified
+        }
+        match self.expansion_device & 0x3F {
+            0x00 => ExpansionDevice::Unspecified,
+            0x01 => ExpansionDevice::StandardControllers,
+            0x02 => ExpansionDevice::FourScore,
+            0x08 => ExpansionDevice::Zapper,
+            0x0B..=0x0E => ExpansionDevice::PowerPad,
+            id => ExpansionDevice::Other(id),
+        }
+    }
+
+    pub fn info(&self) -> CartridgeInfo {
+        let nes2 = self.is_nes2();
+        let battery = self.has_battery();
+        let chr_rom_size = self.get_chr_rom_size();
+        let (prg_ram_size, prg_nvram_size, chr_ram_size, chr_nvram_size) = if nes2 {
+            (ram_size(self.tv2), ram_size(self.tv2 >> 4), ram_size(self.chr_ram_size), ram_size(self.chr_ram_size >> 4))
+        } else {
+            let prg_ram_size = match self.prg_ram_size {
+                0 => DEFAULT_PRG_RAM_SIZE,
+                chunks => chunks as usize * DEFAULT_PRG_RAM_SIZE,
+            };
+            let chr_ram_size = if chr_rom_size == 0 { DEFAULT_CHR_RAM_SIZE } else { 0 };
+            if battery { (0, prg_ram_size, chr_ram_size, 0) } else { (prg_ram_size, 0, chr_ram_size, 0) }
+        };
+        CartridgeInfo {
+            nes2,
+            mapper: self.get_mapper_id(),
+            submapper: self.get_submapper(),
+            prg_rom_size: self.get_prg_rom_size(),
+            chr_rom_size,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            mirror: self.get_mirror_mode(),
+            four_screen: self.mapper1 & (1 << 3) != 0,
+            battery,
+            trainer: self.has_trainer(),
+            timing: self.get_timing(),
+            console: self.get_console_type(),
+            expansion_device: self.get_expansion_device(),
+            misc_roms: if nes2 { self.misc_roms & 0x03 } else { 0 },
+        }
+    }
+}
+
+impl fmt::Display for CartridgeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} mapper {}", if self.nes2 { "NES 2.0" } else { "iNES" }, self.mapper)?;
+        if self.submapper != 0 {
+            write!(f, ".{}", self.submapper)?;
+        }
+        write!(f, ", PRG ROM {}, CHR ROM {}", size_str(self.prg_rom_size), size_str(self.chr_rom_size))?;
+        for (name, size) in [("PRG RAM", self.prg_ram_size), ("PRG NVRAM", self.prg_nvram_size),
+                             ("CHR RAM", self.chr_ram_size), ("CHR NVRAM", self.chr_nvram_size)].iter() {
+            if *size > 0 {
+                write!(f, ", {} {}", name, size_str(*size))?;
+            }
+        }
+        let mirror = if self.four_screen { "four screen".to_string() } else { format!("{:?}", self.mirror).to_lowercase() };
+        write!(f, ", {} mirroring, {:?}", mirror, self.timing)?;
+        if self.battery {
+            write!(f, ", battery")?;
+        }
+        if self.trainer {
+            write!(f, ", trainer")?;
+        }
+        if self.misc_roms > 0 {
+            write!(f, ", {} misc roms", self.misc_roms)?;
+        }
+        if self.console != ConsoleType::Nes {
+            write!(f, ", console {:?}", self.console)?;
+        }
+        if self.expansion_device != ExpansionDevice::Unspecified {
+            write!(f, ", expects {:?}", self.expansion_device)?;
+        }
+        Ok(())
+    }
+}
+
+fn size_str(size: usize) -> String {
+    if size & 0x3FF == 0 { format!("{}K", size / 1024) } else { format!("{} bytes", size) }
+}
+
+// NES 2.0 rom size from the lsb in the size bytes and the msb nibble in
+// byte 9. An msb of 0xF switches to exponent-multiplier notation:
+// lsb = EEEEEEMM, size = 2^E * (MM*2+1)
+fn rom_size(lsb: Byte, msb: Byte, chunk_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        // sizes that don't fit are nonsense anyway
+        2usize.checked_pow(exponent).and_then(|size| size.checked_mul(multiplier)).unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * chunk_size
+    }
+}
+
+// NES 2.0 ram size from a shift count nibble: 0 = none, else 64 << shift
+fn ram_size(shift: Byte) -> usize {
+    match shift & 0x0F {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flags: &[Byte]) -> Header {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(MAGIC);
+        bytes[4] = 1;
+        bytes[5] = 1;
+        bytes[6..6 + flags.len()].copy_from_slice(flags);
+        Header::parse(&bytes)
+    }
+
+    #[test]
+    fn test_header_get_mapper_id() {
+        assert_eq!(0, header(&[0x00, 0x00]).get_mapper_id());
+        assert_eq!(1, header(&[0x10, 0x00]).get_mapper_id());
+        assert_eq!(255, header(&[0xff, 0xff]).get_mapper_id());
+        // NES 2.0: 12 bit mapper number and submapper
+        let nes2 = header(&[0x50, 0x48, 0x31]);
+        assert!(nes2.is_nes2());
+        assert_eq!(0x145, nes2.get_mapper_id());
+        assert_eq!(3, nes2.get_submapper());
+        // "DiskDude!" garbage in bytes 7-15
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[6] = 0x40;
+        bytes[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(4, Header::parse(&bytes).get_mapper_id());
+    }
+
+    #[test]
+    fn test_header_get_mirror_mode() {
+        assert_eq!(header(&[0x00]).get_mirror_mode(), MirrorMode::Horizontal);
+        assert_eq!(header(&[0x01]).get_mirror_mode(), MirrorMode::Vertical);
+        assert_eq!(header(&[0x09]).get_mirror_mode(), MirrorMode::FourScreen);
+    }
+
+    #[test]
+    fn test_header_has_trainer() {
+        assert!(header(&[1 << 2]).has_trainer());
+        assert!(!header(&[0x00]).has_trainer());
+    }
+
+    #[test]
+    fn test_ines_info() {
+        let info = header(&[0x02, 0x00, 0x00, 0x01]).info();
+        assert!(!info.nes2);
+        assert_eq!(info.prg_rom_size, 16384);
+        assert_eq!(info.chr_rom_size, 8192);
+        // battery backed default ram
+        assert_eq!((info.prg_ram_size, info.prg_nvram_size), (0, 8192));
+        assert_eq!(info.chr_ram_size, 0);
+        assert_eq!(info.timing, Timing::Pal);
+        assert_eq!(info.console, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_nes2_info() {
+        // vs system, 0x201 x 16K prg, 2^5 * 3 bytes chr, 8K ram, 32K nvram,
+        // 8K chr ram, multi region, four score
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[4..16].copy_from_slice(&[0x01, 0x15, 0x08, 0x09, 0x00, 0xF2, 0x97, 0x07, 0x02, 0x32, 0x01, 0x02]);
+        let info = Header::parse(&bytes).info();
+        assert!(info.nes2);
+        assert_eq!(info.console, ConsoleType::VsSystem { ppu: 2, hardware: 3 });
+        assert_eq!(info.prg_rom_size, 0x201 * 16384);
+        assert_eq!(info.chr_rom_size, 96);
+        assert_eq!((info.prg_ram_size, info.prg_nvram_size), (8192, 32768));
+        assert_eq!((info.chr_ram_size, info.chr_nvram_size), (8192, 0));
+        assert_eq!(info.timing, Timing::MultiRegion);
+        assert_eq!(info.misc_roms, 1);
+        assert_eq!(info.expansion_device, ExpansionDevice::FourScore);
+        assert!(info.four_screen);
+        assert!(!info.battery);
+        assert_eq!(info.to_string(), "NES 2.0 mapper 0, PRG ROM 8208K, CHR ROM 96 bytes, PRG RAM 8K, \
+            PRG NVRAM 32K, CHR RAM 8K, four screen mirroring, MultiRegion, 1 misc roms, \
+            console VsSystem { ppu: 2, hardware: 3 }, expects FourScore");
+    }
+
+    #[test]
+    fn test_rom_size() {
+        assert_eq!(rom_size(2, 0, 16384), 32768);
+        assert_eq!(rom_size(0x00, 0x0F, 16384), 1);
+        assert_eq!(rom_size(0x4B, 0x0F, 16384), (1 << 18) * 7);
+        assert_eq!(rom_size(0xFF, 0x0F, 16384), usize::MAX);
+    }
+}
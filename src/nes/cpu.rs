@@ -1,10 +1,13 @@
 use crate::nes::Memory;
 use crate::nes::types::*;
+use crate::nes::irq::IrqLine;
 use instructions::{Instruction,Operation,AddrMode};
-use core::fmt::{Debug,Formatter,Result};
+use cycle::CycleState;
+use core::fmt::{Debug,Display,Formatter,Result};
 use log::{debug};
 
 pub mod instructions;
+mod cycle;
 
 // Base address of the stack in memory
 pub const STACK_BASE_ADDR: Addr = 0x0100;
@@ -13,6 +16,11 @@ pub const STACK_BASE_ADDR: Addr = 0x0100;
 pub const LO: Addr  = 0x00FF;
 pub const HI: Addr  = 0xFF00;
 
+// Default magic constant for the unstable opcodes XAA and LAX #i. The real
+// value depends on the individual chip and its temperature; 0xEE is the
+// value most commonly observed on NES hardware
+pub const DEFAULT_MAGIC: Byte = 0xEE;
+
 // CPU status register flags
 bitflags! {
     pub struct Flags: Byte {
@@ -27,6 +35,28 @@ bitflags! {
     }
 }
 
+// How the CPU spreads the work of an instruction over its cycles
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum ExecMode {
+    // The whole instruction runs on its first cycle. The remaining cycles
+    // of the instruction are idle. Fast, but bus accesses happen too early.
+    Instruction,
+    // Every bus access happens on the cycle it happens on the real
+    // hardware, including dummy reads and writes. See cpu/cycle.rs
+    Cycle,
+}
+
+// Interrupt vectors
+pub const NMI_VECTOR: Addr = 0xFFFA;
+pub const IRQ_VECTOR: Addr = 0xFFFE;
+
+// Interrupts the CPU decided to service before the next instruction
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Interrupt {
+    NMI,
+    IRQ,
+}
+
 // The NES CPU registers
 pub struct Registers {
     pub a: Byte,
@@ -57,12 +87,39 @@ impl Debug for Registers {
     } 
 }
 
+// The CPU executed a KIL opcode and is jammed until the next reset
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Halt {
+    pub pc: Addr,  // address of the KIL opcode
+    pub opcode: Byte,
+}
+
+impl Display for Halt {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "CPU halted by KIL opcode {:#04x} at {:#06x}", self.opcode, self.pc)
+    }
+}
+
 pub struct CPU {
     pub regs: Registers,
     curr_op: Byte,  // current operation
     pub cycles: u64,  // number of clock clycles the CPU is ahead of global clock
     cycles_ahead: u8,
-    stopped: bool, 
+    halt: Option<Halt>,
+    // magic constant used by the unstable opcodes XAA and LAX #i:
+    // result = (A | magic) & X & M. Some test roms expect 0xFF or 0x00.
+    pub magic: Byte,
+    pub mode: ExecMode,
+    // instruction in progress in cycle mode
+    cycle_state: Option<CycleState>,
+    // shared IRQ line. Devices assert it through a clone
+    pub irq: IrqLine,
+    // NMI is edge triggered. The edge is latched until the NMI is serviced
+    nmi_latch: bool,
+    // result of the last interrupt poll
+    pending_interrupt: Option<Interrupt>,
+    // I flag to use for the poll of the current instruction (instruction mode)
+    poll_irq_mask: Option<bool>,
 }
 
 impl CPU {
@@ -72,30 +129,74 @@ impl CPU {
             curr_op: 0x00,
             cycles: 7,
             cycles_ahead: 0,
-            stopped: false,
+            halt: None,
+            magic: DEFAULT_MAGIC,
+            mode: ExecMode::Instruction,
+            cycle_state: None,
+            irq: IrqLine::new(),
+            nmi_latch: false,
+            pending_interrupt: None,
+            poll_irq_mask: None,
         }
     }
 
     pub fn clock<T: Memory>(&mut self, mem: &mut T) {
-        // if processor is halted, we do nothing anymore
-        if self.stopped {
-            panic!("Stopped processor clock'ed!");
+        // a halted processor does nothing until it is reset
+        if self.halt.is_some() {
+            return
         }
 
-        if self.cycles_ahead == 0 {
-            let opcode = self.readb_pc(mem);
-            self.curr_op = opcode;
-            let instruction = Instruction::decode_op(opcode);
-            
-            debug!("{:#06X}  {:02X} {}          A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
-                self.regs.pc-1, opcode, instruction.operation,
-                self.regs.a, self.regs.x, self.regs.y, self.regs.flags, self.regs.sp, self.cycles);
-            self.cycles_ahead += self.run_instruction(mem, instruction);
+        match self.mode {
+            ExecMode::Instruction => {
+                if self.cycles_ahead == 0 {
+                    if let Some(interrupt) = self.pending_interrupt.take() {
+                        self.interrupt(mem, interrupt);
+                    } else {
+                        debug!("{}", self.trace(mem));
+                        let irq_mask = self.is_flag_set(Flags::IRQ);
+                        let opcode = self.readb_pc(mem);
+                        self.curr_op = opcode;
+                        let instruction = Instruction::decode_op(opcode);
+                        self.cycles_ahead += self.run_instruction(mem, instruction);
+
+                        // CLI, SEI and PLP change the I flag after the poll.
+                        // Their effect is delayed by one instruction
+                        self.poll_irq_mask = match instruction.operation {
+                            Operation::CLI | Operation::SEI | Operation::PLP => Some(irq_mask),
+                            _ => Some(self.is_flag_set(Flags::IRQ)),
+                        };
+                    }
+                }
+                self.cycles_ahead -= 1;
+
+                // interrupts are polled on the second to last cycle
+                if self.cycles_ahead <= 1 {
+                    if let Some(irq_mask) = self.poll_irq_mask.take() {
+                        self.poll_interrupts(irq_mask);
+                    }
+                }
+            },
+            ExecMode::Cycle => self.clock_cycle(mem),
         }
-        self.cycles_ahead -= 1;
         self.cycles += 1
     }
 
+    // The CPU is halted by a DMA for one cycle
+    pub fn stall(&mut self) {
+        self.cycles += 1;
+    }
+
+    // Formats the instruction at pc together with the current register
+    // state in a nestest.log like fashion. Must be called before the
+    // instruction is run.
+    pub fn trace<T: Memory>(&self, mem: &T) -> String {
+        let opcode = mem.peekb(self.regs.pc);
+        let instruction = Instruction::decode_op(opcode);
+        format!("{:04X}  {:02X} {}          A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.regs.pc, opcode, instruction.operation,
+            self.regs.a, self.regs.x, self.regs.y, self.regs.flags, self.regs.sp, self.cycles)
+    }
+
     // sets PC 
     pub fn find_pc_addr<T: Memory>(&mut self, mem: &T) {
         // 0xfffc and 0xfffc+1 stores the location of the first op code (where
@@ -121,46 +222,85 @@ impl CPU {
 
         // reset internal variables
         self.curr_op = 0x00;
+        self.cycles_ahead = 0;
+        self.cycle_state = None;
+        self.nmi_latch = false;
+        self.pending_interrupt = None;
+        self.poll_irq_mask = None;
+        self.halt = None;
     }
 
-    fn interrupt<T: Memory>(&mut self, mem: &mut T, pc_addr: Addr) {
+    // Run the whole interrupt sequence at once (instruction mode). Takes
+    // 7 cycles.
+    fn interrupt<T: Memory>(&mut self, mem: &mut T, interrupt: Interrupt) {
+        debug!("{:?} at pc {:#06x}", interrupt, self.regs.pc);
+
         // push pc to stack
         self.pushb_sp(mem, (self.regs.pc >> 8) as Byte);
         self.pushb_sp(mem, self.regs.pc as Byte);
 
-        // push status reg with set interrupt flags to stack
+        // push status reg to stack, then disable interrupts
         self.set_flag(Flags::BREAK, false);
         self.set_flag(Flags::UNUSED, true);
-        self.set_flag(Flags::IRQ, true);
         self.pushb_sp(mem, self.regs.flags.bits());
+        self.set_flag(Flags::IRQ, true);
 
         // read new pc
+        let pc_addr = self.interrupt_vector();
         let lo = mem.readb(pc_addr);
         let hi = mem.readb(pc_addr + 1);
         self.regs.pc = ((hi as Addr) << 8) | lo as Addr;
+        self.cycles_ahead = 7;
+    }
+
+    // Vector of the interrupt sequence (IRQ, BRK or NMI) that fetches its
+    // vector now. A latched NMI hijacks the sequence of IRQ and BRK.
+    fn interrupt_vector(&mut self) -> Addr {
+        if self.nmi_latch {
+            self.nmi_latch = false;
+            NMI_VECTOR
+        } else {
+            IRQ_VECTOR
+        }
+    }
+
+    // Non maskable interrupt: Signals the falling edge of the NMI line.
+    // The interrupt is serviced after the current instruction.
+    pub fn nmi(&mut self) {
+        self.nmi_latch = true;
     }
 
-    // Non maskable interrupt
-    pub fn nmi<T: Memory>(&mut self, mem: &mut T) {
-        let new_pc_addr = 0xFFFA;
-        self.interrupt(mem, new_pc_addr);
-        self.cycles_ahead = 8;
+    // Decide if an interrupt is serviced after the current instruction.
+    // The IRQ line is level triggered and masked by the I flag.
+    fn poll_interrupts(&mut self, irq_mask: bool) {
+        self.pending_interrupt = if self.nmi_latch {
+            Some(Interrupt::NMI)
+        } else if self.irq.is_asserted() && !irq_mask {
+            Some(Interrupt::IRQ)
+        } else {
+            None
+        };
+    }
+
+    // Interrupt that will be serviced after the current instruction
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        self.pending_interrupt
     }
 
-    // Interrupt request: Same as nmi, but only takes place if interrupts are
-    // allowed
-    pub fn irq<T: Memory>(&mut self, mem: &mut T) {
-        if self.is_flag_set(Flags::IRQ) {
-            let new_pc_addr = 0xFFFE;
-            self.interrupt(mem, new_pc_addr);
-            self.cycles_ahead = 7;
-        }; 
+    // Some if the CPU is jammed by a KIL opcode
+    pub fn halted(&self) -> Option<Halt> {
+        self.halt
+    }
+
+    // An NMI edge was seen but not yet serviced
+    pub fn is_nmi_latched(&self) -> bool {
+        self.nmi_latch
     }
 
 
     // True if the operation is not finished yet
     pub fn is_ahead(&self) -> bool {
-        return self.cycles_ahead > 0;
+        return self.cycles_ahead > 0 || self.cycle_state.is_some();
     }
 
     // read the next opcode and increment pc
@@ -221,6 +361,30 @@ impl CPU {
         self.regs.pc = addr;
     }
 
+    // Shared store of AHX, SHX, SHY and TAS. The value written is
+    // and'ed with the high byte of the base address + 1 (H+1). If indexing
+    // crossed a page, the high byte of the target address is replaced by
+    // the written value as well.
+    fn store_and_high<T: Memory>(&mut self, mem: &mut T, addr: Addr, index: Byte, val: Byte) {
+        let base = addr.wrapping_sub(index as Addr);
+        let val = val & ((base >> 8) as Byte).wrapping_add(1);
+        let addr = if base & HI != addr & HI {
+            (val as Addr) << 8 | (addr & LO)
+        } else {
+            addr
+        };
+        mem.writeb(addr, val);
+    }
+
+    // index register used by the addressing mode of the current operation
+    fn index_reg(&self) -> Byte {
+        match Instruction::decode_op(self.curr_op).addr_mode {
+            AddrMode::ABX | AddrMode::ZPX | AddrMode::IZX => self.regs.x,
+            AddrMode::ABY | AddrMode::ZPY | AddrMode::IZY => self.regs.y,
+            _ => 0,
+        }
+    }
+
     fn run_instruction<T: Memory>(&mut self, mem: &mut T, i: &Instruction) -> u8 {
         let (value, page_cross) = match &i.addr_mode {
             AddrMode::IMP => self.am_IMP(),
@@ -237,10 +401,28 @@ impl CPU {
             AddrMode::IZY => self.am_IZY(mem),
         };
 
-        let extra_cycle_on_page_cross = match i.operation {
+        let extra_cycle_on_page_cross = self.execute(mem, i, value);
+
+        if page_cross && extra_cycle_on_page_cross {
+            i.cycles[0] + i.cycles[1]
+        } else {
+            i.cycles[0]
+        }
+    }
+
+    // Run the operation of the instruction on the already resolved
+    // address. Returns true if the operation takes an extra cycle on
+    // page cross.
+    fn execute<T: Memory>(&mut self, mem: &mut T, i: &Instruction, value: Addr) -> bool {
+        match i.operation {
             Operation::ADC => self.op_ADC(mem, value),
+            Operation::AHX => self.op_AHX(mem, value),
+            Operation::ALR => self.op_ALR(mem, value),
+            Operation::ANC => self.op_ANC(mem, value),
             Operation::AND => self.op_AND(mem, value),
+            Operation::ARR => self.op_ARR(mem, value),
             Operation::ASL => self.op_ASL(mem, value),
+            Operation::AXS => self.op_AXS(mem, value),
             Operation::BCC => self.op_BCC(value),
             Operation::BCS => self.op_BCS(value),
             Operation::BEQ => self.op_BEQ(value),
@@ -270,6 +452,7 @@ impl CPU {
             Operation::JMP => self.op_JMP(value),
             Operation::JSR => self.op_JSR(mem, value),
             Operation::KIL => self.op_KIL(),
+            Operation::LAS => self.op_LAS(mem, value),
             Operation::LAX => self.op_LAX(mem, value),
             Operation::LDA => self.op_LDA(mem, value),
             Operation::LDX => self.op_LDX(mem, value),
@@ -292,28 +475,21 @@ impl CPU {
             Operation::SEC => self.op_SEC(),
             Operation::SED => self.op_SED(),
             Operation::SEI => self.op_SEI(),
+            Operation::SHX => self.op_SHX(mem, value),
+            Operation::SHY => self.op_SHY(mem, value),
             Operation::SLO => self.op_SLO(mem, value),
             Operation::SRE => self.op_SRE(mem, value),
             Operation::STA => self.op_STA(mem, value),
             Operation::STX => self.op_STX(mem, value),
             Operation::STY => self.op_STY(mem, value),
+            Operation::TAS => self.op_TAS(mem, value),
             Operation::TAX => self.op_TAX(),
             Operation::TAY => self.op_TAY(),
             Operation::TSX => self.op_TSX(),
             Operation::TXA => self.op_TXA(),
             Operation::TXS => self.op_TXS(),
             Operation::TYA => self.op_TYA(),
-            _ => { 
-                // OP not implemented. do nothing
-                warn!("Operation not implemented: {:?}", i.operation);
-                false
-            }
-        };
-
-        if page_cross && extra_cycle_on_page_cross {
-            i.cycles[0] + i.cycles[1]
-        } else {
-            i.cycles[0]
+            Operation::XAA => self.op_XAA(mem, value),
         }
     }
 
@@ -468,6 +644,34 @@ impl CPU {
         true
     }
 
+    // Unofficial: AHX (SHA) - Store A & X & H+1
+    // Unstable on real hardware. See store_and_high
+    fn op_AHX<T: Memory>(&mut self, mem: &mut T, addr: Addr) -> bool {
+        let val = self.regs.a & self.regs.x;
+        let index = self.index_reg();
+        self.store_and_high(mem, addr, index, val);
+        false
+    }
+
+    // Unofficial: ALR (ASR) - AND #i, then LSR A
+    // A,C,Z,N = (A&M)/2
+    fn op_ALR<T: Memory>(&mut self, mem: &T, addr: Addr) -> bool {
+        let val = self.regs.a & mem.readb(addr);
+        self.set_flag(Flags::CARRY, (val & 0b00000001) != 0);
+        self.regs.a = val >> 1;
+        self.set_flag_nz(self.regs.a);
+        false
+    }
+
+    // Unofficial: ANC - AND #i, then copy N to C
+    // A,Z,N = A&M, C = N
+    fn op_ANC<T: Memory>(&mut self, mem: &T, addr: Addr) -> bool {
+        self.regs.a &= mem.readb(addr);
+        self.set_flag_nz(self.regs.a);
+        self.set_flag(Flags::CARRY, self.is_flag_set(Flags::NEGATIVE));
+        false
+    }
+
     // AND - Logical AND
     // A,Z,N = A&M
     // A logical AND is performed, bit by bit, on the accumulator contents
@@ -481,6 +685,20 @@ impl CPU {
         true
     }
 
+    // Unofficial: ARR - AND #i, then ROR A
+    // Flags differ from ROR: C is bit 6 of the result and V is bit 6 xor
+    // bit 5 of the result. Decimal mode does not exist on the NES.
+    fn op_ARR<T: Memory>(&mut self, mem: &T, addr: Addr) -> bool {
+        let val = self.regs.a & mem.readb(addr);
+        let rotated = (val >> 1) | (self.get_flag(Flags::CARRY) << 7);
+        self.regs.a = rotated;
+
+        self.set_flag_nz(rotated);
+        self.set_flag(Flags::CARRY, (rotated & 0b01000000) != 0);
+        self.set_flag(Flags::OVERFLOW, ((rotated >> 6) ^ (rotated >> 5)) & 0x01 != 0);
+        false
+    }
+
     // ASL - Arithmetic Shift Left
     // A,Z,C,N = M*2 or M,Z,C,N = M*2
     // This operation shifts all the bits of the accumulator or memory
@@ -511,6 +729,16 @@ impl CPU {
         false
     }
 
+    // Unofficial: AXS (SBX) - X = A&X - M
+    // Subtracts without borrow. Carry is set like in CMP
+    fn op_AXS<T: Memory>(&mut self, mem: &T, addr: Addr) -> bool {
+        let val = mem.readb(addr);
+        let ax = self.regs.a & self.regs.x;
+        self.set_flag(Flags::CARRY, ax >= val);
+        self.regs.x = ax.wrapping_sub(val);
+        self.set_flag_nz(self.regs.x);
+        false
+    }
 
     // BCC - Branch if Carry Clear
     // If the carry flag is clear then add the relative displacement to
@@ -603,19 +831,20 @@ impl CPU {
     // the break flag in the status set to one.
     fn op_BRK<T: Memory>(&mut self, mem: &mut T) -> bool {
         self.regs.pc += 1;
-        self.set_flag(Flags::IRQ, true);
 
         // Push pc to stack
         self.pushb_sp(mem, (self.regs.pc >> 8) as Byte);
         self.pushb_sp(mem, self.regs.pc as Byte);
         
-        // Push flags to stack
+        // Push flags to stack. Interrupts are disabled after the push
         self.set_flag(Flags::BREAK, true);
         self.pushb_sp(mem, self.regs.flags.bits());
         self.set_flag(Flags::BREAK, false);
+        self.set_flag(Flags::IRQ, true);
 
         // set PC to IRQ vector
-        self.regs.pc = mem.readw(0xFFFE);
+        let vector = self.interrupt_vector();
+        self.regs.pc = mem.readw(vector);
         false 
     }
 
@@ -812,20 +1041,43 @@ impl CPU {
 
     // Unofficial: KIL halts the processor
     fn op_KIL(&mut self) -> bool {
-        self.stopped = true;
-        info!("Processor received KIL instruction.");
+        let halt = Halt { pc: self.regs.pc.wrapping_sub(1), opcode: self.curr_op };
+        self.halt = Some(halt);
+        info!("{}", halt);
 
         false
     }
 
+    // Unofficial: LAS - A,X,SP = M & SP
+    fn op_LAS<T: Memory>(&mut self, mem: &T, addr: Word) -> bool {
+        let val = mem.readb(addr) & self.regs.sp;
+        self.regs.a = val;
+        self.regs.x = val;
+        self.regs.sp = val;
+        self.set_flag_nz(val);
+        true
+    }
+
     // Unofficial op code! Shortcut for LDA, TAX
+    // The immediate version (LXA) is unstable: A,X = (A | magic) & M
     fn op_LAX<T: Memory>(&mut self, mem: &T, addr: Word) -> bool {
-        self.op_LDA(mem, addr);
-        self.op_TAX();
-
         match Instruction::decode_op(self.curr_op).addr_mode {
-            AddrMode::IZY | AddrMode::ABY => true,
-            _ => false,
+            AddrMode::IMM => {
+                let val = (self.regs.a | self.magic) & mem.readb(addr);
+                self.regs.a = val;
+                self.op_TAX();
+                false
+            },
+            AddrMode::IZY | AddrMode::ABY => {
+                self.op_LDA(mem, addr);
+                self.op_TAX();
+                true
+            },
+            _ => {
+                self.op_LDA(mem, addr);
+                self.op_TAX();
+                false
+            }
         }
     }
 
@@ -927,7 +1179,9 @@ impl CPU {
 
         // Im not sure why this is set to false and stack value is not used
         // but that's how the nestest.log shows it..
+        // The unused bit does not exist in hardware and always reads as 1
         self.set_flag(Flags::BREAK, false);  
+        self.set_flag(Flags::UNUSED, true);
         false
     }
 
@@ -1002,6 +1256,7 @@ impl CPU {
     fn op_RTI<T: Memory>(&mut self, mem: &T) -> bool {
         self.regs.flags = Flags::from_bits(self.popb_sp(mem)).unwrap();
         self.regs.flags &= !Flags::BREAK;
+        self.regs.flags |= Flags::UNUSED;
         
         let pc_lo = self.popb_sp(mem) as Word;
         let pc_hi = self.popb_sp(mem) as Word;
@@ -1079,6 +1334,22 @@ impl CPU {
         false
     }
 
+    // Unofficial: SHX (SXA) - Store X & H+1
+    // Unstable on real hardware. See store_and_high
+    fn op_SHX<T: Memory>(&mut self, mem: &mut T, addr: Addr) -> bool {
+        let (val, index) = (self.regs.x, self.regs.y);
+        self.store_and_high(mem, addr, index, val);
+        false
+    }
+
+    // Unofficial: SHY (SYA) - Store Y & H+1
+    // Unstable on real hardware. See store_and_high
+    fn op_SHY<T: Memory>(&mut self, mem: &mut T, addr: Addr) -> bool {
+        let (val, index) = (self.regs.y, self.regs.x);
+        self.store_and_high(mem, addr, index, val);
+        false
+    }
+
     // Unofficial: ASL + ORA
     fn op_SLO<T: Memory>(&mut self, mem: &mut T, addr: Word) -> bool {
         self.op_ASL(mem, addr);
@@ -1111,6 +1382,15 @@ impl CPU {
         false
     }
 
+    // Unofficial: TAS (XAS) - SP = A & X, then store SP & H+1
+    // Unstable on real hardware. See store_and_high
+    fn op_TAS<T: Memory>(&mut self, mem: &mut T, addr: Addr) -> bool {
+        self.regs.sp = self.regs.a & self.regs.x;
+        let (val, index) = (self.regs.sp, self.regs.y);
+        self.store_and_high(mem, addr, index, val);
+        false
+    }
+
     // a to x
     fn op_TAX(&mut self) -> bool {
         self.regs.x = self.regs.a;
@@ -1151,6 +1431,15 @@ impl CPU {
         self.regs.sp = self.regs.x;
         false
     } 
+
+    // Unofficial: XAA (ANE) - A = (A | magic) & X & M
+    // Highly unstable on real hardware. The magic constant is configurable
+    // with CPU::magic
+    fn op_XAA<T: Memory>(&mut self, mem: &T, addr: Addr) -> bool {
+        self.regs.a = (self.regs.a | self.magic) & self.regs.x & mem.readb(addr);
+        self.set_flag_nz(self.regs.a);
+        false
+    }
 }
 
 impl Debug for CPU {
@@ -1161,3 +1450,395 @@ impl Debug for CPU {
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::irq::IrqSource;
+
+    // Flat 64kb memory without any mapping
+    struct TestMemory {
+        data: Vec<Byte>,
+    }
+
+    impl TestMemory {
+        fn new() -> Self {
+            TestMemory { data: vec![0; 0x10000] }
+        }
+    }
+
+    impl Memory for TestMemory {
+        fn readb(&self, addr: Addr) -> Byte {
+            self.data[addr as usize]
+        }
+
+        fn writeb(&mut self, addr: Addr, data: Byte) {
+            self.data[addr as usize] = data;
+        }
+    }
+
+    // Places the given program at 0x8000 and runs its first instruction.
+    // Returns the number of cycles the instruction took.
+    fn run(cpu: &mut CPU, mem: &mut TestMemory, program: &[Byte]) -> u8 {
+        for (i, byte) in program.iter().enumerate() {
+            mem.data[0x8000 + i] = *byte;
+        }
+        cpu.regs.pc = 0x8000;
+        step(cpu, mem)
+    }
+
+    // Runs the next instruction or interrupt sequence. Returns the number of
+    // cycles it took
+    fn step(cpu: &mut CPU, mem: &mut TestMemory) -> u8 {
+        cpu.clock(mem);
+        let mut cycles = 1;
+        while cpu.is_ahead() {
+            cpu.clock(mem);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    // CPU at 0x8000 with the interrupt vectors pointing to 0x9000 (IRQ)
+    // and 0xA000 (NMI)
+    fn interrupt_setup(mode: ExecMode, program: &[Byte]) -> (CPU, TestMemory) {
+        let mut cpu = CPU::new();
+        cpu.mode = mode;
+        cpu.regs.pc = 0x8000;
+        let mut mem = TestMemory::new();
+        for (i, byte) in program.iter().enumerate() {
+            mem.data[0x8000 + i] = *byte;
+        }
+        mem.writew(IRQ_VECTOR, 0x9000);
+        mem.writew(NMI_VECTOR, 0xA000);
+        (cpu, mem)
+    }
+
+    const MODES: [ExecMode; 2] = [ExecMode::Instruction, ExecMode::Cycle];
+
+    #[test]
+    fn test_alr() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        cpu.regs.a = 0b1100_0011;
+        run(&mut cpu, &mut mem, &[0x4B, 0b1000_0001]);
+        assert_eq!(cpu.regs.a, 0b0100_0000);
+        assert!(cpu.is_flag_set(Flags::CARRY));
+        assert!(!cpu.is_flag_set(Flags::NEGATIVE));
+        assert!(!cpu.is_flag_set(Flags::ZERO));
+    }
+
+    #[test]
+    fn test_anc() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        cpu.regs.a = 0xF0;
+        run(&mut cpu, &mut mem, &[0x0B, 0x80]);
+        assert_eq!(cpu.regs.a, 0x80);
+        assert!(cpu.is_flag_set(Flags::NEGATIVE));
+        assert!(cpu.is_flag_set(Flags::CARRY));
+
+        cpu.regs.a = 0xF0;
+        run(&mut cpu, &mut mem, &[0x2B, 0x0F]);
+        assert_eq!(cpu.regs.a, 0x00);
+        assert!(cpu.is_flag_set(Flags::ZERO));
+        assert!(!cpu.is_flag_set(Flags::CARRY));
+    }
+
+    #[test]
+    fn test_arr() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+
+        // carry is rotated in, bit 6 => C, bit 6 ^ bit 5 => V
+        cpu.regs.a = 0xFF;
+        cpu.set_flag(Flags::CARRY, true);
+        run(&mut cpu, &mut mem, &[0x6B, 0xC0]);
+        assert_eq!(cpu.regs.a, 0xE0);
+        assert!(cpu.is_flag_set(Flags::CARRY));
+        assert!(!cpu.is_flag_set(Flags::OVERFLOW));
+        assert!(cpu.is_flag_set(Flags::NEGATIVE));
+
+        cpu.regs.a = 0xFF;
+        cpu.set_flag(Flags::CARRY, false);
+        run(&mut cpu, &mut mem, &[0x6B, 0x80]);
+        assert_eq!(cpu.regs.a, 0x40);
+        assert!(cpu.is_flag_set(Flags::CARRY));
+        assert!(cpu.is_flag_set(Flags::OVERFLOW));
+        assert!(!cpu.is_flag_set(Flags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_axs() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        cpu.regs.a = 0x0F;
+        cpu.regs.x = 0x3C;
+        let cycles = run(&mut cpu, &mut mem, &[0xCB, 0x02]);
+        assert_eq!(cycles, 2);
+        assert_eq!(cpu.regs.x, 0x0A);
+        assert!(cpu.is_flag_set(Flags::CARRY));
+
+        // no borrow is used, result wraps and carry is cleared
+        cpu.regs.a = 0x01;
+        cpu.regs.x = 0x01;
+        cpu.set_flag(Flags::CARRY, false);
+        run(&mut cpu, &mut mem, &[0xCB, 0x02]);
+        assert_eq!(cpu.regs.x, 0xFF);
+        assert!(!cpu.is_flag_set(Flags::CARRY));
+        assert!(cpu.is_flag_set(Flags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_ahx() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        cpu.regs.a = 0xFF;
+        cpu.regs.x = 0xF3;
+        cpu.regs.y = 0x01;
+        run(&mut cpu, &mut mem, &[0x9F, 0x00, 0x02]);
+        assert_eq!(mem.readb(0x0201), 0xF3 & 0x03);
+
+        // indirect indexed
+        mem.writeb(0x0010, 0x00);
+        mem.writeb(0x0011, 0x06);
+        run(&mut cpu, &mut mem, &[0x93, 0x10]);
+        assert_eq!(mem.readb(0x0601), 0xF3 & 0x07);
+    }
+
+    #[test]
+    fn test_shx_shy_page_cross() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+
+        // no page cross: X & (H+1)
+        cpu.regs.x = 0xFF;
+        cpu.regs.y = 0x10;
+        run(&mut cpu, &mut mem, &[0x9E, 0x00, 0x04]);
+        assert_eq!(mem.readb(0x0410), 0x05);
+
+        // page cross: the high byte of the target is replaced by the value
+        cpu.regs.x = 0x01;
+        cpu.regs.y = 0x10;
+        run(&mut cpu, &mut mem, &[0x9E, 0xF8, 0x02]);
+        assert_eq!(mem.readb(0x0108), 0x01);
+        assert_eq!(mem.readb(0x0308), 0x00);
+
+        // SHY is SHX with X and Y swapped
+        cpu.regs.x = 0x10;
+        cpu.regs.y = 0xFF;
+        run(&mut cpu, &mut mem, &[0x9C, 0x00, 0x04]);
+        assert_eq!(mem.readb(0x0410), 0x05);
+    }
+
+    #[test]
+    fn test_tas() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        cpu.regs.a = 0xF7;
+        cpu.regs.x = 0x7F;
+        cpu.regs.y = 0x01;
+        run(&mut cpu, &mut mem, &[0x9B, 0x00, 0x03]);
+        assert_eq!(cpu.regs.sp, 0x77);
+        assert_eq!(mem.readb(0x0301), 0x77 & 0x04);
+    }
+
+    #[test]
+    fn test_las() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        cpu.regs.sp = 0xF0;
+        cpu.regs.y = 0x01;
+        mem.writeb(0x0301, 0x9F);
+        let cycles = run(&mut cpu, &mut mem, &[0xBB, 0x00, 0x03]);
+        assert_eq!(cycles, 4);
+        assert_eq!(cpu.regs.a, 0x90);
+        assert_eq!(cpu.regs.x, 0x90);
+        assert_eq!(cpu.regs.sp, 0x90);
+        assert!(cpu.is_flag_set(Flags::NEGATIVE));
+
+        // extra cycle on page cross
+        cpu.regs.y = 0xFF;
+        let cycles = run(&mut cpu, &mut mem, &[0xBB, 0x02, 0x03]);
+        assert_eq!(cycles, 5);
+    }
+
+    #[test]
+    fn test_xaa_magic() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        assert_eq!(cpu.magic, DEFAULT_MAGIC);
+
+        cpu.regs.a = 0x00;
+        cpu.regs.x = 0xFF;
+        run(&mut cpu, &mut mem, &[0x8B, 0xFF]);
+        assert_eq!(cpu.regs.a, 0xEE);
+
+        cpu.magic = 0xFF;
+        cpu.regs.a = 0x00;
+        cpu.regs.x = 0x0F;
+        run(&mut cpu, &mut mem, &[0x8B, 0x3C]);
+        assert_eq!(cpu.regs.a, 0x0C);
+
+        cpu.magic = 0x00;
+        cpu.regs.a = 0x00;
+        run(&mut cpu, &mut mem, &[0x8B, 0xFF]);
+        assert_eq!(cpu.regs.a, 0x00);
+        assert!(cpu.is_flag_set(Flags::ZERO));
+    }
+
+    #[test]
+    fn test_lax_immediate_magic() {
+        let mut cpu = CPU::new();
+        let mut mem = TestMemory::new();
+        cpu.regs.a = 0x01;
+        run(&mut cpu, &mut mem, &[0xAB, 0x0F]);
+        assert_eq!(cpu.regs.a, 0x0F);
+        assert_eq!(cpu.regs.x, 0x0F);
+
+        cpu.magic = 0x00;
+        cpu.regs.a = 0x01;
+        run(&mut cpu, &mut mem, &[0xAB, 0x0F]);
+        assert_eq!(cpu.regs.a, 0x01);
+        assert_eq!(cpu.regs.x, 0x01);
+    }
+
+    #[test]
+    fn test_irq_line() {
+        for &mode in MODES.iter() {
+            // masked
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0xEA, 0xEA]);
+            cpu.irq.assert(IrqSource::MAPPER);
+            step(&mut cpu, &mut mem);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x8002, "{:?}", mode);
+
+            // not masked
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0xEA, 0xEA]);
+            cpu.set_flag(Flags::IRQ, false);
+            cpu.irq.assert(IrqSource::MAPPER);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.pending_interrupt(), Some(Interrupt::IRQ));
+            assert_eq!(step(&mut cpu, &mut mem), 7, "{:?}", mode);
+            assert_eq!(cpu.regs.pc, 0x9000, "{:?}", mode);
+            assert!(cpu.is_flag_set(Flags::IRQ));
+            // return address and flags without B on the stack
+            assert_eq!(mem.readw(0x01FC), 0x8001);
+            assert_eq!(mem.readb(0x01FB), 0x20);
+
+            // line is level triggered: released before the poll, no irq
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0xEA, 0xEA]);
+            cpu.set_flag(Flags::IRQ, false);
+            cpu.irq.assert(IrqSource::DMC);
+            cpu.irq.release(IrqSource::DMC);
+            step(&mut cpu, &mut mem);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x8002, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_irq_cli_sei_delay() {
+        for &mode in MODES.iter() {
+            // CLI: the irq is taken after the following instruction
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0x58, 0xEA, 0xEA]);
+            cpu.irq.assert(IrqSource::FRAME_COUNTER);
+            step(&mut cpu, &mut mem);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x8002, "{:?}", mode);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x9000, "{:?}", mode);
+
+            // SEI: the irq is still taken right after SEI
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0x78, 0xEA]);
+            cpu.set_flag(Flags::IRQ, false);
+            cpu.irq.assert(IrqSource::FRAME_COUNTER);
+            step(&mut cpu, &mut mem);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x9000, "{:?}", mode);
+            // pushed flags have I set by SEI
+            assert_eq!(mem.readb(0x01FB), 0x24);
+
+            // PLP clearing I is delayed like CLI
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0x28, 0xEA, 0xEA]);
+            mem.writeb(0x01FE, 0x20);
+            cpu.irq.assert(IrqSource::FRAME_COUNTER);
+            step(&mut cpu, &mut mem);
+            assert!(!cpu.is_flag_set(Flags::IRQ));
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x8002, "{:?}", mode);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x9000, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_nmi() {
+        for &mode in MODES.iter() {
+            // NMI is not masked by I and wins over IRQ
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0xEA, 0xEA]);
+            cpu.irq.assert(IrqSource::MAPPER);
+            cpu.set_flag(Flags::IRQ, false);
+            cpu.nmi();
+            step(&mut cpu, &mut mem);
+            assert_eq!(step(&mut cpu, &mut mem), 7);
+            assert_eq!(cpu.regs.pc, 0xA000, "{:?}", mode);
+
+            // the handler runs at least one instruction before the IRQ
+            mem.writeb(0xA000, 0xEA);
+            cpu.set_flag(Flags::IRQ, false);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0xA001, "{:?}", mode);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x9000, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        // NMI during BRK: the BRK sequence continues with the NMI vector
+        // and the pushed flags have B set
+        let (mut cpu, mut mem) = interrupt_setup(ExecMode::Cycle, &[0x00, 0x00]);
+        cpu.clock(&mut mem);
+        cpu.clock(&mut mem);
+        cpu.nmi();
+        while cpu.is_ahead() {
+            cpu.clock(&mut mem);
+        }
+        assert_eq!(cpu.regs.pc, 0xA000);
+        assert_eq!(mem.readw(0x01FC), 0x8002);
+        assert_eq!(mem.readb(0x01FB) & Flags::BREAK.bits(), Flags::BREAK.bits());
+        assert_eq!(cpu.pending_interrupt(), None);
+
+        // BRK without NMI
+        let (mut cpu, mut mem) = interrupt_setup(ExecMode::Cycle, &[0x00, 0x00]);
+        step(&mut cpu, &mut mem);
+        assert_eq!(cpu.regs.pc, 0x9000);
+    }
+
+    #[test]
+    fn test_kil_halts() {
+        for &mode in MODES.iter() {
+            let (mut cpu, mut mem) = interrupt_setup(mode, &[0xEA, 0x12, 0xEA]);
+            mem.writew(0xFFFC, 0x8000);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.halted(), None);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.halted(), Some(Halt { pc: 0x8001, opcode: 0x12 }), "{:?}", mode);
+
+            // a halted cpu ignores clocks and interrupts
+            let cycles = cpu.cycles;
+            cpu.nmi();
+            for _ in 0..10 {
+                cpu.clock(&mut mem);
+            }
+            assert_eq!(cpu.regs.pc, 0x8002);
+            assert_eq!(cpu.cycles, cycles);
+
+            // until it is reset
+            cpu.reset(&mem);
+            assert_eq!(cpu.halted(), None);
+            step(&mut cpu, &mut mem);
+            assert_eq!(cpu.regs.pc, 0x8001);
+        }
+    }
+}
@@ -0,0 +1,700 @@
+// Cycle stepped execution of instructions (ExecMode::Cycle).
+//
+// Each call of clock_cycle performs exactly the one bus access the 6502
+// does on that cycle, including the dummy reads of indexed addressing and
+// implied instructions and the double write of read-modify-write
+// instructions. The access patterns follow http://nesdev.com/6502_cpu.txt
+//
+// The operations themselves are not duplicated: once the operand is on the
+// data latch, the regular op_ handlers run on a Latch memory that hands them
+// the value and captures their write.
+//
+// Interrupts are polled at the end of every cycle but the last of an
+// instruction, so the poll of the second to last cycle decides. Changes of
+// the I flag by CLI, SEI and PLP happen on their last cycle and are
+// therefore seen one instruction late, just like on the hardware.
+use crate::nes::Memory;
+use crate::nes::types::*;
+use super::{CPU, Flags, STACK_BASE_ADDR, LO, HI};
+use super::instructions::{Instruction, Operation, AddrMode};
+
+// The memory access of an instruction with a memory operand
+#[derive(Debug,Copy,Clone,PartialEq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+impl Access {
+    fn of(i: &Instruction) -> Access {
+        match i.operation {
+            Operation::STA | Operation::STX | Operation::STY | Operation::SAX |
+            Operation::AHX | Operation::SHX | Operation::SHY | Operation::TAS => Access::Write,
+            Operation::ASL | Operation::LSR | Operation::ROL | Operation::ROR |
+            Operation::INC | Operation::DEC | Operation::SLO | Operation::SRE |
+            Operation::RLA | Operation::RRA | Operation::DCP | Operation::ISB => Access::Modify,
+            _ => Access::Read,
+        }
+    }
+}
+
+// State of the instruction in progress
+pub struct CycleState {
+    instruction: &'static Instruction,
+    // cycle of the instruction that was run last. 1 is the opcode fetch
+    cycle: u8,
+    // operand address. Indexed modes store the corrected address here
+    addr: Addr,
+    // zero page pointer of the indirect modes
+    ptr: Byte,
+    // data latch
+    data: Byte,
+    // indexing crossed a page boundary
+    page_cross: bool,
+    // interrupt sequence instead of BRK
+    interrupt: bool,
+}
+
+impl CycleState {
+    fn new(instruction: &'static Instruction) -> Self {
+        CycleState {
+            instruction,
+            cycle: 1,
+            addr: 0x0000,
+            ptr: 0x00,
+            data: 0x00,
+            page_cross: false,
+            interrupt: false,
+        }
+    }
+
+    // IRQ and NMI run the BRK sequence without incrementing pc and
+    // without setting the B flag
+    fn interrupt() -> Self {
+        let mut state = CycleState::new(Instruction::decode_op(0x00));
+        state.interrupt = true;
+        state
+    }
+}
+
+// Memory stand-in for the op_ handlers. Reads return the data latch and
+// writes are captured instead of being put on the bus.
+struct Latch {
+    data: Byte,
+    write_addr: Option<Addr>,
+}
+
+impl Memory for Latch {
+    fn readb(&self, _addr: Addr) -> Byte {
+        self.data
+    }
+
+    fn writeb(&mut self, addr: Addr, data: Byte) {
+        self.data = data;
+        self.write_addr = Some(addr);
+    }
+}
+
+impl CPU {
+    // Run a single cycle of the current instruction or fetch the next
+    // opcode if no instruction is in progress
+    pub(super) fn clock_cycle<T: Memory>(&mut self, mem: &mut T) {
+        let mut state = match self.cycle_state.take() {
+            Some(state) => state,
+            None => {
+                if let Some(interrupt) = self.pending_interrupt.take() {
+                    // the opcode is fetched, but thrown away
+                    debug!("{:?} at pc {:#06x}", interrupt, self.regs.pc);
+                    mem.readb(self.regs.pc);
+                    self.cycle_state = Some(CycleState::interrupt());
+                    return
+                }
+
+                debug!("{}", self.trace(mem));
+                let opcode = self.readb_pc(mem);
+                self.curr_op = opcode;
+                let instruction = Instruction::decode_op(opcode);
+                if instruction.operation == Operation::KIL {
+                    self.execute(mem, instruction, 0);
+                } else {
+                    self.cycle_state = Some(CycleState::new(instruction));
+                    self.poll_interrupts(self.is_flag_set(Flags::IRQ));
+                }
+                return
+            }
+        };
+
+        state.cycle += 1;
+        if self.step(mem, &mut state) {
+            return
+        }
+
+        // The interrupt sequence does not poll, so the first instruction of
+        // the handler always runs. Taken branches without page cross do
+        // not poll on their last cycle.
+        let skip_poll = state.instruction.addr_mode == AddrMode::REL && state.cycle == 2;
+        if !(state.interrupt || skip_poll) {
+            self.poll_interrupts(self.is_flag_set(Flags::IRQ));
+        }
+        self.cycle_state = Some(state);
+    }
+
+    // Run one cycle after the opcode fetch. Returns true if this was the
+    // last cycle of the instruction
+    fn step<T: Memory>(&mut self, mem: &mut T, st: &mut CycleState) -> bool {
+        let i = st.instruction;
+        match i.operation {
+            Operation::BRK | Operation::JSR | Operation::RTI | Operation::RTS |
+            Operation::PHA | Operation::PHP | Operation::PLA | Operation::PLP => {
+                return self.step_stack(mem, st)
+            },
+            Operation::JMP => return self.step_jump(mem, st),
+            _ => { },
+        }
+
+        match i.addr_mode {
+            AddrMode::IMP => {
+                // implied instructions read the next byte and throw it away
+                mem.readb(self.regs.pc);
+                self.execute(mem, i, 0);
+                true
+            },
+            AddrMode::IMM => {
+                st.addr = self.regs.pc;
+                st.data = self.readb_pc(mem);
+                self.execute_latched(st);
+                true
+            },
+            AddrMode::REL => self.step_branch(mem, st),
+            _ => self.step_memory(mem, st),
+        }
+    }
+
+    // Run the operation on the data latch. Returns the address and value
+    // of a write of the operation, if there was one.
+    fn execute_latched(&mut self, st: &mut CycleState) -> Option<(Addr, Byte)> {
+        let mut latch = Latch { data: st.data, write_addr: None };
+        self.execute(&mut latch, st.instruction, st.addr);
+        st.data = latch.data;
+        latch.write_addr.map(|addr| (addr, latch.data))
+    }
+
+    // Instructions with a memory operand: Resolve the address, then read,
+    // write or read-modify-write it.
+    fn step_memory<T: Memory>(&mut self, mem: &mut T, st: &mut CycleState) -> bool {
+        let i = st.instruction;
+
+        // first cycle after the address is resolved
+        let access_cycle = match i.addr_mode {
+            AddrMode::ZP0 => 3,
+            AddrMode::ZPX | AddrMode::ZPY | AddrMode::ABS => 4,
+            AddrMode::ABX | AddrMode::ABY => 5,
+            AddrMode::IZX | AddrMode::IZY => 6,
+            _ => unreachable!("{:?} has no memory operand", i.addr_mode),
+        };
+        if st.cycle < access_cycle {
+            return self.step_addressing(mem, st)
+        }
+
+        match (Access::of(i), st.cycle - access_cycle) {
+            (Access::Read, 0) => {
+                st.data = mem.readb(st.addr);
+                self.execute_latched(st);
+                true
+            },
+            (Access::Write, 0) => {
+                if let Some((addr, data)) = self.execute_latched(st) {
+                    mem.writeb(addr, data);
+                }
+                true
+            },
+            (Access::Modify, 0) => {
+                st.data = mem.readb(st.addr);
+                false
+            },
+            (Access::Modify, 1) => {
+                // the unmodified value is written back while the ALU works
+                mem.writeb(st.addr, st.data);
+                self.execute_latched(st);
+                false
+            },
+            (Access::Modify, 2) => {
+                mem.writeb(st.addr, st.data);
+                true
+            },
+            (access, cycle) => unreachable!("{:?} cycle {} of {:?}", access, cycle, i),
+        }
+    }
+
+    // Address resolution of the memory operand. Returns true if the
+    // instruction finished (indexed reads without page cross)
+    fn step_addressing<T: Memory>(&mut self, mem: &mut T, st: &mut CycleState) -> bool {
+        match (&st.instruction.addr_mode, st.cycle) {
+            (AddrMode::ZP0, 2) | (AddrMode::ZPX, 2) | (AddrMode::ZPY, 2) |
+            (AddrMode::ABS, 2) | (AddrMode::ABX, 2) | (AddrMode::ABY, 2) => {
+                st.addr = self.readb_pc(mem) as Addr;
+            },
+            (AddrMode::ZPX, 3) => {
+                mem.readb(st.addr);
+                st.addr = st.addr.wrapping_add(self.regs.x as Addr) & LO;
+            },
+            (AddrMode::ZPY, 3) => {
+                mem.readb(st.addr);
+                st.addr = st.addr.wrapping_add(self.regs.y as Addr) & LO;
+            },
+            (AddrMode::ABS, 3) => {
+                st.addr |= (self.readb_pc(mem) as Addr) << 8;
+            },
+            (AddrMode::ABX, 3) | (AddrMode::ABY, 3) => {
+                let index = if st.instruction.addr_mode == AddrMode::ABX {
+                    self.regs.x
+                } else {
+                    self.regs.y
+                };
+                let base = st.addr | (self.readb_pc(mem) as Addr) << 8;
+                self.index_addr(st, base, index);
+            },
+            (AddrMode::IZX, 2) | (AddrMode::IZY, 2) => {
+                st.ptr = self.readb_pc(mem);
+            },
+            (AddrMode::IZX, 3) => {
+                mem.readb(st.ptr as Addr);
+                st.ptr = st.ptr.wrapping_add(self.regs.x);
+            },
+            (AddrMode::IZX, 4) | (AddrMode::IZY, 3) => {
+                st.addr = mem.readb(st.ptr as Addr) as Addr;
+            },
+            (AddrMode::IZX, 5) => {
+                st.addr |= (mem.readb(st.ptr.wrapping_add(1) as Addr) as Addr) << 8;
+            },
+            (AddrMode::IZY, 4) => {
+                let base = st.addr | (mem.readb(st.ptr.wrapping_add(1) as Addr) as Addr) << 8;
+                self.index_addr(st, base, self.regs.y);
+            },
+            (AddrMode::ABX, 4) | (AddrMode::ABY, 4) | (AddrMode::IZY, 5) => {
+                return self.step_fixup(mem, st)
+            },
+            (mode, cycle) => unreachable!("{:?} has no cycle {}", mode, cycle),
+        }
+        false
+    }
+
+    fn index_addr(&self, st: &mut CycleState, base: Addr, index: Byte) {
+        st.addr = base.wrapping_add(index as Addr);
+        st.page_cross = st.addr & HI != base & HI;
+    }
+
+    // Indexed modes first read from the address without the carry into the
+    // high byte. For read instructions without page cross this already is
+    // the operand, everything else uses it as a dummy read and accesses the
+    // corrected address on the following cycles.
+    fn step_fixup<T: Memory>(&mut self, mem: &mut T, st: &mut CycleState) -> bool {
+        let uncorrected = if st.page_cross {
+            st.addr.wrapping_sub(0x0100)
+        } else {
+            st.addr
+        };
+        let data = mem.readb(uncorrected);
+
+        if Access::of(st.instruction) == Access::Read && !st.page_cross {
+            st.data = data;
+            self.execute_latched(st);
+            return true
+        }
+        false
+    }
+
+    fn branch_taken(&self, operation: &Operation) -> bool {
+        match operation {
+            Operation::BCC => !self.is_flag_set(Flags::CARRY),
+            Operation::BCS => self.is_flag_set(Flags::CARRY),
+            Operation::BEQ => self.is_flag_set(Flags::ZERO),
+            Operation::BNE => !self.is_flag_set(Flags::ZERO),
+            Operation::BMI => self.is_flag_set(Flags::NEGATIVE),
+            Operation::BPL => !self.is_flag_set(Flags::NEGATIVE),
+            Operation::BVC => !self.is_flag_set(Flags::OVERFLOW),
+            Operation::BVS => self.is_flag_set(Flags::OVERFLOW),
+            _ => unreachable!("{:?} is not a branch", operation),
+        }
+    }
+
+    // Branches take 2 cycles, +1 if taken and +1 if the target is on
+    // another page
+    fn step_branch<T: Memory>(&mut self, mem: &mut T, st: &mut CycleState) -> bool {
+        match st.cycle {
+            2 => {
+                let (target, page_cross) = self.am_REL(mem);
+                st.addr = target;
+                st.page_cross = page_cross;
+                !self.branch_taken(&st.instruction.operation)
+            },
+            3 => {
+                // pc lo is fixed first, the carry into hi takes another cycle
+                mem.readb(self.regs.pc);
+                self.regs.pc = (self.regs.pc & HI) | (st.addr & LO);
+                !st.page_cross
+            },
+            4 => {
+                mem.readb(self.regs.pc);
+                self.regs.pc = st.addr;
+                true
+            },
+            cycle => unreachable!("branch has no cycle {}", cycle),
+        }
+    }
+
+    fn step_jump<T: Memory>(&mut self, mem: &mut T, st: &mut CycleState) -> bool {
+        match (&st.instruction.addr_mode, st.cycle) {
+            (_, 2) => {
+                st.addr = self.readb_pc(mem) as Addr;
+                false
+            },
+            (AddrMode::ABS, 3) => {
+                st.addr |= (mem.readb(self.regs.pc) as Addr) << 8;
+                self.regs.pc = st.addr;
+                true
+            },
+            (AddrMode::IND, 3) => {
+                st.addr |= (self.readb_pc(mem) as Addr) << 8;
+                false
+            },
+            (AddrMode::IND, 4) => {
+                st.data = mem.readb(st.addr);
+                false
+            },
+            (AddrMode::IND, 5) => {
+                // hi is read from the same page, see am_IND
+                let hi_addr = (st.addr & HI) | (st.addr.wrapping_add(1) & LO);
+                self.regs.pc = (mem.readb(hi_addr) as Addr) << 8 | st.data as Addr;
+                true
+            },
+            (mode, cycle) => unreachable!("JMP {:?} has no cycle {}", mode, cycle),
+        }
+    }
+
+    fn step_stack<T: Memory>(&mut self, mem: &mut T, st: &mut CycleState) -> bool {
+        let stack_addr = STACK_BASE_ADDR + self.regs.sp as Addr;
+        match (&st.instruction.operation, st.cycle) {
+            // BRK skips a padding byte
+            (Operation::BRK, 2) => {
+                if st.interrupt {
+                    mem.readb(self.regs.pc);
+                } else {
+                    self.readb_pc(mem);
+                }
+            },
+            (Operation::BRK, 3) | (Operation::JSR, 4) => {
+                self.pushb_sp(mem, (self.regs.pc >> 8) as Byte);
+            },
+            (Operation::BRK, 4) | (Operation::JSR, 5) => {
+                self.pushb_sp(mem, self.regs.pc as Byte);
+            },
+            (Operation::BRK, 5) => {
+                let mut flags = self.regs.flags | Flags::UNUSED;
+                flags.set(Flags::BREAK, !st.interrupt);
+                self.pushb_sp(mem, flags.bits());
+                self.set_flag(Flags::IRQ, true);
+            },
+            (Operation::BRK, 6) => {
+                // an NMI until now hijacks the sequence
+                st.addr = self.interrupt_vector();
+                st.data = mem.readb(st.addr);
+            },
+            (Operation::BRK, 7) => {
+                self.regs.pc = (mem.readb(st.addr + 1) as Addr) << 8 | st.data as Addr;
+                return true
+            },
+            (Operation::JSR, 2) => {
+                st.data = self.readb_pc(mem);
+            },
+            (Operation::JSR, 3) => {
+                // internal operation, the cpu reads the stack
+                mem.readb(stack_addr);
+            },
+            (Operation::JSR, 6) => {
+                // pc points to the hi byte of the target. This is the
+                // return address - 1 pushed before
+                self.regs.pc = (mem.readb(self.regs.pc) as Addr) << 8 | st.data as Addr;
+                return true
+            },
+            (_, 2) => {
+                mem.readb(self.regs.pc);
+            },
+            (Operation::PHA, 3) => {
+                self.op_PHA(mem);
+                return true
+            },
+            (Operation::PHP, 3) => {
+                self.op_PHP(mem);
+                return true
+            },
+            (_, 3) => {
+                // pulling instructions read the stack before incrementing sp
+                mem.readb(stack_addr);
+            },
+            (Operation::PLA, 4) => {
+                self.op_PLA(mem);
+                return true
+            },
+            (Operation::PLP, 4) => {
+                self.op_PLP(mem);
+                return true
+            },
+            (Operation::RTI, 4) => {
+                self.regs.flags = Flags::from_bits(self.popb_sp(mem)).unwrap();
+                self.regs.flags &= !Flags::BREAK;
+                self.regs.flags |= Flags::UNUSED;
+            },
+            (Operation::RTI, 5) | (Operation::RTS, 4) => {
+                st.data = self.popb_sp(mem);
+            },
+            (Operation::RTI, 6) => {
+                self.regs.pc = (self.popb_sp(mem) as Addr) << 8 | st.data as Addr;
+                return true
+            },
+            (Operation::RTS, 5) => {
+                self.regs.pc = (self.popb_sp(mem) as Addr) << 8 | st.data as Addr;
+            },
+            (Operation::RTS, 6) => {
+                self.readb_pc(mem);
+                return true
+            },
+            (operation, cycle) => unreachable!("{:?} has no cycle {}", operation, cycle),
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::cpu::ExecMode;
+    use core::cell::RefCell;
+
+    // Flat 64kb memory that logs every bus access
+    struct LogMemory {
+        data: RefCell<Vec<Byte>>,
+        log: RefCell<Vec<(char, Addr, Byte)>>,
+    }
+
+    impl LogMemory {
+        fn new() -> Self {
+            LogMemory {
+                data: RefCell::new(vec![0; 0x10000]),
+                log: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn load(&self, addr: Addr, bytes: &[Byte]) {
+            for (i, byte) in bytes.iter().enumerate() {
+                self.data.borrow_mut()[addr as usize + i] = *byte;
+            }
+        }
+    }
+
+    impl Memory for LogMemory {
+        fn readb(&self, addr: Addr) -> Byte {
+            let val = self.data.borrow()[addr as usize];
+            self.log.borrow_mut().push(('R', addr, val));
+            val
+        }
+
+        fn peekb(&self, addr: Addr) -> Byte {
+            self.data.borrow()[addr as usize]
+        }
+
+        fn writeb(&mut self, addr: Addr, data: Byte) {
+            self.log.borrow_mut().push(('W', addr, data));
+            self.data.borrow_mut()[addr as usize] = data;
+        }
+    }
+
+    fn cycle_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.mode = ExecMode::Cycle;
+        cpu.regs.pc = 0x8000;
+        cpu
+    }
+
+    // Runs one instruction and returns the number of cycles and the number
+    // of bus accesses that happened on each cycle
+    fn run(cpu: &mut CPU, mem: &mut LogMemory) -> (u8, Vec<usize>) {
+        let mut accesses = Vec::new();
+        let mut cycles = 0;
+        loop {
+            let before = mem.log.borrow().len();
+            cpu.clock(mem);
+            cycles += 1;
+            accesses.push(mem.log.borrow().len() - before);
+            if !cpu.is_ahead() {
+                break
+            }
+        }
+        (cycles, accesses)
+    }
+
+    #[test]
+    fn test_cycles_match_instruction_table() {
+        for opcode in 0x00..=0xFFu8 {
+            let i = Instruction::decode_op(opcode);
+            if i.operation == Operation::KIL {
+                continue
+            }
+            let mut cpu = cycle_cpu();
+            let mut mem = LogMemory::new();
+            // operands that never cross a page. Taken branches stay on
+            // the same page as well
+            mem.load(0x8000, &[opcode, 0x10, 0x02]);
+            cpu.regs.sp = 0xF0;
+            let taken = match i.addr_mode {
+                AddrMode::REL => cpu.branch_taken(&i.operation),
+                _ => false,
+            };
+
+            let (cycles, accesses) = run(&mut cpu, &mut mem);
+            let expected = i.cycles[0] + if taken { 1 } else { 0 };
+            assert_eq!(cycles, expected, "cycles of {:?}", i);
+            assert!(accesses.iter().all(|&n| n == 1),
+                "not one bus access per cycle for {:?}: {:?}", i, accesses);
+        }
+    }
+
+    #[test]
+    fn test_trace_is_not_a_bus_access() {
+        // the opcode fetch is traced with debug! before it is put on the bus
+        let cpu = cycle_cpu();
+        let mem = LogMemory::new();
+        mem.load(0x8000, &[0xEA]);
+        assert!(cpu.trace(&mem).starts_with("8000  EA NOP"));
+        assert!(mem.log.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_cycles_page_cross() {
+        for opcode in 0x00..=0xFFu8 {
+            let i = Instruction::decode_op(opcode);
+            match i.addr_mode {
+                AddrMode::ABX | AddrMode::ABY | AddrMode::IZY => { },
+                _ => continue,
+            }
+            let mut cpu = cycle_cpu();
+            let mut mem = LogMemory::new();
+            mem.load(0x8000, &[opcode, 0x10, 0x02]);
+            mem.load(0x0010, &[0xF0, 0x02]);
+            cpu.regs.x = 0xFF;
+            cpu.regs.y = 0xFF;
+
+            let (cycles, _) = run(&mut cpu, &mut mem);
+            assert_eq!(cycles, i.cycles[0] + i.cycles[1], "cycles of {:?}", i);
+        }
+    }
+
+    #[test]
+    fn test_read_modify_write_accesses() {
+        // INC $02F0,X with page cross
+        let mut cpu = cycle_cpu();
+        let mut mem = LogMemory::new();
+        mem.load(0x8000, &[0xFE, 0xF0, 0x02]);
+        mem.load(0x0310, &[0x41]);
+        cpu.regs.x = 0x20;
+
+        run(&mut cpu, &mut mem);
+        assert_eq!(*mem.log.borrow(), vec![
+            ('R', 0x8000, 0xFE),
+            ('R', 0x8001, 0xF0),
+            ('R', 0x8002, 0x02),
+            ('R', 0x0210, 0x00),  // dummy read without carry
+            ('R', 0x0310, 0x41),
+            ('W', 0x0310, 0x41),  // dummy write of the old value
+            ('W', 0x0310, 0x42),
+        ]);
+    }
+
+    #[test]
+    fn test_indexed_read_accesses() {
+        // LDA $02F0,X with and without page cross
+        let mut cpu = cycle_cpu();
+        let mut mem = LogMemory::new();
+        mem.load(0x8000, &[0xBD, 0xF0, 0x02, 0xBD, 0xF0, 0x02]);
+        mem.load(0x02F1, &[0x11]);
+        mem.load(0x0310, &[0x22]);
+
+        cpu.regs.x = 0x01;
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.regs.a, 0x11);
+        cpu.regs.x = 0x20;
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.regs.a, 0x22);
+        assert_eq!(*mem.log.borrow(), vec![
+            ('R', 0x8000, 0xBD),
+            ('R', 0x8001, 0xF0),
+            ('R', 0x8002, 0x02),
+            ('R', 0x02F1, 0x11),
+            ('R', 0x8003, 0xBD),
+            ('R', 0x8004, 0xF0),
+            ('R', 0x8005, 0x02),
+            ('R', 0x0210, 0x00),
+            ('R', 0x0310, 0x22),
+        ]);
+    }
+
+    #[test]
+    fn test_jsr_rts_accesses() {
+        let mut cpu = cycle_cpu();
+        let mut mem = LogMemory::new();
+        mem.load(0x8000, &[0x20, 0x34, 0x92]);
+        mem.load(0x9234, &[0x60]);
+
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.regs.pc, 0x9234);
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.regs.pc, 0x8003);
+        assert_eq!(*mem.log.borrow(), vec![
+            ('R', 0x8000, 0x20),
+            ('R', 0x8001, 0x34),
+            ('R', 0x01FD, 0x00),
+            ('W', 0x01FD, 0x80),
+            ('W', 0x01FC, 0x02),
+            ('R', 0x8002, 0x92),
+            ('R', 0x9234, 0x60),
+            ('R', 0x9235, 0x00),
+            ('R', 0x01FB, 0x00),
+            ('R', 0x01FC, 0x02),
+            ('R', 0x01FD, 0x80),
+            ('R', 0x8002, 0x92),
+        ]);
+    }
+
+    #[test]
+    fn test_modes_agree() {
+        // a short program using most addressing modes
+        let program = [
+            0xA2, 0x05,        // LDX #$05
+            0xA9, 0x80,        // LDA #$80
+            0x95, 0x10,        // STA $10,X
+            0xF6, 0x10,        // INC $10,X
+            0x1E, 0x00, 0x02,  // ASL $0200,X
+            0xB5, 0x10,        // LDA $10,X
+            0x48,              // PHA
+            0xCA,              // DEX
+            0xD0, 0xF1,        // BNE -15
+            0x68,              // PLA
+        ];
+        let mut instruction_cpu = CPU::new();
+        instruction_cpu.regs.pc = 0x8000;
+        let mut instruction_mem = LogMemory::new();
+        instruction_mem.load(0x8000, &program);
+        let mut cycle_cpu = cycle_cpu();
+        let mut cycle_mem = LogMemory::new();
+        cycle_mem.load(0x8000, &program);
+
+        while instruction_cpu.regs.pc < 0x8000 + program.len() as Addr {
+            run(&mut instruction_cpu, &mut instruction_mem);
+            run(&mut cycle_cpu, &mut cycle_mem);
+            assert_eq!(format!("{:?}", instruction_cpu), format!("{:?}", cycle_cpu));
+            assert_eq!(instruction_cpu.cycles, cycle_cpu.cycles);
+        }
+        assert_eq!(*instruction_mem.data.borrow(), *cycle_mem.data.borrow());
+    }
+}
@@ -21,7 +21,7 @@ impl Disasm {
         let mut mem_iter = ((start as usize) .. (stop as usize)+1).map({|a| a as Addr});
         while let Some(addr) = mem_iter.next() {
 
-            let opcode = mem.readb(addr);
+            let opcode = mem.peekb(addr);
             
             // Decode opcode, default to NOP/IMP to "skip" the byte
             let i = Instruction::decode_op(opcode); 
@@ -32,7 +32,7 @@ impl Disasm {
                 AddrMode::IMM => {
                     match mem_iter.next() {
                         Some(val) => {
-                            let val = mem.readb(val);
+                            let val = mem.peekb(val);
                             format!("#{0:02x} ({0})", val)
                         },
                         None => continue,
@@ -41,7 +41,7 @@ impl Disasm {
                 AddrMode::ZP0 | AddrMode::ZPX | AddrMode::ZPY  => {
                     match mem_iter.next() {
                         Some(val) => {
-                            let rel_addr = mem.readb(val);
+                            let rel_addr = mem.peekb(val);
                             format!("{:#04x}", rel_addr)
                         }
                         None => continue,
@@ -50,7 +50,7 @@ impl Disasm {
                 AddrMode::ABS | AddrMode::ABX | AddrMode::ABY => {
                     match mem_iter.next() {
                         Some(val) => {
-                            let val = mem.readw(val);
+                            let val = mem.peekw(val);
                             mem_iter.next().unwrap();
                             format!("{:#06x}", val)
                         },
@@ -60,7 +60,7 @@ impl Disasm {
                 AddrMode::REL => {
                     match mem_iter.next() {
                         Some(val) => {
-                            let rel_addr = mem.readb(val) as Word;
+                            let rel_addr = mem.peekb(val) as Word;
                             let jmp_addr = Disasm::get_rel_addr(rel_addr, addr+2);
                             format!("#{:02x} => {:#06x}", rel_addr, jmp_addr)
                         },
@@ -70,7 +70,7 @@ impl Disasm {
                 AddrMode::IND => {
                     match mem_iter.next() {
                         Some(val) => {
-                            let addr = mem.readw(val);
+                            let addr = mem.peekw(val);
                             mem_iter.next().unwrap();
                             format!("{:#06x}", addr)
                         },
@@ -80,7 +80,7 @@ impl Disasm {
                 AddrMode::IZX | AddrMode::IZY => {
                     match mem_iter.next() {
                         Some(val) => {
-                            let addr = mem.readb(val);
+                            let addr = mem.peekb(val);
                             format!("{:#06x}", addr)
                         },
                         None => continue,
@@ -0,0 +1,123 @@
+use crate::nes::bus::Memory;
+use crate::nes::types::*;
+
+// bytes copied by one OAM DMA
+const OAM_DMA_SIZE: u16 = 256;
+// OAMDATA register the DMA writes to
+const OAM_DATA_ADDR: Addr = 0x2004;
+
+// OAM DMA unit of the 2A03. A write to 0x4014 copies the 256 byte page
+// $XX00-$XXFF into OAM through 0x2004. The CPU is halted while the DMA
+// runs: one halt cycle, one more if the DMA starts on an odd CPU cycle,
+// then 256 alternating read and write cycles. 513 or 514 cycles total.
+pub struct OamDma {
+    page: Byte,
+    data: Byte,
+    // cycles run and total cycles of the current transfer
+    cycle: u16,
+    cycles: u16,
+    active: bool,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        OamDma { page: 0, data: 0, cycle: 0, cycles: 0, active: false }
+    }
+
+    // start a transfer of page. odd_cycle: the first DMA cycle is an odd
+    // CPU cycle and needs an extra alignment cycle
+    pub fn start(&mut self, page: Byte, odd_cycle: bool) {
+        self.page = page;
+        self.cycle = 0;
+        self.cycles = 2 * OAM_DMA_SIZE + if odd_cycle { 2 } else { 1 };
+        self.active = true;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Run one CPU cycle of the transfer
+    pub fn clock<T: Memory>(&mut self, mem: &mut T) {
+        if !self.active {
+            return
+        }
+        // the first 1 or 2 cycles only halt and align
+        let transfer_start = self.cycles - 2 * OAM_DMA_SIZE;
+        if self.cycle >= transfer_start {
+            let step = self.cycle - transfer_start;
+            if step & 0x01 == 0 {
+                let addr = ((self.page as Addr) << 8) | (step / 2);
+                self.data = mem.readb(addr);
+            } else {
+                mem.writeb(OAM_DATA_ADDR, self.data);
+            }
+        }
+
+        self.cycle += 1;
+        if self.cycle == self.cycles {
+            self.active = false;
+        }
+    }
+
+    // page, cycles run and total cycles of the running transfer
+    pub fn progress(&self) -> Option<(Byte, u16, u16)> {
+        if self.active {
+            Some((self.page, self.cycle, self.cycles))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nes::*;
+
+    // run the NES until the dma is done. Returns the CPU cycles it took
+    fn run_dma(nes: &mut NES) -> u64 {
+        let start = nes.cpu.cycles;
+        while !nes.dma.is_active() {
+            let _ = nes.clock();
+        }
+        while nes.dma.is_active() {
+            let _ = nes.clock();
+        }
+        nes.cpu.cycles - start
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut nes = NES::new();
+        for i in 0..256 {
+            nes.bus.writeb(0x0200 + i, i as Byte);
+        }
+        // starts at the OAM address and wraps around
+        nes.bus.writeb(0x2003, 0x10);
+        nes.bus.writeb(0x4014, 0x02);
+        let cycles = run_dma(&mut nes);
+        assert!(cycles == 513 || cycles == 514, "{}", cycles);
+
+        let ppu = nes.ppu.borrow();
+        assert_eq!(ppu.regs.dma, 0x02);
+        assert_eq!(ppu.oam[0x10], 0x00);
+        assert_eq!(ppu.oam[0xFF], 0xEF);
+        assert_eq!(ppu.oam[0x00], 0xF0);
+        assert_eq!(ppu.oam[0x0F], 0xFF);
+    }
+
+    #[test]
+    fn test_oam_dma_alignment() {
+        let mut dma = super::OamDma::new();
+        let mut nes = NES::new();
+        for &(odd_cycle, expected) in [(false, 513), (true, 514)].iter() {
+            dma.start(0x00, odd_cycle);
+            let mut cycles = 0;
+            while dma.is_active() {
+                dma.clock(&mut nes.bus);
+                cycles += 1;
+            }
+            assert_eq!(cycles, expected);
+        }
+    }
+}
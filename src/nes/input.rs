@@ -0,0 +1,123 @@
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use core::cell::RefCell;
+use failure::Error;
+use crate::nes::types::*;
+use crate::nes::ppu::PPU;
+
+pub mod controller;
+pub mod zapper;
+pub mod four_score;
+pub mod power_pad;
+
+pub use crate::nes::input::controller::{Controller,Buttons};
+pub use crate::nes::input::zapper::Zapper;
+pub use crate::nes::input::four_score::FourScore;
+pub use crate::nes::input::power_pad::PowerPad;
+
+// A device plugged into one of the two controller ports. The CPU talks
+// to it through $4016 (writes go to both ports) and $4016/$4017 (reads).
+pub trait InputDevice {
+    // Write to $4016. Bit 0 is the strobe line
+    fn write(&mut self, data: Byte);
+    // Read the port. The device drives the data lines D0-D4, the upper
+    // bits are open bus
+    fn read(&mut self) -> Byte;
+}
+
+// Devices that can be selected for a port
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum InputKind {
+    None,
+    Controller,
+    Zapper,
+    FourScore,
+    PowerPad,
+}
+
+// A connected device. The bus only sees the InputDevice, the frontend
+// keeps the concrete type to feed buttons, aim and so on into it
+#[derive(Clone)]
+pub enum Input {
+    None,
+    Controller(Rc<RefCell<Controller>>),
+    Zapper(Rc<RefCell<Zapper>>),
+    FourScore(Rc<RefCell<FourScore>>),
+    PowerPad(Rc<RefCell<PowerPad>>),
+}
+
+impl Input {
+    // new device for port 0 or 1. The zapper looks at the picture of the ppu
+    pub fn new(kind: InputKind, port: usize, ppu: &Rc<RefCell<PPU>>) -> Self {
+        match kind {
+            InputKind::None => Input::None,
+            InputKind::Controller => Input::Controller(Rc::new(RefCell::new(Controller::new()))),
+            InputKind::Zapper => Input::Zapper(Rc::new(RefCell::new(Zapper::new(ppu.clone())))),
+            InputKind::FourScore => Input::FourScore(Rc::new(RefCell::new(FourScore::new(port)))),
+            InputKind::PowerPad => Input::PowerPad(Rc::new(RefCell::new(PowerPad::new()))),
+        }
+    }
+
+    pub fn kind(&self) -> InputKind {
+        match self {
+            Input::None => InputKind::None,
+            Input::Controller(_) => InputKind::Controller,
+            Input::Zapper(_) => InputKind::Zapper,
+            Input::FourScore(_) => InputKind::FourScore,
+            Input::PowerPad(_) => InputKind::PowerPad,
+        }
+    }
+
+    // the device as seen by the bus
+    pub fn device(&self) -> Option<Rc<RefCell<dyn InputDevice>>> {
+        match self {
+            Input::None => None,
+            Input::Controller(device) => Some(device.clone()),
+            Input::Zapper(device) => Some(device.clone()),
+            Input::FourScore(device) => Some(device.clone()),
+            Input::PowerPad(device) => Some(device.clone()),
+        }
+    }
+}
+
+impl FromStr for InputKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(InputKind::None),
+            "controller" | "pad" => Ok(InputKind::Controller),
+            "zapper" => Ok(InputKind::Zapper),
+            "fourscore" | "four-score" => Ok(InputKind::FourScore),
+            "powerpad" | "power-pad" => Ok(InputKind::PowerPad),
+            _ => bail!("Unknown input device '{}'. Use none, controller, zapper, fourscore or powerpad", s),
+        }
+    }
+}
+
+impl fmt::Display for InputKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            InputKind::None => "none",
+            InputKind::Controller => "controller",
+            InputKind::Zapper => "zapper",
+            InputKind::FourScore => "fourscore",
+            InputKind::PowerPad => "powerpad",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_kind() {
+        assert_eq!("Zapper".parse::<InputKind>().unwrap(), InputKind::Zapper);
+        assert_eq!("four-score".parse::<InputKind>().unwrap(), InputKind::FourScore);
+        assert!("keyboard".parse::<InputKind>().is_err());
+        assert_eq!(InputKind::PowerPad.to_string(), "powerpad");
+    }
+}
@@ -0,0 +1,107 @@
+use crate::nes::types::*;
+use crate::nes::input::InputDevice;
+
+// Buttons of the standard controller in the order they are shifted out
+bitflags! {
+    pub struct Buttons: Byte {
+        const A      = 1 << 0;
+        const B      = 1 << 1;
+        const SELECT = 1 << 2;
+        const START  = 1 << 3;
+        const UP     = 1 << 4;
+        const DOWN   = 1 << 5;
+        const LEFT   = 1 << 6;
+        const RIGHT  = 1 << 7;
+    }
+}
+
+// Standard NES controller. Writing 1 to the strobe bit ($4016) latches the
+// button states into an 8 bit shift register, reads from $4016/$4017
+// return one button per read in bit 0.
+pub struct Controller {
+    pub buttons: Buttons,
+    strobe: bool,
+    shift: Byte,
+    // buttons already shifted out. An official controller returns 1
+    // after the 8 buttons
+    reads: u8,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Controller { buttons: Buttons::empty(), strobe: false, shift: 0, reads: 0 }
+    }
+
+    pub fn set_button(&mut self, button: Buttons, pressed: bool) {
+        self.buttons.set(button, pressed);
+    }
+
+    fn latch(&mut self) {
+        self.shift = self.buttons.bits();
+        self.reads = 0;
+    }
+}
+
+impl InputDevice for Controller {
+    // Only the strobe bit is used
+    fn write(&mut self, data: Byte) {
+        self.strobe = data & 0x01 > 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    // Next button state in bit 0. While the strobe is set the register is
+    // reloaded continuously and always returns A
+    fn read(&mut self) -> Byte {
+        if self.strobe {
+            self.latch();
+        }
+        if self.reads >= 8 {
+            return 0x01
+        }
+        let data = self.shift & 0x01;
+        self.shift >>= 1;
+        self.reads += 1;
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(controller: &mut Controller) -> Vec<Byte> {
+        (0..10).map(|_| controller.read()).collect()
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut controller = Controller::new();
+        controller.set_button(Buttons::A, true);
+        controller.set_button(Buttons::START, true);
+        controller.set_button(Buttons::LEFT, true);
+        controller.write(0x01);
+        controller.write(0x00);
+
+        // button changes after the strobe are not seen
+        controller.set_button(Buttons::B, true);
+        assert_eq!(read_all(&mut controller), vec![1, 0, 0, 1, 0, 0, 1, 0, 1, 1]);
+
+        // latch again
+        controller.write(0x01);
+        controller.write(0x00);
+        assert_eq!(read_all(&mut controller), vec![1, 1, 0, 1, 0, 0, 1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high() {
+        let mut controller = Controller::new();
+        controller.set_button(Buttons::A, true);
+        controller.write(0x01);
+        assert_eq!(read_all(&mut controller), vec![1; 10]);
+
+        controller.set_button(Buttons::A, false);
+        assert_eq!(controller.read(), 0);
+    }
+}
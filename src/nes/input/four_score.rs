@@ -0,0 +1,75 @@
+use crate::nes::types::*;
+use crate::nes::input::InputDevice;
+use crate::nes::input::controller::Controller;
+
+// Four Score 4 player adapter. Each port reads 8 buttons of the first
+// controller, 8 buttons of the second and an 8 bit signature that tells
+// games the adapter is connected. Port 1 has controllers 1 and 3, port 2
+// controllers 2 and 4. One half of the adapter is plugged into each port.
+pub struct FourScore {
+    pub controllers: [Controller; 2],
+    signature: Byte,
+    strobe: bool,
+    reads: u8,
+}
+
+impl FourScore {
+    // port: 0 for $4016, 1 for $4017
+    pub fn new(port: usize) -> Self {
+        FourScore {
+            controllers: [Controller::new(), Controller::new()],
+            // shifted out lsb first: 0,0,0,1,0,0,0,0 on port 1 and
+            // 0,0,1,0,0,0,0,0 on port 2
+            signature: if port == 0 { 0x08 } else { 0x04 },
+            strobe: false,
+            reads: 0,
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, data: Byte) {
+        self.strobe = data & 0x01 > 0;
+        self.controllers.iter_mut().for_each(|c| c.write(data));
+        if self.strobe {
+            self.reads = 0;
+        }
+    }
+
+    fn read(&mut self) -> Byte {
+        if self.strobe {
+            self.reads = 0;
+        }
+        let data = match self.reads {
+            0..=7 => self.controllers[0].read(),
+            8..=15 => self.controllers[1].read(),
+            16..=23 => (self.signature >> (self.reads - 16)) & 0x01,
+            _ => 0x01,
+        };
+        if self.reads < 24 && !self.strobe {
+            self.reads += 1;
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::input::Buttons;
+
+    #[test]
+    fn test_four_score() {
+        let mut four_score = FourScore::new(1);
+        four_score.controllers[0].set_button(Buttons::A, true);
+        four_score.controllers[1].set_button(Buttons::RIGHT, true);
+        four_score.write(0x01);
+        four_score.write(0x00);
+
+        let bits: Vec<Byte> = (0..26).map(|_| four_score.read()).collect();
+        assert_eq!(bits[0..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[8..16], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bits[16..24], [0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[24..], [1, 1]);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::nes::types::*;
+use crate::nes::input::InputDevice;
+
+// Order the buttons (numbered 1-12 on side B of the mat) are shifted out
+// on D3 and D4. D4 only has 4 buttons, then the line stays high
+const D3_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+// Power Pad (Family Trainer) mat with 12 buttons. It has two shift
+// registers that are read in parallel on bit 3 and bit 4.
+pub struct PowerPad {
+    // bit n-1: button n is pressed
+    pub buttons: u16,
+    strobe: bool,
+    shift_d3: Byte,
+    shift_d4: Byte,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad { buttons: 0, strobe: false, shift_d3: 0, shift_d4: 0 }
+    }
+
+    // button: 1-12
+    pub fn set_button(&mut self, button: usize, pressed: bool) {
+        let mask = 1 << (button - 1);
+        if pressed {
+            self.buttons |= mask;
+        } else {
+            self.buttons &= !mask;
+        }
+    }
+
+    fn pressed(&self, button: usize) -> Byte {
+        ((self.buttons >> (button - 1)) & 0x01) as Byte
+    }
+
+    fn latch(&mut self) {
+        self.shift_d3 = D3_ORDER.iter().enumerate()
+            .fold(0, |shift, (i, &button)| shift | self.pressed(button) << i);
+        // unused bits read as 1
+        self.shift_d4 = D4_ORDER.iter().enumerate()
+            .fold(0xF0, |shift, (i, &button)| shift | self.pressed(button) << i);
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write(&mut self, data: Byte) {
+        self.strobe = data & 0x01 > 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self) -> Byte {
+        if self.strobe {
+            self.latch();
+        }
+        let data = (self.shift_d3 & 0x01) << 3 | (self.shift_d4 & 0x01) << 4;
+        // ones are shifted in
+        self.shift_d3 = self.shift_d3 >> 1 | 0x80;
+        self.shift_d4 = self.shift_d4 >> 1 | 0x80;
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_pad() {
+        let mut pad = PowerPad::new();
+        pad.set_button(1, true);
+        pad.set_button(12, true);
+        pad.write(0x01);
+        pad.write(0x00);
+
+        let reads: Vec<Byte> = (0..10).map(|_| pad.read()).collect();
+        // button 1 is the second bit on D3, button 12 the third on D4
+        assert_eq!(reads, vec![0x00, 0x08, 0x10, 0x00, 0x10, 0x10, 0x10, 0x10, 0x18, 0x18]);
+    }
+}
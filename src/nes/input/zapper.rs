@@ -0,0 +1,105 @@
+use std::rc::Rc;
+use core::cell::RefCell;
+use crate::nes::types::*;
+use crate::nes::ppu::PPU;
+use crate::nes::input::InputDevice;
+
+// Minimum brightness (0-255, average of r, g and b) the light sensor
+// reacts to
+const LIGHT_THRESHOLD: u16 = 0xA0;
+// The photodiode stays lit for a while after the beam passed. Games
+// check for light during the frame, so this is measured in scanlines
+const LIGHT_LINES: i32 = 20;
+// Scanlines of the visible picture
+const SCREEN_HEIGHT: u32 = 240;
+const SCREEN_WIDTH: u32 = 256;
+
+// NES Zapper light gun. Reports the light sensor in bit 3 (0: light seen)
+// and the trigger in bit 4 (1: pulled). Light is seen if the PPU has just
+// drawn a bright pixel at the aim point.
+pub struct Zapper {
+    ppu: Rc<RefCell<PPU>>,
+    // screen coordinates the gun points at. None when it points off
+    // screen
+    pub aim: Option<(u32, u32)>,
+    pub trigger: bool,
+}
+
+impl Zapper {
+    pub fn new(ppu: Rc<RefCell<PPU>>) -> Self {
+        Zapper { ppu, aim: None, trigger: false }
+    }
+
+    pub fn aim_at(&mut self, x: i32, y: i32) {
+        self.aim = if x >= 0 && y >= 0 && (x as u32) < SCREEN_WIDTH && (y as u32) < SCREEN_HEIGHT {
+            Some((x as u32, y as u32))
+        } else {
+            None
+        };
+    }
+
+    fn light_sensed(&self) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+        let ppu = self.ppu.borrow();
+        // the beam must have passed the aim point recently
+        let lines_since = ppu.scanline as i32 - y as i32;
+        let passed = lines_since > 0 || (lines_since == 0 && ppu.cycle as u32 > x);
+        if !passed || lines_since >= LIGHT_LINES {
+            return false
+        }
+        let pixel = ppu.canvas_main.get_pixel(x, y);
+        let brightness = (pixel[0] as u16 + pixel[1] as u16 + pixel[2] as u16) / 3;
+        brightness >= LIGHT_THRESHOLD
+    }
+}
+
+impl InputDevice for Zapper {
+    // The zapper has no shift register and ignores the strobe
+    fn write(&mut self, _data: Byte) { }
+
+    fn read(&mut self) -> Byte {
+        let light = if self.light_sensed() { 0x00 } else { 0x08 };
+        let trigger = if self.trigger { 0x10 } else { 0x00 };
+        light | trigger
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_light_sense() {
+        let ppu = Rc::new(RefCell::new(PPU::new()));
+        let mut zapper = Zapper::new(ppu.clone());
+        ppu.borrow_mut().canvas_main.put_pixel(100, 50, Rgba([0xFF, 0xFF, 0xFF, 0xFF]));
+        assert_eq!(zapper.read(), 0x08, "no light when aiming off screen");
+
+        zapper.aim_at(100, 50);
+        zapper.trigger = true;
+        let at = |line, cycle| {
+            let mut ppu = ppu.borrow_mut();
+            ppu.scanline = line;
+            ppu.cycle = cycle;
+        };
+        // before and right after the beam drew the pixel
+        at(50, 90);
+        assert_eq!(zapper.read(), 0x18);
+        at(50, 110);
+        assert_eq!(zapper.read(), 0x10);
+        at(60, 0);
+        assert_eq!(zapper.read(), 0x10);
+        // the sensor has recovered
+        at(80, 0);
+        assert_eq!(zapper.read(), 0x18);
+
+        // dark pixels are not seen
+        zapper.aim_at(101, 50);
+        at(51, 0);
+        assert_eq!(zapper.read(), 0x18);
+    }
+}
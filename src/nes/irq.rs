@@ -0,0 +1,77 @@
+use std::rc::Rc;
+use core::cell::Cell;
+use crate::nes::types::*;
+
+// Devices that can assert the IRQ line of the CPU
+bitflags! {
+    pub struct IrqSource: Byte {
+        const MAPPER        = 1 << 0;  // scanline and cycle counters on the cartridge
+        const FRAME_COUNTER = 1 << 1;  // APU frame counter
+        const DMC           = 1 << 2;  // APU DMC end of sample
+    }
+}
+
+// The IRQ line of the CPU. The line is level triggered and wired-or: It
+// stays asserted as long as at least one source holds it. Every device
+// keeps a clone of the line and asserts and releases its own source.
+#[derive(Clone,Debug)]
+pub struct IrqLine {
+    sources: Rc<Cell<IrqSource>>,
+}
+
+impl IrqLine {
+    pub fn new() -> Self {
+        IrqLine { sources: Rc::new(Cell::new(IrqSource::empty())) }
+    }
+
+    pub fn assert(&self, source: IrqSource) {
+        self.sources.set(self.sources.get() | source);
+    }
+
+    pub fn release(&self, source: IrqSource) {
+        self.sources.set(self.sources.get() & !source);
+    }
+
+    // assert or release the source
+    pub fn set(&self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.assert(source);
+        } else {
+            self.release(source);
+        }
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        !self.sources.get().is_empty()
+    }
+
+    // all sources that currently assert the line
+    pub fn sources(&self) -> IrqSource {
+        self.sources.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_irq_line_wired_or() {
+        let line = IrqLine::new();
+        let mapper = line.clone();
+        let apu = line.clone();
+        assert!(!line.is_asserted());
+
+        mapper.assert(IrqSource::MAPPER);
+        apu.assert(IrqSource::DMC);
+        assert!(line.is_asserted());
+        assert_eq!(line.sources(), IrqSource::MAPPER | IrqSource::DMC);
+
+        // line stays asserted until the last source releases it
+        mapper.release(IrqSource::MAPPER);
+        assert!(line.is_asserted());
+        assert_eq!(line.sources(), IrqSource::DMC);
+        apu.set(IrqSource::DMC, false);
+        assert!(!line.is_asserted());
+    }
+}
@@ -1,70 +1,149 @@
 use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
 
-pub trait Mapper {
-    fn map_read_addr(&self, addr: Addr) -> Option<Addr>;
-    fn map_write_addr(&self, addr: Addr) -> Option<Addr>;
-    fn map_read_addr_ppu(&self, addr: Addr) -> Option<Addr>;
-    fn map_write_addr_ppu(&self, addr: Addr) -> Option<Addr>;
+pub mod nrom;
+pub mod mmc1;
+pub mod uxrom;
+pub mod cnrom;
+pub mod axrom;
+pub mod gxrom;
+pub mod mmc3;
+pub mod mmc2;
+pub mod mmc5;
+pub mod vrc_irq;
+pub mod vrc4;
+pub mod vrc6;
+pub mod fme7;
+pub mod namco163;
+
+pub use crate::nes::mappers::nrom::Nrom;
+pub use crate::nes::mappers::mmc1::Mmc1;
+pub use crate::nes::mappers::uxrom::Uxrom;
+pub use crate::nes::mappers::cnrom::Cnrom;
+pub use crate::nes::mappers::axrom::Axrom;
+pub use crate::nes::mappers::gxrom::Gxrom;
+pub use crate::nes::mappers::mmc3::Mmc3;
+pub use crate::nes::mappers::mmc2::Mmc2;
+pub use crate::nes::mappers::mmc5::Mmc5;
+pub use crate::nes::mappers::vrc4::Vrc4;
+pub use crate::nes::mappers::vrc6::Vrc6;
+pub use crate::nes::mappers::fme7::Fme7;
+pub use crate::nes::mappers::namco163::Namco163;
+
+// Where a CPU or PPU access of the cartridge ends up. Indices are into
+// the memory of the cartridge and wrap around its size
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Mapped {
+    // nothing on the cartridge answers
+    None,
+    PrgRom(usize),
+    PrgRam(usize),
+    // CHR ROM or CHR RAM
+    Chr(usize),
+    // the mapper answered a read from its own registers or memory, or
+    // consumed a write
+    Data(Byte),
 }
 
-// Mapper 0
-// prg rom: 16K or 32K
-// chr rom: 8K
-// All banks are fixed:
-// CPU:
-//     0x8000 - 0xbfff // first 16k
-//     0xc000 - 0xffff // second 16k
-//     
-// Cartrige:
-// 0x4000-0xffff -> first 16K 
-// 0xc000-0xffff -> last 16K or mirror or 0x8000-0xbfff
-#[derive(Debug)]
-pub struct Mapper0 {
-    prg_banks: Byte,
-    chr_banks: Byte, 
+// Memory of the cartridge a mapper is set up for. Sizes are in bytes
+#[derive(Clone,Copy,Debug)]
+pub struct Layout {
+    pub prg_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub chr_size: usize,
+    pub submapper: Byte,
 }
 
-impl Mapper0 {
-    pub fn new(prg_banks: Byte, chr_banks: Byte) -> Self {
-        Mapper0 { prg_banks, chr_banks }
+// The bank switching hardware of a cartridge. It sees every access to
+// the cartridge, translates addresses into the cartridge memory and can
+// hold registers, count cycles or PPU fetches and raise IRQs.
+pub trait Mapper {
+    // CPU read from $4020-$FFFF without side effects. Used by debug views
+    // and for bus conflicts
+    fn peek(&self, addr: Addr) -> Mapped;
+    // CPU read from $4020-$FFFF. Overridden by mappers with registers that
+    // change when they are read
+    fn read(&mut self, addr: Addr) -> Mapped {
+        self.peek(addr)
     }
+    // CPU write to $4020-$FFFF. Registers are written here. Returns where
+    // the data is stored, if anywhere
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped;
+    // PPU read from $0000-$3EFF. Nametables are left to the console with
+    // Mapped::None
+    fn read_ppu(&self, addr: Addr) -> Mapped;
+    fn write_ppu(&mut self, addr: Addr, data: Byte) -> Mapped;
+    // Called with every address the PPU puts on its bus after the access.
+    // Used to clock counters on A12 or to switch banks on tile fetches
+    fn ppu_bus(&mut self, _addr: Addr) { }
+    // One CPU cycle
+    fn clock(&mut self) { }
+    // The mapper asserts the CPU IRQ line
+    fn irq(&self) -> bool { false }
+    // Nametable mirroring if the mapper controls it. None for the one
+    // soldered on the board
+    fn mirror_mode(&self) -> Option<MirrorMode> { None }
+    // Reset button. Most mappers keep their registers
+    fn reset(&mut self) { }
+    // The ROM drives the data bus while a register is written, so the
+    // written value is ANDed with the ROM byte at that address
+    fn bus_conflicts(&self) -> bool { false }
+    // CPU write to a PPU register ($2000-$2007). The cartridge sees the
+    // whole CPU bus, MMC5 watches the sprite size this way
+    fn ppu_register_write(&mut self, _addr: Addr, _data: Byte) { }
+    // Level of the expansion audio chip, on the same scale as the APU
+    // output it is mixed with
+    fn audio_output(&self) -> f32 { 0.0 }
 }
 
-impl Mapper for Mapper0 {
-    fn map_read_addr(&self, addr: Addr) -> Option<Addr> {
-        if 0x8000 <= addr && addr <= 0xFFFF { 
-            if self.prg_banks > 1 {
-                return Some(addr & 0x7fff);
-            } else {
-                return Some(addr & 0x3fff);
-            }
-        }
-        None
-    }
-    fn map_write_addr(&self, addr: Addr) -> Option<Addr> {
-        if 0x8000 <= addr && addr <= 0xFFFF { 
-            if self.prg_banks > 1 {
-                return Some(addr & 0x7fff);
-            } else {
-                return Some(addr & 0x3fff);
-            }
-        }
-        None
-    }
+// Bank number `n` banks before the last one of a memory. Small memories
+// wrap around like the bank registers do
+pub fn bank_from_end(size: usize, bank_size: usize, n: usize) -> usize {
+    let banks = (size / bank_size).max(1);
+    banks - 1 - n % banks
+}
 
-    fn map_read_addr_ppu(&self, addr: Addr) -> Option<Addr> {
-        if 0x0000 <= addr && addr <= 0x1FFF {
-            return Some(addr)
-        }
-        None
+// The mapper for an iNES mapper number. None if it is not supported
+pub fn new_mapper(id: u16, layout: Layout) -> Option<Box<dyn Mapper>> {
+    match id {
+        0 => Some(Box::new(Nrom::new(layout))),
+        1 => Some(Box::new(Mmc1::new(layout))),
+        2 => Some(Box::new(Uxrom::new(layout))),
+        3 => Some(Box::new(Cnrom::new(layout))),
+        4 => Some(Box::new(Mmc3::new(layout))),
+        5 => Some(Box::new(Mmc5::new(layout))),
+        7 => Some(Box::new(Axrom::new(layout))),
+        9 | 10 => Some(Box::new(Mmc2::new(id, layout))),
+        19 => Some(Box::new(Namco163::new(layout))),
+        21 | 22 | 23 | 25 => Some(Box::new(Vrc4::new(id, layout))),
+        24 | 26 => Some(Box::new(Vrc6::new(id, layout))),
+        66 => Some(Box::new(Gxrom::new(layout))),
+        69 => Some(Box::new(Fme7::new(layout))),
+        _ => None,
     }
+}
 
-    fn map_write_addr_ppu(&self, addr: Addr) -> Option<Addr> {
-        if 0x0000 <= addr && addr <= 0x1FFF {
-            if self.chr_banks == 0 {  // no banks => RAM
-                return Some(addr)
-            }
-        }
-        None
+// Common name of an iNES mapper number
+pub fn mapper_name(id: u16) -> &'static str {
+    match id {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3",
+        5 => "MMC5",
+        7 => "AxROM",
+        9 => "MMC2",
+        10 => "MMC4",
+        11 => "Color Dreams",
+        19 => "Namco 163",
+        21 | 22 | 23 | 25 => "VRC2/VRC4",
+        24 | 26 => "VRC6",
+        34 => "BNROM/NINA-001",
+        66 => "GxROM",
+        69 => "Sunsoft FME-7",
+        71 => "Camerica",
+        85 => "VRC7",
+        _ => "unknown",
     }
 }
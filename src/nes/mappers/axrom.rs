@@ -0,0 +1,88 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout};
+
+// Mapper 7 (AxROM: ANROM, AN1ROM, AMROM, AOROM)
+// Writing to 0x8000 - 0xffff:
+//     bits 0-2: 32K PRG ROM bank
+//     bit 4: single screen nametable, lower or upper 1K of VRAM
+// CHR is 8K of RAM.
+// Only AMROM (submapper 2) has bus conflicts. The other boards are far
+// more common, so submapper 0 has none.
+#[derive(Debug)]
+pub struct Axrom {
+    prg_ram_size: usize,
+    bus_conflicts: bool,
+    bank: Byte,
+}
+
+impl Axrom {
+    pub fn new(layout: Layout) -> Self {
+        Axrom {
+            prg_ram_size: layout.prg_ram_size,
+            bus_conflicts: layout.submapper == 2,
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize % self.prg_ram_size),
+            0x8000..=0xFFFF => Mapped::PrgRom((self.bank & 0x07) as usize * 0x8000 + (addr & 0x7FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x8000..=0xFFFF => {
+                self.bank = data;
+                Mapped::Data(data)
+            },
+            _ => self.peek(addr),
+        }
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(addr as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        if self.bank & 0x10 == 0 {
+            Some(MirrorMode::SingleScreenLower)
+        } else {
+            Some(MirrorMode::SingleScreenUpper)
+        }
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_axrom() {
+        let layout = Layout { prg_rom_size: 0x40000, prg_ram_size: 0, chr_size: 0x2000, submapper: 0 };
+        let mut mapper = Axrom::new(layout);
+        assert_eq!(mapper.read(0xFFFC), Mapped::PrgRom(0x7FFC));
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::SingleScreenLower));
+        mapper.write(0x8000, 0x13);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(3 * 0x8000 + 1));
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::SingleScreenUpper));
+        assert!(!mapper.bus_conflicts());
+        assert!(Axrom::new(Layout { submapper: 2, ..layout }).bus_conflicts());
+    }
+}
@@ -0,0 +1,77 @@
+use crate::nes::types::*;
+use crate::nes::mappers::{Mapper,Mapped,Layout};
+
+// Mapper 3 (CNROM)
+// PRG ROM is fixed like NROM: 16K mirrored or 32K.
+// Writing to 0x8000 - 0xffff selects the 8K CHR ROM bank.
+// Submapper 1 has no bus conflicts, the original boards (0 and 2) do.
+#[derive(Debug)]
+pub struct Cnrom {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    bus_conflicts: bool,
+    chr_bank: Byte,
+}
+
+impl Cnrom {
+    pub fn new(layout: Layout) -> Self {
+        Cnrom {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            bus_conflicts: layout.submapper != 1,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize % self.prg_ram_size),
+            0x8000..=0xFFFF if self.prg_rom_size > 0x4000 => Mapped::PrgRom((addr & 0x7FFF) as usize),
+            0x8000..=0xFFFF => Mapped::PrgRom((addr & 0x3FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x8000..=0xFFFF => {
+                self.chr_bank = data;
+                Mapped::Data(data)
+            },
+            _ => self.peek(addr),
+        }
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(self.chr_bank as usize * 0x2000 + addr as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cnrom() {
+        let layout = Layout { prg_rom_size: 0x8000, prg_ram_size: 0, chr_size: 0x8000, submapper: 0 };
+        let mut mapper = Cnrom::new(layout);
+        assert_eq!(mapper.read(0xFFFC), Mapped::PrgRom(0x7FFC));
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(0x1001));
+        mapper.write(0x8000, 0x03);
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(3 * 0x2000 + 0x1001));
+        assert_eq!(mapper.read_ppu(0x2000), Mapped::None);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout,bank_from_end};
+
+// Mapper 69 (Sunsoft FME-7 and 5B)
+// $8000 selects a command, $A000 writes its parameter:
+//     0-7: 1K CHR banks
+//     8:   $6000 bank  ER.BBBBB  E: RAM enable, R: RAM instead of ROM
+//     9-B: 8K PRG banks at $8000, $A000 and $C000
+//     C:   mirroring  V, H, single lower, single upper
+//     D:   IRQ control  C......I  C: count, I: IRQ enable. Acknowledges
+//     E-F: IRQ counter low and high byte
+// The last 8K bank is fixed. The counter counts down every CPU cycle and
+// raises the IRQ when it wraps from 0 to 0xFFFF.
+// The 5B variant adds an audio chip at $C000 (register) and $E000 (data).
+pub struct Fme7 {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    command: Byte,
+    chr_banks: [Byte; 8],
+    prg_banks: [Byte; 4],
+    mirror: MirrorMode,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: Word,
+    irq: bool,
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(layout: Layout) -> Self {
+        Fme7 {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirror: MirrorMode::Vertical,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn write_parameter(&mut self, data: Byte) {
+        match self.command & 0x0F {
+            c @ 0..=7 => self.chr_banks[c as usize] = data,
+            c @ 8..=0xB => self.prg_banks[c as usize - 8] = data,
+            0xC => {
+                self.mirror = match data & 0x03 {
+                    0 => MirrorMode::Vertical,
+                    1 => MirrorMode::Horizontal,
+                    2 => MirrorMode::SingleScreenLower,
+                    _ => MirrorMode::SingleScreenUpper,
+                };
+            },
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.counter_enabled = data & 0x80 != 0;
+                self.irq = false;
+            },
+            0xE => self.counter = self.counter & 0xFF00 | data as Word,
+            _ => self.counter = self.counter & 0x00FF | (data as Word) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        let bank = self.prg_banks[0];
+        match addr {
+            // disabled or missing RAM reads open bus
+            0x6000..=0x7FFF if bank & 0x40 != 0 && (bank & 0x80 == 0 || self.prg_ram_size == 0) => Mapped::None,
+            0x6000..=0x7FFF if bank & 0x40 != 0 => Mapped::PrgRam((bank & 0x3F) as usize * 0x2000 + (addr - 0x6000) as usize),
+            0x6000..=0x7FFF => Mapped::PrgRom((bank & 0x3F) as usize * 0x2000 + (addr - 0x6000) as usize),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x6000) >> 13) as usize] & 0x3F;
+                Mapped::PrgRom(bank as usize * 0x2000 + (addr & 0x1FFF) as usize)
+            },
+            0xE000..=0xFFFF => Mapped::PrgRom(bank_from_end(self.prg_rom_size, 0x2000, 0) * 0x2000 + (addr & 0x1FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF => {
+                return match self.peek(addr) {
+                    Mapped::PrgRam(index) => Mapped::PrgRam(index),
+                    _ => Mapped::None,
+                }
+            },
+            0x8000..=0x9FFF => self.command = data,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.select(data),
+            0xE000..=0xFFFF => self.audio.writeb(data),
+            _ => return Mapped::None,
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => {
+                let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+                Mapped::Chr(bank * 0x0400 + (addr & 0x03FF) as usize)
+            },
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn clock(&mut self) {
+        if self.counter_enabled {
+            self.counter = self.counter.wrapping_sub(1);
+            if self.counter == 0xFFFF && self.irq_enabled {
+                self.irq = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        Some(self.mirror)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+// Output of a tone channel at full volume, about an APU pulse
+const SUNSOFT_5B_LEVEL: f32 = 0.15;
+
+// Sunsoft 5B audio, a YM2149F (AY-3-8910) clone with three square wave
+// tone channels. Only the tones are emulated. The noise and the envelope
+// generator are not used by Gimmick!, the only game with the chip.
+//     0-5: tone period of A, B and C, low 8 and high 4 bits
+//     7:   ..CBA...  tone disable
+//     8-A: volume of A, B and C
+struct Sunsoft5b {
+    register: Byte,
+    periods: [Word; 3],
+    timers: [Word; 3],
+    high: [bool; 3],
+    volumes: [Byte; 3],
+    tone_disable: Byte,
+    // the tone timers run at 1/16 of the CPU clock
+    divider: Byte,
+}
+
+impl Sunsoft5b {
+    fn new() -> Self {
+        Sunsoft5b {
+            register: 0,
+            periods: [0; 3],
+            timers: [0; 3],
+            high: [false; 3],
+            volumes: [0; 3],
+            tone_disable: 0xFF,
+            divider: 0,
+        }
+    }
+
+    fn select(&mut self, data: Byte) {
+        self.register = data & 0x0F;
+    }
+
+    fn writeb(&mut self, data: Byte) {
+        match self.register {
+            r @ 0..=5 => {
+                let channel = (r >> 1) as usize;
+                let period = self.periods[channel];
+                self.periods[channel] = if r & 0x01 == 0 {
+                    period & 0x0F00 | data as Word
+                } else {
+                    period & 0x00FF | ((data & 0x0F) as Word) << 8
+                };
+            },
+            7 => self.tone_disable = data,
+            r @ 8..=0xA => self.volumes[r as usize - 8] = data & 0x0F,
+            _ => { }
+        }
+    }
+
+    fn clock(&mut self) {
+        self.divider = (self.divider + 1) & 0x0F;
+        if self.divider != 0 {
+            return
+        }
+        for channel in 0..3 {
+            self.timers[channel] += 1;
+            if self.timers[channel] >= self.periods[channel] {
+                self.timers[channel] = 0;
+                self.high[channel] = !self.high[channel];
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        (0..3).filter(|&channel| self.tone_disable & (1 << channel) == 0 && self.high[channel])
+            .map(|channel| volume_level(self.volumes[channel]))
+            .sum()
+    }
+}
+
+// The volume steps are logarithmic, 3dB each. 0 is silent
+fn volume_level(volume: Byte) -> f32 {
+    if volume == 0 {
+        0.0
+    } else {
+        SUNSOFT_5B_LEVEL * 10f32.powf((volume as f32 - 15.0) * 3.0 / 20.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fme7() -> Fme7 {
+        Fme7::new(Layout { prg_rom_size: 0x40000, prg_ram_size: 0x2000, chr_size: 0x40000, submapper: 0 })
+    }
+
+    fn command(mapper: &mut Fme7, command: Byte, data: Byte) {
+        mapper.write(0x8000, command);
+        mapper.write(0xA000, data);
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = fme7();
+        command(&mut mapper, 0x09, 0x05);
+        command(&mut mapper, 0x0B, 0x07);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(5 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(7 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xE001), Mapped::PrgRom(31 * 0x2000 + 1));
+        command(&mut mapper, 0x05, 0x33);
+        assert_eq!(mapper.read_ppu(0x1401), Mapped::Chr(0x33 * 0x0400 + 1));
+
+        // ROM, disabled RAM and RAM at $6000
+        command(&mut mapper, 0x08, 0x02);
+        assert_eq!(mapper.read(0x6001), Mapped::PrgRom(2 * 0x2000 + 1));
+        assert_eq!(mapper.write(0x6001, 0x00), Mapped::None);
+        command(&mut mapper, 0x08, 0x40);
+        assert_eq!(mapper.read(0x6001), Mapped::None);
+        command(&mut mapper, 0x08, 0xC0);
+        assert_eq!(mapper.write(0x6001, 0x00), Mapped::PrgRam(1));
+
+        command(&mut mapper, 0x0C, 0x02);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::SingleScreenLower));
+
+        // boards without PRG RAM
+        let mut mapper = Fme7::new(Layout { prg_rom_size: 0x40000, prg_ram_size: 0, chr_size: 0x40000, submapper: 0 });
+        command(&mut mapper, 0x08, 0xC0);
+        assert_eq!(mapper.read(0x6001), Mapped::None);
+        assert_eq!(mapper.write(0x6001, 0x00), Mapped::None);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = fme7();
+        command(&mut mapper, 0x0E, 0x02);
+        command(&mut mapper, 0x0F, 0x00);
+        command(&mut mapper, 0x0D, 0x81);
+        for _ in 0..2 {
+            mapper.clock();
+        }
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+        command(&mut mapper, 0x0D, 0x80);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut audio = Sunsoft5b::new();
+        audio.select(0x00);
+        audio.writeb(0x01);
+        audio.select(0x08);
+        audio.writeb(0x0F);
+        // disabled
+        for _ in 0..16 {
+            audio.clock();
+        }
+        assert_eq!(audio.output(), 0.0);
+        audio.select(0x07);
+        audio.writeb(0x3E);
+        assert!((audio.output() - SUNSOFT_5B_LEVEL).abs() < 1e-6);
+        // half as loud 6dB lower
+        assert!((volume_level(13) / volume_level(15) - 0.5).abs() < 0.01);
+    }
+}
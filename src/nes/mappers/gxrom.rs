@@ -0,0 +1,68 @@
+use crate::nes::types::*;
+use crate::nes::mappers::{Mapper,Mapped,Layout};
+
+// Mapper 66 (GxROM: GNROM, MHROM)
+// Writing to 0x8000 - 0xffff:
+//     bits 0-1: 8K CHR ROM bank
+//     bits 4-5: 32K PRG ROM bank
+// The boards have bus conflicts.
+#[derive(Debug)]
+pub struct Gxrom {
+    prg_ram_size: usize,
+    bank: Byte,
+}
+
+impl Gxrom {
+    pub fn new(layout: Layout) -> Self {
+        Gxrom { prg_ram_size: layout.prg_ram_size, bank: 0 }
+    }
+}
+
+impl Mapper for Gxrom {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize % self.prg_ram_size),
+            0x8000..=0xFFFF => Mapped::PrgRom(((self.bank >> 4) & 0x03) as usize * 0x8000 + (addr & 0x7FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x8000..=0xFFFF => {
+                self.bank = data;
+                Mapped::Data(data)
+            },
+            _ => self.peek(addr),
+        }
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr((self.bank & 0x03) as usize * 0x2000 + addr as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gxrom() {
+        let layout = Layout { prg_rom_size: 0x20000, prg_ram_size: 0, chr_size: 0x8000, submapper: 0 };
+        let mut mapper = Gxrom::new(layout);
+        mapper.write(0x8000, 0x21);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(2 * 0x8000 + 1));
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x2000 + 1));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout};
+
+// Mapper 1 (MMC1, SxROM boards)
+// Registers are written serially: five writes to $8000-$FFFF shift in
+// bit 0, lsb first. The address of the fifth write selects the register:
+//     $8000-$9FFF: control   CPPMM  C: CHR mode, P: PRG mode, M: mirroring
+//     $A000-$BFFF: CHR bank 0
+//     $C000-$DFFF: CHR bank 1
+//     $E000-$FFFF: PRG bank  RPPPP  R: PRG RAM disable, P: 16K bank
+// A write with bit 7 set clears the shift register and sets PRG mode 3.
+//
+// Boards with 8K of CHR don't need the upper CHR bank bits and use them
+// for more PRG ROM and RAM:
+//     SNROM: bit 4 disables the PRG RAM
+//     SOROM: bit 3 selects one of two 8K PRG RAM banks
+//     SUROM: bit 4 selects the 256K half of a 512K PRG ROM
+//     SXROM: like SUROM, bits 2-3 select one of four 8K PRG RAM banks
+pub struct Mmc1 {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    // 8K CHR boards use the CHR registers for PRG banking
+    chr_8k: bool,
+    shift: Byte,
+    shift_count: u8,
+    control: Byte,
+    chr_bank: [Byte; 2],
+    prg_bank: Byte,
+    // CHR bank register the PPU uses right now. Chosen by A12 in 4K mode
+    chr_select: usize,
+    // CPU cycle count. Writes on the cycle after a write are ignored
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(layout: Layout) -> Self {
+        Mmc1 {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            chr_8k: layout.chr_size <= 0x2000,
+            shift: 0,
+            shift_count: 0,
+            // PRG mode 3 at power on, the reset vector is in the last bank
+            control: 0x0C,
+            chr_bank: [0, 0],
+            prg_bank: 0,
+            chr_select: 0,
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    fn write_register(&mut self, addr: Addr, data: Byte) {
+        match addr {
+            0x8000..=0x9FFF => self.control = data,
+            0xA000..=0xBFFF => self.chr_bank[0] = data,
+            0xC000..=0xDFFF => self.chr_bank[1] = data,
+            _ => self.prg_bank = data,
+        }
+    }
+
+    // CHR bank register that holds the PRG extension bits on 8K CHR boards
+    fn prg_extension(&self) -> Byte {
+        if self.control & 0x10 == 0 {
+            self.chr_bank[0]
+        } else {
+            self.chr_bank[self.chr_select]
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        if self.prg_ram_size == 0 || self.prg_bank & 0x10 != 0 {
+            return false
+        }
+        // SNROM
+        !(self.chr_8k && self.prg_rom_size <= 0x40000 && self.prg_ram_size <= 0x2000
+            && self.prg_extension() & 0x10 != 0)
+    }
+
+    fn prg_ram_bank(&self) -> usize {
+        if !self.chr_8k {
+            return 0
+        }
+        match self.prg_ram_size {
+            0x4000 => ((self.prg_extension() >> 3) & 0x01) as usize,  // SOROM
+            0x8000 => ((self.prg_extension() >> 2) & 0x03) as usize,  // SXROM
+            _ => 0,
+        }
+    }
+
+    fn prg_rom_index(&self, addr: Addr) -> usize {
+        // SUROM/SXROM: 256K outer bank
+        let outer = if self.chr_8k && self.prg_rom_size > 0x40000 {
+            (self.prg_extension() & 0x10) as usize
+        } else {
+            0
+        };
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let bank = match ((self.control >> 2) & 0x03, addr) {
+            // 32K, low bit ignored
+            (0, _) | (1, _) => (bank & 0x0E) | ((addr >> 14) & 0x01) as usize,
+            // first bank fixed at $8000
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            // last bank fixed at $C000
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => 0x0F,
+        };
+        (outer | bank) * 0x4000 + (addr & 0x3FFF) as usize
+    }
+
+    fn chr_index(&self, addr: Addr) -> usize {
+        if self.control & 0x10 == 0 {
+            // 8K
+            (self.chr_bank[0] & 0x1E) as usize * 0x1000 + (addr & 0x1FFF) as usize
+        } else {
+            let bank = self.chr_bank[(addr >> 12) as usize & 0x01];
+            bank as usize * 0x1000 + (addr & 0x0FFF) as usize
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() =>
+                Mapped::PrgRam(self.prg_ram_bank() * 0x2000 + (addr - 0x6000) as usize),
+            0x8000..=0xFFFF => Mapped::PrgRom(self.prg_rom_index(addr)),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        if addr < 0x8000 {
+            return self.peek(addr)
+        }
+        // the second write of read-modify-write instructions is ignored
+        let consecutive = self.last_write == Some(self.cycle.wrapping_sub(1));
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return Mapped::Data(data)
+        }
+
+        if data & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return Mapped::Data(data)
+        }
+        self.shift |= (data & 0x01) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+            self.write_register(addr, self.shift);
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(self.chr_index(addr)),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn ppu_bus(&mut self, addr: Addr) {
+        if addr < 0x2000 {
+            self.chr_select = (addr >> 12) as usize & 0x01;
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        Some(match self.control & 0x03 {
+            0 => MirrorMode::SingleScreenLower,
+            1 => MirrorMode::SingleScreenUpper,
+            2 => MirrorMode::Vertical,
+            _ => MirrorMode::Horizontal,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mmc1(prg_rom_size: usize, prg_ram_size: usize, chr_size: usize) -> Mmc1 {
+        Mmc1::new(Layout { prg_rom_size, prg_ram_size, chr_size, submapper: 0 })
+    }
+
+    // write a register with five serial writes on separate cycles
+    fn write_register(mapper: &mut Mmc1, addr: Addr, value: Byte) {
+        for i in 0..5 {
+            mapper.write(addr, (value >> i) & 0x01);
+            mapper.clock();
+            mapper.clock();
+        }
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut mapper = mmc1(0x40000, 0x2000, 0x20000);
+        write_register(&mut mapper, 0xE000, 0x05);
+        assert_eq!(mapper.prg_bank, 0x05);
+
+        // only the address of the fifth write counts
+        for (i, addr) in [0x8000, 0xA000, 0xC000, 0xE000, 0xA000].iter().enumerate() {
+            mapper.write(*addr, (0x13 >> i) & 0x01);
+            mapper.clock();
+            mapper.clock();
+        }
+        assert_eq!(mapper.chr_bank[0], 0x13);
+        assert_eq!(mapper.prg_bank, 0x05);
+
+        // bit 7 clears the shift register and sets PRG mode 3
+        write_register(&mut mapper, 0x8000, 0x00);
+        mapper.write(0xE000, 0x01);
+        mapper.clock();
+        mapper.clock();
+        mapper.write(0xE000, 0x80);
+        mapper.clock();
+        mapper.clock();
+        assert_eq!(mapper.control, 0x0C);
+        write_register(&mut mapper, 0xE000, 0x02);
+        assert_eq!(mapper.prg_bank, 0x02);
+    }
+
+    #[test]
+    fn test_consecutive_writes() {
+        let mut mapper = mmc1(0x40000, 0x2000, 0x20000);
+        // dummy write and write of a read-modify-write instruction on two
+        // cycles in a row: only the first counts
+        for i in 0..5 {
+            mapper.write(0xE000, 0x01);
+            mapper.clock();
+            mapper.write(0xE000, (0x0A >> i) & 0x01);
+            mapper.clock();
+            mapper.clock();
+        }
+        assert_eq!(mapper.prg_bank, 0x1F);
+        // writes on the same cycle (whole instruction at once) both count
+        let mut mapper = mmc1(0x40000, 0x2000, 0x20000);
+        for i in 0..5 {
+            mapper.write(0xE000, (0x0A >> i) & 0x01);
+        }
+        assert_eq!(mapper.prg_bank, 0x0A);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = mmc1(0x40000, 0x2000, 0x20000);
+        write_register(&mut mapper, 0xE000, 0x03);
+        // mode 3: switch $8000, last bank at $C000
+        assert_eq!(mapper.read(0x8000), Mapped::PrgRom(3 * 0x4000));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(15 * 0x4000 + 1));
+        // mode 2: first bank at $8000, switch $C000
+        write_register(&mut mapper, 0x8000, 0x08);
+        assert_eq!(mapper.read(0x8000), Mapped::PrgRom(0));
+        assert_eq!(mapper.read(0xC000), Mapped::PrgRom(3 * 0x4000));
+        // mode 0: 32K, low bit ignored
+        write_register(&mut mapper, 0x8000, 0x00);
+        assert_eq!(mapper.read(0x8000), Mapped::PrgRom(2 * 0x4000));
+        assert_eq!(mapper.read(0xC000), Mapped::PrgRom(3 * 0x4000));
+    }
+
+    #[test]
+    fn test_chr_modes_and_mirroring() {
+        let mut mapper = mmc1(0x40000, 0x2000, 0x20000);
+        write_register(&mut mapper, 0xA000, 0x05);
+        write_register(&mut mapper, 0xC000, 0x09);
+        // 8K mode, low bit ignored
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(4 * 0x1000 + 0x1001));
+        // 4K mode
+        write_register(&mut mapper, 0x8000, 0x10);
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(5 * 0x1000 + 1));
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(9 * 0x1000 + 1));
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::SingleScreenLower));
+        write_register(&mut mapper, 0x8000, 0x11);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::SingleScreenUpper));
+        write_register(&mut mapper, 0x8000, 0x12);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Vertical));
+        write_register(&mut mapper, 0x8000, 0x13);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Horizontal));
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = mmc1(0x40000, 0x2000, 0x20000);
+        assert_eq!(mapper.read(0x6001), Mapped::PrgRam(1));
+        write_register(&mut mapper, 0xE000, 0x10);
+        assert_eq!(mapper.read(0x6001), Mapped::None);
+
+        // SNROM: CHR bit 4 disables the RAM as well
+        let mut mapper = mmc1(0x40000, 0x2000, 0x2000);
+        write_register(&mut mapper, 0xA000, 0x10);
+        assert_eq!(mapper.read(0x6001), Mapped::None);
+
+        // SOROM: CHR bit 3 selects the RAM bank
+        let mut mapper = mmc1(0x40000, 0x4000, 0x2000);
+        write_register(&mut mapper, 0xA000, 0x08);
+        assert_eq!(mapper.read(0x6001), Mapped::PrgRam(0x2001));
+    }
+
+    #[test]
+    fn test_surom() {
+        let mut mapper = mmc1(0x80000, 0x2000, 0x2000);
+        write_register(&mut mapper, 0xE000, 0x01);
+        assert_eq!(mapper.read(0x8000), Mapped::PrgRom(0x4000));
+        assert_eq!(mapper.read(0xC000), Mapped::PrgRom(15 * 0x4000));
+        // second 256K
+        write_register(&mut mapper, 0xA000, 0x10);
+        assert_eq!(mapper.read(0x8000), Mapped::PrgRom(17 * 0x4000));
+        assert_eq!(mapper.read(0xC000), Mapped::PrgRom(31 * 0x4000));
+        // bit 4 selects the PRG half, the RAM stays on
+        assert_eq!(mapper.read(0x6000), Mapped::PrgRam(0));
+    }
+}
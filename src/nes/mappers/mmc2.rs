@@ -0,0 +1,165 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout,bank_from_end};
+
+// Mappers 9 (MMC2, PxROM) and 10 (MMC4, FxROM)
+//     $A000: PRG bank. MMC2: 8K at $8000, MMC4: 16K at $8000
+//     $B000: 4K CHR bank at $0000 when latch 0 is $FD
+//     $C000: 4K CHR bank at $0000 when latch 0 is $FE
+//     $D000: 4K CHR bank at $1000 when latch 1 is $FD
+//     $E000: 4K CHR bank at $1000 when latch 1 is $FE
+//     $F000: mirroring  V, H
+// The rest of the PRG ROM is fixed to the last banks.
+// The latches switch when the PPU fetches tile $FD or $FE: reading $0FD8,
+// $0FE8, $1FD8-$1FDF or $1FE8-$1FEF sets the latch for the following
+// fetches. The MMC4 also takes $0FD8-$0FDF and $0FE8-$0FEF.
+pub struct Mmc2 {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    mmc4: bool,
+    prg_bank: Byte,
+    chr_banks: [[Byte; 2]; 2],
+    // false: $FD, true: $FE
+    latches: [bool; 2],
+    mirror: MirrorMode,
+}
+
+impl Mmc2 {
+    pub fn new(id: u16, layout: Layout) -> Self {
+        Mmc2 {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            mmc4: id == 10,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [false; 2],
+            mirror: MirrorMode::Vertical,
+        }
+    }
+
+    fn prg_rom_index(&self, addr: Addr) -> usize {
+        if self.mmc4 {
+            match addr {
+                0x8000..=0xBFFF => self.prg_bank as usize * 0x4000 + (addr & 0x3FFF) as usize,
+                _ => bank_from_end(self.prg_rom_size, 0x4000, 0) * 0x4000 + (addr & 0x3FFF) as usize,
+            }
+        } else {
+            match addr {
+                0x8000..=0x9FFF => self.prg_bank as usize * 0x2000 + (addr & 0x1FFF) as usize,
+                // the last three 8K banks
+                _ => {
+                    let bank = bank_from_end(self.prg_rom_size, 0x2000, 7 - (addr >> 13) as usize);
+                    bank * 0x2000 + (addr & 0x1FFF) as usize
+                },
+            }
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize),
+            0x8000..=0xFFFF => Mapped::PrgRom(self.prg_rom_index(addr)),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xEFFF => {
+                let register = ((addr >> 12) - 0xB) as usize;
+                self.chr_banks[register >> 1][register & 0x01] = data & 0x1F;
+            },
+            0xF000..=0xFFFF => {
+                self.mirror = if data & 0x01 == 0 { MirrorMode::Vertical } else { MirrorMode::Horizontal };
+            },
+            0x8000..=0x9FFF => { },
+            _ => return self.peek(addr),
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => {
+                let half = (addr >> 12) as usize;
+                let bank = self.chr_banks[half][self.latches[half] as usize] as usize;
+                Mapped::Chr(bank * 0x1000 + (addr & 0x0FFF) as usize)
+            },
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn ppu_bus(&mut self, addr: Addr) {
+        match addr {
+            0x0FD8 => self.latches[0] = false,
+            0x0FE8 => self.latches[0] = true,
+            0x0FD9..=0x0FDF if self.mmc4 => self.latches[0] = false,
+            0x0FE9..=0x0FEF if self.mmc4 => self.latches[0] = true,
+            0x1FD8..=0x1FDF => self.latches[1] = false,
+            0x1FE8..=0x1FEF => self.latches[1] = true,
+            _ => { }
+        }
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        Some(self.mirror)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mmc2(id: u16) -> Mmc2 {
+        Mmc2::new(id, Layout { prg_rom_size: 0x20000, prg_ram_size: 0, chr_size: 0x20000, submapper: 0 })
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = mmc2(9);
+        mapper.write(0xA000, 0x03);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(3 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xA001), Mapped::PrgRom(13 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xFFFC), Mapped::PrgRom(0x1FFFC));
+
+        let mut mapper = mmc2(10);
+        mapper.write(0xA000, 0x03);
+        assert_eq!(mapper.read(0xBFFF), Mapped::PrgRom(4 * 0x4000 - 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(7 * 0x4000 + 1));
+    }
+
+    #[test]
+    fn test_latches() {
+        let mut mapper = mmc2(9);
+        mapper.write(0xB000, 0x01);
+        mapper.write(0xC000, 0x02);
+        mapper.write(0xD000, 0x03);
+        mapper.write(0xE000, 0x04);
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x1001));
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(0x3001));
+        // the fetch of the latch tile still uses the old bank
+        assert_eq!(mapper.read_ppu(0x0FE8), Mapped::Chr(0x1FE8));
+        mapper.ppu_bus(0x0FE8);
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x2001));
+        mapper.ppu_bus(0x1FEA);
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(0x4001));
+        // only $0FD8 exactly on the MMC2
+        mapper.ppu_bus(0x0FDA);
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x2001));
+
+        let mut mapper = mmc2(10);
+        mapper.write(0xB000, 0x01);
+        mapper.ppu_bus(0x0FEA);
+        mapper.ppu_bus(0x0FDA);
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x1001));
+        mapper.write(0xF000, 0x01);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Horizontal));
+    }
+}
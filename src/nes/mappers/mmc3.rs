@@ -0,0 +1,325 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout,bank_from_end};
+
+// Mapper 4 (MMC3, TxROM boards)
+// Registers, even and odd addresses of each 8K range:
+//     $8000: bank select    CP...RRR  C: CHR A12 inversion, P: PRG mode,
+//                                     R: bank register for $8001
+//     $8001: bank data
+//     $A000: mirroring      0: vertical, 1: horizontal
+//     $A001: PRG RAM        EW......  E: enable, W: write protect
+//     $C000: IRQ latch
+//     $C001: IRQ reload     the counter is reloaded on its next clock
+//     $E000: IRQ disable    also acknowledges a pending IRQ
+//     $E001: IRQ enable
+//
+// PRG (8K banks, -1 is the last bank):
+//     mode 0: $8000 R6, $A000 R7, $C000 -2, $E000 -1
+//     mode 1: $8000 -2, $A000 R7, $C000 R6, $E000 -1
+// CHR (1K banks, halves swapped with inversion):
+//     $0000 R0 (2K), $0800 R1 (2K), $1000 R2, $1400 R3, $1800 R4, $1C00 R5
+//
+// The IRQ counter is clocked by rising edges of PPU A12. Like the real chip
+// an edge only counts if A12 was low for a few CPU cycles, so the
+// background fetches from $1000 don't clock it eight times per scanline.
+pub struct Mmc3 {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    revision: Revision,
+    bank_select: Byte,
+    banks: [Byte; 8],
+    mirror: MirrorMode,
+    prg_ram_control: Byte,
+    irq_latch: Byte,
+    irq_counter: Byte,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    // A12 filter
+    a12: bool,
+    a12_low_cycle: u64,
+    cycle: u64,
+}
+
+// CPU cycles A12 has to stay low before a rise clocks the counter
+const A12_FILTER_CYCLES: u64 = 3;
+
+// The chip revisions differ in when a counter of 0 raises the IRQ
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Revision {
+    // MMC3B/C: every clock that leaves the counter at 0
+    Sharp,
+    // MMC3A: only a decrement to 0 or a forced reload. A latch of 0
+    // raises the IRQ once instead of every scanline
+    Nec,
+}
+
+impl Mmc3 {
+    // NES 2.0 submapper 4 is the MMC3A
+    pub fn new(layout: Layout) -> Self {
+        Mmc3 {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            revision: if layout.submapper == 4 { Revision::Nec } else { Revision::Sharp },
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirror: MirrorMode::Vertical,
+            prg_ram_control: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            a12: false,
+            a12_low_cycle: 0,
+            cycle: 0,
+        }
+    }
+
+    fn prg_rom_index(&self, addr: Addr) -> usize {
+        let second_last = bank_from_end(self.prg_rom_size, 0x2000, 1);
+        let swap = self.bank_select & 0x40 != 0;
+        let bank = match (addr >> 13) & 0x03 {
+            0 if swap => second_last,
+            0 => self.banks[6] as usize,
+            1 => self.banks[7] as usize,
+            2 if swap => self.banks[6] as usize,
+            2 => second_last,
+            _ => bank_from_end(self.prg_rom_size, 0x2000, 0),
+        };
+        bank * 0x2000 + (addr & 0x1FFF) as usize
+    }
+
+    fn chr_index(&self, addr: Addr) -> usize {
+        // inversion flips A12
+        let addr = if self.bank_select & 0x80 != 0 { addr ^ 0x1000 } else { addr };
+        let bank = match addr >> 10 {
+            0 | 1 => (self.banks[0] & 0xFE) as usize + (addr >> 10) as usize,
+            2 | 3 => (self.banks[1] & 0xFE) as usize + (addr >> 10) as usize - 2,
+            n => self.banks[n as usize - 2] as usize,
+        };
+        bank * 0x0400 + (addr & 0x03FF) as usize
+    }
+
+    fn prg_ram(&self, addr: Addr, write: bool) -> Mapped {
+        let enabled = self.prg_ram_size > 0 && self.prg_ram_control & 0x80 != 0;
+        let protected = write && self.prg_ram_control & 0x40 != 0;
+        if enabled && !protected {
+            Mapped::PrgRam((addr - 0x6000) as usize)
+        } else {
+            Mapped::None
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let old = self.irq_counter;
+        let reload = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+        let trigger = match self.revision {
+            Revision::Sharp => self.irq_counter == 0,
+            Revision::Nec => self.irq_counter == 0 && (old != 0 || reload),
+        };
+        if trigger && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram(addr, false),
+            0x8000..=0xFFFF => Mapped::PrgRom(self.prg_rom_index(addr)),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match (addr & 0xE001, addr) {
+            (_, 0x6000..=0x7FFF) => return self.prg_ram(addr, true),
+            (0x8000, _) => self.bank_select = data,
+            (0x8001, _) => self.banks[(self.bank_select & 0x07) as usize] = data,
+            (0xA000, _) => {
+                self.mirror = if data & 0x01 == 0 { MirrorMode::Vertical } else { MirrorMode::Horizontal };
+            },
+            (0xA001, _) => self.prg_ram_control = data,
+            (0xC000, _) => self.irq_latch = data,
+            (0xC001, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            },
+            (0xE000, _) => {
+                self.irq_enabled = false;
+                self.irq = false;
+            },
+            (0xE001, _) => self.irq_enabled = true,
+            _ => return Mapped::None,
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(self.chr_index(addr)),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn ppu_bus(&mut self, addr: Addr) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycle - self.a12_low_cycle >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        } else if !a12 && self.a12 {
+            self.a12_low_cycle = self.cycle;
+        }
+        self.a12 = a12;
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        Some(self.mirror)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mmc3(submapper: Byte) -> Mmc3 {
+        Mmc3::new(Layout { prg_rom_size: 0x40000, prg_ram_size: 0x2000, chr_size: 0x40000, submapper })
+    }
+
+    // a scanline of sprite fetches from $1000 between background fetches
+    // from $0000
+    fn scanline(mapper: &mut Mmc3) {
+        for _ in 0..100 {
+            mapper.ppu_bus(0x0000);
+            mapper.clock();
+        }
+        mapper.ppu_bus(0x1000);
+        mapper.clock();
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = mmc3(0);
+        mapper.write(0x8000, 0x06);
+        mapper.write(0x8001, 0x03);
+        mapper.write(0x8000, 0x07);
+        mapper.write(0x8001, 0x05);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(3 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xA001), Mapped::PrgRom(5 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(30 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xE001), Mapped::PrgRom(31 * 0x2000 + 1));
+        // PRG mode 1 swaps $8000 and $C000
+        mapper.write(0x8000, 0x46);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(30 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(3 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xE001), Mapped::PrgRom(31 * 0x2000 + 1));
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = mmc3(0);
+        for (i, bank) in [0x11, 0x20, 0x30, 0x31, 0x32, 0x33].iter().enumerate() {
+            mapper.write(0x8000, i as Byte);
+            mapper.write(0x8001, *bank);
+        }
+        // 2K banks ignore the low bit
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x10 * 0x400 + 1));
+        assert_eq!(mapper.read_ppu(0x0401), Mapped::Chr(0x11 * 0x400 + 1));
+        assert_eq!(mapper.read_ppu(0x0C01), Mapped::Chr(0x21 * 0x400 + 1));
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(0x30 * 0x400 + 1));
+        assert_eq!(mapper.read_ppu(0x1C01), Mapped::Chr(0x33 * 0x400 + 1));
+        // inversion
+        mapper.write(0x8000, 0x80);
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x30 * 0x400 + 1));
+        assert_eq!(mapper.read_ppu(0x1401), Mapped::Chr(0x11 * 0x400 + 1));
+        assert_eq!(mapper.read_ppu(0x1801), Mapped::Chr(0x20 * 0x400 + 1));
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram() {
+        let mut mapper = mmc3(0);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Vertical));
+        mapper.write(0xA000, 0x01);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Horizontal));
+
+        assert_eq!(mapper.write(0x6001, 0x12), Mapped::PrgRam(1));
+        mapper.write(0xA001, 0xC0);
+        assert_eq!(mapper.read(0x6001), Mapped::PrgRam(1));
+        assert_eq!(mapper.write(0x6001, 0x12), Mapped::None);
+        mapper.write(0xA001, 0x00);
+        assert_eq!(mapper.read(0x6001), Mapped::None);
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut mapper = mmc3(0);
+        mapper.write(0xC000, 2);
+        mapper.write(0xC001, 0);
+        mapper.write(0xE001, 0);
+        // reload to 2, then 1, then 0
+        scanline(&mut mapper);
+        scanline(&mut mapper);
+        assert!(!mapper.irq());
+        scanline(&mut mapper);
+        assert!(mapper.irq());
+        // acknowledge and disable
+        mapper.write(0xE000, 0);
+        assert!(!mapper.irq());
+        for _ in 0..3 {
+            scanline(&mut mapper);
+        }
+        assert!(!mapper.irq());
+
+        // rises of A12 in quick succession are filtered
+        mapper.write(0xC001, 0);
+        mapper.write(0xE001, 0);
+        scanline(&mut mapper);
+        for _ in 0..16 {
+            mapper.ppu_bus(0x0000);
+            mapper.ppu_bus(0x1000);
+            mapper.clock();
+        }
+        assert_eq!(mapper.irq_counter, 2);
+    }
+
+    #[test]
+    fn test_irq_revisions() {
+        // a latch of 0 raises the IRQ on every scanline
+        let mut sharp = mmc3(0);
+        let mut nec = mmc3(4);
+        assert_eq!(nec.revision, Revision::Nec);
+        for mapper in [&mut sharp, &mut nec].iter_mut() {
+            mapper.write(0xC000, 0);
+            mapper.write(0xC001, 0);
+            mapper.write(0xE001, 0);
+            scanline(mapper);
+            assert!(mapper.irq());
+            mapper.write(0xE000, 0);
+            mapper.write(0xE001, 0);
+            scanline(mapper);
+        }
+        // but only once on the NEC MMC3A
+        assert!(sharp.irq());
+        assert!(!nec.irq());
+    }
+}
@@ -0,0 +1,685 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout};
+use crate::nes::apu::pulse::{Pulse,Channel};
+use crate::nes::apu::mixer;
+
+// Mapper 5 (MMC5, ExROM)
+//     $5000-$5015: audio, two pulses and a PCM channel
+//     $5100: PRG mode  0: 32K, 1: 16K+16K, 2: 16K+8K+8K, 3: 8K each
+//     $5101: CHR mode  0: 8K, 1: 4K, 2: 2K, 3: 1K banks
+//     $5102, $5103: PRG RAM write protect, writable with 2 and 1
+//     $5104: ExRAM mode  0: nametable, 1: extended attributes,
+//                        2: RAM, 3: read only RAM
+//     $5105: nametable mapping  DDCCBBAA  0: VRAM page 0, 1: VRAM page 1,
+//                               2: ExRAM, 3: fill mode
+//     $5106, $5107: fill mode tile and attribute
+//     $5113: PRG RAM bank at $6000
+//     $5114-$5117: PRG banks, bit 7 selects ROM. $5117 is always ROM
+//     $5120-$5127: CHR banks A, sprites (and everything with 8x8 sprites)
+//     $5128-$512B: CHR banks B, background with 8x16 sprites
+//     $5130: upper CHR bank bits
+//     $5200-$5202: vertical split  ER.TTTTT  E: enable, R: right side,
+//                  T: tile. Then scroll and 4K CHR bank of the split
+//     $5203: IRQ scanline, $5204: IRQ enable (write), status (read)
+//     $5205, $5206: 8x8 bit multiplier
+//     $5C00-$5FFF: 1K ExRAM
+//
+// The MMC5 follows the PPU by watching its bus: the two identical
+// nametable fetches at the end of every rendered line start the next
+// one, the fetches after the attribute byte are background patterns and
+// pattern fetches after a plain nametable fetch are sprites. It doesn't
+// see the PPU registers, but the CPU writes to them.
+pub struct Mmc5 {
+    prg_ram_size: usize,
+    prg_mode: Byte,
+    chr_mode: Byte,
+    prg_ram_protect: [Byte; 2],
+    exram_mode: Byte,
+    nametables: Byte,
+    fill_tile: Byte,
+    fill_attrib: Byte,
+    // $5113-$5117
+    prg_banks: [Byte; 5],
+    // $5120-$512B with the upper bits
+    chr_banks: [Word; 12],
+    chr_upper: Byte,
+    // the last CHR register written was from set B
+    chr_set_b: bool,
+    split_control: Byte,
+    split_scroll: Byte,
+    split_bank: Byte,
+    irq_scanline: Byte,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplier: [Byte; 2],
+    exram: [Byte; EXRAM_SIZE],
+    // from the PPU registers
+    sprite_8x16: bool,
+    // scanline detection
+    in_frame: bool,
+    scanline: Byte,
+    last_ppu_addr: Addr,
+    nametable_repeats: u8,
+    last_ppu_cycle: u64,
+    cycle: u64,
+    fetch: Fetch,
+    // x of the next background tile. Tiles 0-2 are fetched at the end of
+    // the previous line
+    tile_x: usize,
+    prefetch: bool,
+    // what the last background nametable fetch found
+    tile: TileFetch,
+    audio: Mmc5Audio,
+}
+
+const EXRAM_SIZE: usize = 1024;
+// CPU cycles without PPU reads that end the frame
+const FRAME_TIMEOUT: u64 = 3;
+
+// What the PPU fetched last
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Fetch {
+    // CPU access or not rendering
+    Other,
+    Nametable,
+    Attribute,
+    Background,
+    Sprite,
+}
+
+// A background tile from the split or with an extended attribute
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum TileFetch {
+    Normal,
+    // ExRAM byte of the tile: palette and 4K CHR bank
+    Extended(Byte),
+    Split { tile: Byte, row: usize, x: usize },
+}
+
+impl Mmc5 {
+    pub fn new(layout: Layout) -> Self {
+        Mmc5 {
+            prg_ram_size: layout.prg_ram_size,
+            prg_mode: 3,
+            chr_mode: 3,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attrib: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_set_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplier: [0xFF; 2],
+            exram: [0; EXRAM_SIZE],
+            sprite_8x16: false,
+            in_frame: false,
+            scanline: 0,
+            last_ppu_addr: 0,
+            nametable_repeats: 0,
+            last_ppu_cycle: 0,
+            cycle: 0,
+            fetch: Fetch::Other,
+            tile_x: 0,
+            prefetch: false,
+            tile: TileFetch::Normal,
+            audio: Mmc5Audio::new(),
+        }
+    }
+
+    // PRG register and bank size in 8K banks for a CPU address
+    fn prg_register(&self, addr: Addr) -> (Byte, usize) {
+        match (self.prg_mode, addr) {
+            (0, _) => (self.prg_banks[4], 4),
+            (1, 0x8000..=0xBFFF) => (self.prg_banks[2], 2),
+            (1, _) => (self.prg_banks[4], 2),
+            (2, 0x8000..=0xBFFF) => (self.prg_banks[2], 2),
+            (2, 0xC000..=0xDFFF) => (self.prg_banks[3], 1),
+            (2, _) => (self.prg_banks[4], 1),
+            (_, _) => (self.prg_banks[((addr - 0x6000) >> 13) as usize], 1),
+        }
+    }
+
+    fn prg_map(&self, addr: Addr) -> Mapped {
+        let (bank, size) = match addr {
+            0x6000..=0x7FFF => (self.prg_banks[0] & 0x0F, 1),
+            _ => self.prg_register(addr),
+        };
+        // bigger banks take the low bits of the 8K bank from addr
+        let index = |bank: usize| {
+            (bank & !(size - 1) | (addr >> 13) as usize & (size - 1)) * 0x2000 + (addr & 0x1FFF) as usize
+        };
+        let rom = addr >= 0xE000 || (addr >= 0x8000 && bank & 0x80 != 0);
+        if rom {
+            Mapped::PrgRom(index((bank & 0x7F) as usize))
+        } else if self.prg_ram_size > 0 {
+            Mapped::PrgRam(index((bank & 0x0F) as usize))
+        } else {
+            Mapped::None
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect[0] & 0x03 == 0x02 && self.prg_ram_protect[1] & 0x03 == 0x01
+    }
+
+    // Kind of pattern fetch the PPU does next
+    fn pattern_fetch(&self) -> Fetch {
+        if !self.in_frame {
+            return Fetch::Other
+        }
+        match self.fetch {
+            Fetch::Attribute | Fetch::Background => Fetch::Background,
+            Fetch::Nametable | Fetch::Sprite => Fetch::Sprite,
+            Fetch::Other => Fetch::Other,
+        }
+    }
+
+    fn chr_index(&self, addr: Addr, fetch: Fetch) -> usize {
+        let set_b = match fetch {
+            Fetch::Background if self.sprite_8x16 => true,
+            Fetch::Sprite if self.sprite_8x16 => false,
+            _ => self.chr_set_b,
+        };
+        let r = &self.chr_banks;
+        let addr = addr as usize;
+        let (bank, size) = match (self.chr_mode, set_b) {
+            (0, false) => (r[7], 0x2000),
+            (1, false) => (r[3 + (addr >> 12) * 4], 0x1000),
+            (2, false) => (r[1 + (addr >> 11) * 2], 0x0800),
+            (_, false) => (r[addr >> 10], 0x0400),
+            // set B is the same for both pattern tables
+            (0, true) | (1, true) => (r[11], if self.chr_mode == 0 { 0x2000 } else { 0x1000 }),
+            (2, true) => (r[9 + ((addr >> 11) & 0x01) * 2], 0x0800),
+            (_, true) => (r[8 + ((addr >> 10) & 0x03)], 0x0400),
+        };
+        bank as usize * size + (addr & (size - 1))
+    }
+
+    fn split_active(&self, x: usize) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return false
+        }
+        let tile = (self.split_control & 0x1F) as usize;
+        if self.split_control & 0x40 == 0 {
+            x < tile
+        } else {
+            x >= tile
+        }
+    }
+
+    // row of the split at the current scanline. The prefetched tiles
+    // belong to the next line
+    fn split_row(&self) -> usize {
+        let line = self.scanline as usize + self.prefetch as usize;
+        (line + self.split_scroll as usize) % 240
+    }
+
+    // Nametable fetch of the PPU or a CPU access of the nametables
+    fn read_nametable(&self, addr: Addr) -> Mapped {
+        let attribute = addr & 0x03FF >= 0x03C0;
+        if self.in_frame && attribute {
+            match self.tile {
+                TileFetch::Split { row, x, .. } => {
+                    let attrib = self.exram[0x3C0 + (row / 32) * 8 + x / 4];
+                    let shift = ((row / 16) & 0x01) * 4 + ((x / 2) & 0x01) * 2;
+                    return Mapped::Data(((attrib >> shift) & 0x03) * 0x55)
+                },
+                TileFetch::Extended(ex) => return Mapped::Data((ex >> 6) * 0x55),
+                TileFetch::Normal => { },
+            }
+        } else if self.in_frame && self.split_active(self.tile_x) {
+            let row = self.split_row();
+            return Mapped::Data(self.exram[(row / 8) * 32 + self.tile_x % 32])
+        }
+        match (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+            2 if self.exram_mode <= 1 => Mapped::Data(self.exram[(addr & 0x03FF) as usize]),
+            2 => Mapped::Data(0),
+            3 if attribute => Mapped::Data((self.fill_attrib & 0x03) * 0x55),
+            3 => Mapped::Data(self.fill_tile),
+            // VRAM, see mirror_mode
+            _ => Mapped::None,
+        }
+    }
+
+    // A new scanline starts. The PPU has fetched the first three tiles
+    fn next_scanline(&mut self) {
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+        } else {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_scanline {
+                self.irq_pending = true;
+            }
+        }
+        self.tile_x = 3;
+        self.prefetch = false;
+    }
+
+    fn end_frame(&mut self) {
+        self.in_frame = false;
+        self.fetch = Fetch::Other;
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x5015 => Mapped::Data(self.audio.status()),
+            0x5204 => Mapped::Data((self.irq_pending as Byte) << 7 | (self.in_frame as Byte) << 6),
+            0x5205 => Mapped::Data((self.multiplier[0] as Word * self.multiplier[1] as Word) as Byte),
+            0x5206 => Mapped::Data(((self.multiplier[0] as Word * self.multiplier[1] as Word) >> 8) as Byte),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Mapped::Data(self.exram[(addr - 0x5C00) as usize]),
+            0x6000..=0xFFFF => self.prg_map(addr),
+            _ => Mapped::None,
+        }
+    }
+
+    fn read(&mut self, addr: Addr) -> Mapped {
+        let mapped = self.peek(addr);
+        // reading the status acknowledges the IRQ
+        if addr == 0x5204 {
+            self.irq_pending = false;
+        }
+        mapped
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x5000..=0x5015 => self.audio.writeb(addr, data),
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 | 0x5103 => self.prg_ram_protect[(addr - 0x5102) as usize] = data,
+            0x5104 => self.exram_mode = data & 0x03,
+            0x5105 => self.nametables = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attrib = data,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512B => {
+                self.chr_banks[(addr - 0x5120) as usize] = data as Word | ((self.chr_upper & 0x03) as Word) << 8;
+                self.chr_set_b = addr >= 0x5128;
+            },
+            0x5130 => self.chr_upper = data,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_scanline = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 | 0x5206 => self.multiplier[(addr - 0x5205) as usize] = data,
+            0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[(addr - 0x5C00) as usize] = data,
+            0x6000..=0xFFFF => {
+                return match self.prg_map(addr) {
+                    Mapped::PrgRam(index) if self.prg_ram_writable() => Mapped::PrgRam(index),
+                    _ => Mapped::None,
+                }
+            },
+            _ => return Mapped::None,
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => {
+                let fetch = self.pattern_fetch();
+                match (fetch, self.tile) {
+                    (Fetch::Background, TileFetch::Split { tile, row, .. }) => {
+                        let index = self.split_bank as usize * 0x1000 + (tile as usize) * 16
+                            + (addr & 0x08) as usize + row % 8;
+                        Mapped::Chr(index)
+                    },
+                    (Fetch::Background, TileFetch::Extended(ex)) => {
+                        let bank = (ex & 0x3F) as usize | ((self.chr_upper & 0x03) as usize) << 6;
+                        Mapped::Chr(bank * 0x1000 + (addr & 0x0FFF) as usize)
+                    },
+                    _ => Mapped::Chr(self.chr_index(addr, fetch)),
+                }
+            },
+            0x2000..=0x3EFF => self.read_nametable(addr),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(self.chr_index(addr, Fetch::Other)),
+            0x2000..=0x3EFF => match (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+                2 => {
+                    if self.exram_mode <= 1 {
+                        self.exram[(addr & 0x03FF) as usize] = data;
+                    }
+                    Mapped::Data(data)
+                },
+                3 => Mapped::Data(data),
+                _ => Mapped::None,
+            },
+            _ => Mapped::None,
+        }
+    }
+
+    fn ppu_bus(&mut self, addr: Addr) {
+        let repeated = addr == self.last_ppu_addr;
+        self.last_ppu_addr = addr;
+        self.last_ppu_cycle = self.cycle;
+        match addr {
+            0x2000..=0x2FFF if addr & 0x03FF >= 0x03C0 => self.fetch = Fetch::Attribute,
+            0x2000..=0x2FFF => {
+                // the tile fetch at dot 337 is repeated twice
+                if repeated && self.fetch == Fetch::Nametable {
+                    self.nametable_repeats += 1;
+                    if self.nametable_repeats == 1 {
+                        self.next_scanline();
+                    }
+                } else {
+                    self.nametable_repeats = 0;
+                    if self.in_frame {
+                        // a background tile, or the garbage fetch before
+                        // the sprites
+                        let x = self.tile_x;
+                        self.tile = if self.split_active(x) {
+                            let row = self.split_row();
+                            TileFetch::Split { tile: self.exram[(row / 8) * 32 + x % 32], row, x }
+                        } else if self.exram_mode == 1 {
+                            TileFetch::Extended(self.exram[(addr & 0x03FF) as usize])
+                        } else {
+                            TileFetch::Normal
+                        };
+                        self.tile_x += 1;
+                    }
+                }
+                self.fetch = Fetch::Nametable;
+            },
+            0x0000..=0x1FFF => {
+                self.fetch = match self.fetch {
+                    Fetch::Attribute | Fetch::Background => Fetch::Background,
+                    Fetch::Nametable => {
+                        // sprites, then the first tiles of the next line
+                        self.tile_x = 0;
+                        self.prefetch = true;
+                        Fetch::Sprite
+                    },
+                    fetch => fetch,
+                };
+            },
+            _ => { }
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: Addr, data: Byte) {
+        match addr {
+            0x2000 => self.sprite_8x16 = data & 0x20 != 0,
+            0x2001 if data & 0x18 == 0 => self.end_frame(),
+            _ => { }
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+        if self.in_frame && self.cycle - self.last_ppu_cycle > FRAME_TIMEOUT {
+            self.end_frame();
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        let n = self.nametables;
+        Some(MirrorMode::Custom([n & 0x01, (n >> 2) & 0x01, (n >> 4) & 0x01, (n >> 6) & 0x01]))
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+// CPU cycles of the 240Hz frame sequencer
+const AUDIO_FRAME_CYCLES: u16 = 7457;
+
+// Two pulse channels like the ones of the APU, without sweep, and a raw
+// PCM channel. Their envelopes and length counters are clocked at a fixed
+// 240Hz. The PCM read mode (samples fetched from $8000-$BFFF) isn't
+// supported.
+struct Mmc5Audio {
+    pulses: [Pulse; 2],
+    pcm: Byte,
+    divider: u16,
+    cycles: u64,
+}
+
+impl Mmc5Audio {
+    fn new() -> Self {
+        Mmc5Audio {
+            pulses: [Pulse::new(Channel::Mmc5), Pulse::new(Channel::Mmc5)],
+            pcm: 0,
+            divider: 0,
+            cycles: 0,
+        }
+    }
+
+    fn writeb(&mut self, addr: Addr, data: Byte) {
+        match addr {
+            // no sweep unit at $5001 and $5005
+            0x5001 | 0x5005 => { },
+            0x5000..=0x5003 => self.pulses[0].writeb(addr & 0x03, data),
+            0x5004..=0x5007 => self.pulses[1].writeb(addr & 0x03, data),
+            // writing 0 is ignored
+            0x5011 if data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulses[0].length.set_enabled(data & 0x01 != 0);
+                self.pulses[1].length.set_enabled(data & 0x02 != 0);
+            },
+            _ => { }
+        }
+    }
+
+    fn status(&self) -> Byte {
+        self.pulses[0].length.is_active() as Byte | (self.pulses[1].length.is_active() as Byte) << 1
+    }
+
+    fn clock(&mut self) {
+        if self.cycles & 0x01 == 1 {
+            self.pulses[0].clock_timer();
+            self.pulses[1].clock_timer();
+        }
+        self.cycles += 1;
+        self.divider += 1;
+        if self.divider == AUDIO_FRAME_CYCLES {
+            self.divider = 0;
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+    }
+
+    // mixed like the APU channels, the PCM channel in place of the DMC
+    fn output(&self) -> f32 {
+        mixer::mix(self.pulses[0].output(), self.pulses[1].output(), 0, 0, self.pcm >> 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mmc5() -> Mmc5 {
+        Mmc5::new(Layout { prg_rom_size: 0x80000, prg_ram_size: 0x10000, chr_size: 0x80000, submapper: 0 })
+    }
+
+    // sprite fetch, then the third tile fetch and two dummy fetches at
+    // the end of a line
+    fn end_of_line(mapper: &mut Mmc5) {
+        mapper.ppu_bus(0x1FF0);
+        for _ in 0..3 {
+            mapper.ppu_bus(0x2002);
+        }
+        mapper.clock();
+    }
+
+    // fetches of one background tile
+    fn tile(mapper: &mut Mmc5, nametable: Addr) -> (Mapped, Mapped, Mapped) {
+        let nt = mapper.read_ppu(nametable);
+        mapper.ppu_bus(nametable);
+        let at = mapper.read_ppu(0x23C0);
+        mapper.ppu_bus(0x23C0);
+        let pattern = mapper.read_ppu(0x1003);
+        mapper.ppu_bus(0x1003);
+        (nt, at, pattern)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = mmc5();
+        // mode 3 at power on, $5117 is the last bank (modulo the ROM size)
+        assert_eq!(mapper.read(0xFFFC), Mapped::PrgRom(0x7F * 0x2000 + 0x1FFC));
+        mapper.write(0x5114, 0x81);
+        mapper.write(0x5115, 0x02);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(0x2001));
+        assert_eq!(mapper.read(0xA001), Mapped::PrgRam(2 * 0x2000 + 1));
+        // mode 1: 16K banks ignore the low bit
+        mapper.write(0x5100, 0x01);
+        mapper.write(0x5115, 0x85);
+        mapper.write(0x5117, 0x87);
+        assert_eq!(mapper.read(0xA001), Mapped::PrgRom(5 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(6 * 0x2000 + 1));
+        // mode 0: 32K
+        mapper.write(0x5100, 0x00);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(4 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xE001), Mapped::PrgRom(7 * 0x2000 + 1));
+
+        // PRG RAM is write protected until $5102/$5103 are 2 and 1
+        mapper.write(0x5113, 0x03);
+        assert_eq!(mapper.read(0x6001), Mapped::PrgRam(3 * 0x2000 + 1));
+        assert_eq!(mapper.write(0x6001, 0x00), Mapped::None);
+        mapper.write(0x5102, 0x02);
+        mapper.write(0x5103, 0x01);
+        assert_eq!(mapper.write(0x6001, 0x00), Mapped::PrgRam(3 * 0x2000 + 1));
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mapper = mmc5();
+        mapper.write(0x5130, 0x01);
+        for i in 0..12 {
+            mapper.write(0x5120 + i, 0x10 + i as Byte);
+        }
+        // with 8x8 sprites the last written set is used
+        assert_eq!(mapper.read_ppu(0x0401), Mapped::Chr(0x119 * 0x0400 + 1));
+        mapper.write(0x5127, 0x17);
+        assert_eq!(mapper.read_ppu(0x1C01), Mapped::Chr(0x117 * 0x0400 + 1));
+        mapper.write(0x5101, 0x01);
+        assert_eq!(mapper.read_ppu(0x1001), Mapped::Chr(0x117 * 0x1000 + 1));
+
+        // 8x16 sprites: background fetches use set B
+        mapper.write(0x5101, 0x03);
+        mapper.ppu_register_write(0x2000, 0x20);
+        end_of_line(&mut mapper);
+        let (_, _, pattern) = tile(&mut mapper, 0x2000);
+        assert_eq!(pattern, Mapped::Chr(0x118 * 0x0400 + 3));
+        mapper.ppu_bus(0x2000);
+        assert_eq!(mapper.read_ppu(0x1003), Mapped::Chr(0x114 * 0x0400 + 3));
+    }
+
+    #[test]
+    fn test_nametables() {
+        let mut mapper = mmc5();
+        // VRAM 0, VRAM 1, ExRAM, fill
+        mapper.write(0x5105, 0xE4);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Custom([0, 1, 0, 1])));
+        assert_eq!(mapper.read_ppu(0x2401), Mapped::None);
+        mapper.write_ppu(0x2801, 0x42);
+        assert_eq!(mapper.read_ppu(0x2801), Mapped::Data(0x42));
+        mapper.write(0x5106, 0x33);
+        mapper.write(0x5107, 0x02);
+        assert_eq!(mapper.read_ppu(0x2C01), Mapped::Data(0x33));
+        assert_eq!(mapper.read_ppu(0x2FC1), Mapped::Data(0xAA));
+        // ExRAM as RAM for the CPU
+        mapper.write(0x5104, 0x02);
+        mapper.write(0x5C01, 0x24);
+        assert_eq!(mapper.read(0x5C01), Mapped::Data(0x24));
+        assert_eq!(mapper.read_ppu(0x2801), Mapped::Data(0x00));
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = mmc5();
+        mapper.write(0x5203, 2);
+        mapper.write(0x5204, 0x80);
+        // pre-render line starts the frame
+        end_of_line(&mut mapper);
+        assert_eq!(mapper.read(0x5204), Mapped::Data(0x40));
+        end_of_line(&mut mapper);
+        assert!(!mapper.irq());
+        end_of_line(&mut mapper);
+        assert!(mapper.irq());
+        // peeking doesn't acknowledge
+        assert_eq!(mapper.peek(0x5204), Mapped::Data(0xC0));
+        assert!(mapper.irq());
+        assert_eq!(mapper.read(0x5204), Mapped::Data(0xC0));
+        assert!(!mapper.irq());
+        // vblank: no more PPU reads
+        for _ in 0..4 {
+            mapper.clock();
+        }
+        assert_eq!(mapper.read(0x5204), Mapped::Data(0x00));
+    }
+
+    #[test]
+    fn test_extended_attributes() {
+        let mut mapper = mmc5();
+        mapper.write(0x5104, 0x01);
+        mapper.write(0x5C05, 0x83);
+        end_of_line(&mut mapper);
+        let (_, at, pattern) = tile(&mut mapper, 0x2005);
+        assert_eq!(at, Mapped::Data(0xAA));
+        assert_eq!(pattern, Mapped::Chr(0x3003));
+    }
+
+    #[test]
+    fn test_split() {
+        let mut mapper = mmc5();
+        // left split of 4 tiles
+        mapper.write(0x5200, 0x84);
+        mapper.write(0x5201, 0x08);
+        mapper.write(0x5202, 0x02);
+        mapper.write(0x5C23, 0x11);
+        mapper.write(0x5FC0, 0x0C);
+        end_of_line(&mut mapper);
+        // tile 3 comes from the split, row 8 + 0
+        let (nt, at, pattern) = tile(&mut mapper, 0x2003);
+        assert_eq!(nt, Mapped::Data(0x11));
+        assert_eq!(at, Mapped::Data(0xFF));
+        assert_eq!(pattern, Mapped::Chr(0x2000 + 0x110));
+        let (nt, _, _) = tile(&mut mapper, 0x2004);
+        assert_eq!(nt, Mapped::None);
+    }
+
+    #[test]
+    fn test_multiplier_and_audio() {
+        let mut mapper = mmc5();
+        mapper.write(0x5205, 0x12);
+        mapper.write(0x5206, 0x34);
+        assert_eq!(mapper.read(0x5205), Mapped::Data(0xA8));
+        assert_eq!(mapper.read(0x5206), Mapped::Data(0x03));
+
+        mapper.write(0x5015, 0x01);
+        mapper.write(0x5000, 0xDF);
+        mapper.write(0x5002, 0x00);
+        mapper.write(0x5003, 0x0F);
+        assert_eq!(mapper.read(0x5015), Mapped::Data(0x01));
+        // a low note the APU pulse would mute
+        assert!((0..64).any(|_| { mapper.clock(); mapper.audio_output() > 0.0 }));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout,bank_from_end};
+
+// Mapper 19 (Namco 163)
+//     $4800-$4FFF: sound RAM data port
+//     $5000-$57FF: IRQ counter low 8 bits
+//     $5800-$5FFF: IRQ counter  EHHHHHHH  E: enable, H: high 7 bits
+//     $8000-$BFFF: eight 1K CHR banks, one per $800
+//     $C000-$DFFF: four nametable banks, one per $800. $E0 and above is
+//                  VRAM page 0 or 1, lower values are CHR ROM banks
+//     $E000: PRG bank at $8000, bit 6 disables the sound
+//     $E800: PRG bank at $A000  HL......  CHR bank values of $E0 and
+//            above select VRAM at $0000 (L) and $1000 (H) when clear
+//     $F000: PRG bank at $C000
+//     $F800: sound RAM address  I.AAAAAA  I: auto increment
+// The last 8K bank is fixed. The IRQ counter counts up every CPU cycle
+// and raises the IRQ at $7FFF. Writing either counter register
+// acknowledges it.
+// VRAM as pattern table is not supported, those banks read the pattern
+// memory of the console instead.
+pub struct Namco163 {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    prg_banks: [Byte; 3],
+    chr_banks: [Byte; 8],
+    nametables: [Byte; 4],
+    counter: Word,
+    irq: bool,
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn new(layout: Layout) -> Self {
+        Namco163 {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametables: [0xE0, 0xE1, 0xE0, 0xE1],
+            counter: 0,
+            irq: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+
+    fn counter_enabled(&self) -> bool {
+        self.counter & 0x8000 != 0
+    }
+
+    // CHR values of $E0 and above are VRAM unless disabled for this half
+    fn chr_is_vram(&self, addr: Addr) -> bool {
+        let bank = self.chr_banks[(addr >> 10) as usize];
+        let mask = if addr < 0x1000 { 0x40 } else { 0x80 };
+        let disabled = self.prg_banks[1] & mask != 0;
+        bank >= 0xE0 && !disabled
+    }
+}
+
+impl Mapper for Namco163 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x4800..=0x4FFF => Mapped::Data(self.audio.peek_data()),
+            0x5000..=0x57FF => Mapped::Data(self.counter as Byte),
+            0x5800..=0x5FFF => Mapped::Data((self.counter >> 8) as Byte),
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] & 0x3F;
+                Mapped::PrgRom(bank as usize * 0x2000 + (addr & 0x1FFF) as usize)
+            },
+            0xE000..=0xFFFF => Mapped::PrgRom(bank_from_end(self.prg_rom_size, 0x2000, 0) * 0x2000 + (addr & 0x1FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn read(&mut self, addr: Addr) -> Mapped {
+        match addr {
+            // reading the sound RAM data port increments the address
+            0x4800..=0x4FFF => Mapped::Data(self.audio.read_data()),
+            _ => self.peek(addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write_data(data),
+            0x5000..=0x57FF => {
+                self.counter = self.counter & 0xFF00 | data as Word;
+                self.irq = false;
+            },
+            0x5800..=0x5FFF => {
+                self.counter = self.counter & 0x00FF | (data as Word) << 8;
+                self.irq = false;
+            },
+            0x6000..=0x7FFF => return self.peek(addr),
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = data,
+            0xC000..=0xDFFF => self.nametables[((addr - 0xC000) >> 11) as usize] = data,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = data;
+                self.audio.enabled = data & 0x40 == 0;
+            },
+            0xE800..=0xEFFF => self.prg_banks[1] = data,
+            0xF000..=0xF7FF => self.prg_banks[2] = data,
+            0xF800..=0xFFFF => self.audio.address = data,
+            _ => return Mapped::None,
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF if self.chr_is_vram(addr) => Mapped::None,
+            0x0000..=0x1FFF => {
+                let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+                Mapped::Chr(bank * 0x0400 + (addr & 0x03FF) as usize)
+            },
+            0x2000..=0x3EFF => match self.nametables[((addr >> 10) & 0x03) as usize] {
+                // VRAM, see mirror_mode
+                0xE0..=0xFF => Mapped::None,
+                bank => Mapped::Chr(bank as usize * 0x0400 + (addr & 0x03FF) as usize),
+            },
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn clock(&mut self) {
+        if self.counter_enabled() && self.counter & 0x7FFF != 0x7FFF {
+            self.counter += 1;
+            if self.counter & 0x7FFF == 0x7FFF {
+                self.irq = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        let n = &self.nametables;
+        Some(MirrorMode::Custom([n[0] & 0x01, n[1] & 0x01, n[2] & 0x01, n[3] & 0x01]))
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+// Output of a channel at full volume, about an APU pulse
+const NAMCO163_LEVEL: f32 = 0.15 / 225.0;
+// CPU cycles to update one channel
+const CHANNEL_CYCLES: u8 = 15;
+
+// Wavetable synthesis with up to eight channels. The waveforms of 4 bit
+// samples and the channel registers share 128 bytes of RAM. Channel n
+// uses $40 + 8n to $47 + 8n:
+//     +0, +2, +4: frequency, 18 bits. +4 also holds the wave length
+//     +1, +3, +5: phase, 24 bits
+//     +6: wave address in samples
+//     +7: volume. $7F also holds the number of channels - 1 in bits 4-6
+// The chip updates one channel every 15 cycles and outputs them in turn.
+// The turns are averaged here, that's what a speaker hears anyway.
+struct Namco163Audio {
+    ram: [Byte; 128],
+    // I.AAAAAA  I: auto increment
+    address: Byte,
+    enabled: bool,
+    cycles: u8,
+    // channel to update next, counts down from 7
+    channel: usize,
+    outputs: [Byte; 8],
+}
+
+impl Namco163Audio {
+    fn new() -> Self {
+        Namco163Audio { ram: [0; 128], address: 0, enabled: true, cycles: 0, channel: 7, outputs: [0; 8] }
+    }
+
+    fn peek_data(&self) -> Byte {
+        self.ram[(self.address & 0x7F) as usize]
+    }
+
+    fn read_data(&mut self) -> Byte {
+        let data = self.peek_data();
+        self.increment_address();
+        data
+    }
+
+    fn write_data(&mut self, data: Byte) {
+        self.ram[(self.address & 0x7F) as usize] = data;
+        self.increment_address();
+    }
+
+    fn increment_address(&mut self) {
+        if self.address & 0x80 != 0 {
+            self.address = 0x80 | self.address.wrapping_add(1) & 0x7F;
+        }
+    }
+
+    fn channel_count(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return
+        }
+        self.cycles += 1;
+        if self.cycles < CHANNEL_CYCLES {
+            return
+        }
+        self.cycles = 0;
+        self.update_channel(self.channel);
+        self.channel = if self.channel <= 8 - self.channel_count() { 7 } else { self.channel - 1 };
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let regs = &self.ram[base..base + 8];
+        let frequency = regs[0] as u32 | (regs[2] as u32) << 8 | ((regs[4] & 0x03) as u32) << 16;
+        let mut phase = regs[1] as u32 | (regs[3] as u32) << 8 | (regs[5] as u32) << 16;
+        let length = (256 - (regs[4] & 0xFC) as u32) << 16;
+        let wave_addr = regs[6] as u32;
+        let volume = regs[7] & 0x0F;
+
+        phase = (phase + frequency) % length;
+        let sample_addr = (wave_addr + (phase >> 16)) & 0xFF;
+        let byte = self.ram[(sample_addr >> 1) as usize];
+        let sample = if sample_addr & 0x01 == 0 { byte & 0x0F } else { byte >> 4 };
+        self.outputs[channel] = sample * volume;
+
+        self.ram[base + 1] = phase as Byte;
+        self.ram[base + 3] = (phase >> 8) as Byte;
+        self.ram[base + 5] = (phase >> 16) as Byte;
+    }
+
+    fn output(&self) -> f32 {
+        if !self.enabled {
+            return 0.0
+        }
+        let count = self.channel_count();
+        let sum: u32 = self.outputs[8 - count..].iter().map(|&o| o as u32).sum();
+        sum as f32 / count as f32 * NAMCO163_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namco163() -> Namco163 {
+        Namco163::new(Layout { prg_rom_size: 0x40000, prg_ram_size: 0x2000, chr_size: 0x40000, submapper: 0 })
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = namco163();
+        mapper.write(0xE000, 0x01);
+        mapper.write(0xE800, 0x02);
+        mapper.write(0xF000, 0x03);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(0x2001));
+        assert_eq!(mapper.read(0xA001), Mapped::PrgRom(2 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(3 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xE001), Mapped::PrgRom(31 * 0x2000 + 1));
+
+        mapper.write(0x8800, 0x12);
+        assert_eq!(mapper.read_ppu(0x0401), Mapped::Chr(0x12 * 0x0400 + 1));
+        mapper.write(0x8800, 0xE0);
+        assert_eq!(mapper.read_ppu(0x0401), Mapped::None);
+        mapper.write(0xE800, 0x42);
+        assert_eq!(mapper.read_ppu(0x0401), Mapped::Chr(0xE0 * 0x0400 + 1));
+
+        // nametables from VRAM or CHR ROM
+        mapper.write(0xC000, 0xE1);
+        mapper.write(0xC800, 0xE1);
+        mapper.write(0xD000, 0xE0);
+        mapper.write(0xD800, 0x05);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Custom([1, 1, 0, 1])));
+        assert_eq!(mapper.read_ppu(0x2001), Mapped::None);
+        assert_eq!(mapper.read_ppu(0x2C01), Mapped::Chr(5 * 0x0400 + 1));
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = namco163();
+        mapper.write(0x5000, 0xFD);
+        mapper.write(0x5800, 0xFF);
+        mapper.clock();
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+        // stops at $7FFF
+        mapper.clock();
+        assert_eq!(mapper.read(0x5000), Mapped::Data(0xFF));
+        mapper.write(0x5800, 0x00);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut mapper = namco163();
+        // auto increment
+        mapper.write(0xF800, 0x80);
+        mapper.write(0x4800, 0x21);
+        mapper.write(0x4800, 0x43);
+        mapper.write(0xF800, 0x80);
+        // peeking doesn't increment
+        assert_eq!(mapper.peek(0x4800), Mapped::Data(0x21));
+        assert_eq!(mapper.read(0x4800), Mapped::Data(0x21));
+        assert_eq!(mapper.read(0x4800), Mapped::Data(0x43));
+
+        // one channel: wave of 4 samples 1, 2, 3, 4 at address 0 with a
+        // frequency of one sample per update
+        mapper.write(0xF800, 0xF8);
+        for data in [0x00, 0x00, 0x00, 0x00, 0xFD, 0x00, 0x00, 0x0F].iter() {
+            mapper.write(0x4800, *data);
+        }
+        let mut levels = vec![];
+        for _ in 0..4 {
+            for _ in 0..CHANNEL_CYCLES {
+                mapper.clock();
+            }
+            levels.push(mapper.audio_output() / NAMCO163_LEVEL / 15.0);
+        }
+        assert_eq!(levels.iter().map(|l| l.round() as u8).collect::<Vec<_>>(), vec![2, 3, 4, 1]);
+
+        mapper.write(0xE000, 0x40);
+        assert_eq!(mapper.audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::nes::types::*;
+use crate::nes::mappers::{Mapper,Mapped,Layout};
+
+// Mapper 0 (NROM)
+// prg rom: 16K or 32K
+// chr rom: 8K
+// All banks are fixed:
+// CPU:
+//     0x8000 - 0xbfff // first 16k
+//     0xc000 - 0xffff // second 16k
+//
+// Cartrige:
+// 0x4000-0xffff -> first 16K
+// 0xc000-0xffff -> last 16K or mirror or 0x8000-0xbfff
+// 0x6000-0x7fff -> PRG RAM (Family Basic), mirrored if smaller than 8K
+#[derive(Debug)]
+pub struct Nrom {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+}
+
+impl Nrom {
+    pub fn new(layout: Layout) -> Self {
+        Nrom { prg_rom_size: layout.prg_rom_size, prg_ram_size: layout.prg_ram_size }
+    }
+
+    fn map(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize % self.prg_ram_size),
+            0x8000..=0xFFFF if self.prg_rom_size > 0x4000 => Mapped::PrgRom((addr & 0x7FFF) as usize),
+            0x8000..=0xFFFF => Mapped::PrgRom((addr & 0x3FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn peek(&self, addr: Addr) -> Mapped {
+        self.map(addr)
+    }
+
+    fn write(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.map(addr)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(addr as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nrom(prg_rom_size: usize, prg_ram_size: usize) -> Nrom {
+        Nrom::new(Layout { prg_rom_size, prg_ram_size, chr_size: 8192, submapper: 0 })
+    }
+
+    #[test]
+    fn test_nrom() {
+        let mut mapper = nrom(0x4000, 0x2000);
+        assert_eq!(mapper.read(0x8000), Mapped::PrgRom(0x0000));
+        assert_eq!(mapper.read(0xFFFC), Mapped::PrgRom(0x3FFC));
+        assert_eq!(mapper.read(0x7001), Mapped::PrgRam(0x1001));
+        assert_eq!(mapper.read(0x5000), Mapped::None);
+        assert_eq!(mapper.read_ppu(0x1234), Mapped::Chr(0x1234));
+        assert_eq!(mapper.read_ppu(0x2000), Mapped::None);
+
+        let mut mapper = nrom(0x8000, 0);
+        assert_eq!(mapper.read(0xFFFC), Mapped::PrgRom(0x7FFC));
+        assert_eq!(mapper.read(0x6000), Mapped::None);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::nes::types::*;
+use crate::nes::mappers::{Mapper,Mapped,Layout,bank_from_end};
+
+// Mapper 2 (UxROM: UNROM, UOROM)
+// CPU:
+//     0x6000 - 0x7fff // PRG RAM if the header asks for it
+//     0x8000 - 0xbfff // switchable 16K bank
+//     0xc000 - 0xffff // last 16K bank
+// Writing to 0x8000 - 0xffff selects the bank at 0x8000.
+// CHR is 8K of RAM, mirroring is soldered.
+// Submapper 1 has no bus conflicts, the original boards (0 and 2) do.
+#[derive(Debug)]
+pub struct Uxrom {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    bus_conflicts: bool,
+    bank: Byte,
+}
+
+impl Uxrom {
+    pub fn new(layout: Layout) -> Self {
+        Uxrom {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            bus_conflicts: layout.submapper != 1,
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize % self.prg_ram_size),
+            0x8000..=0xBFFF => Mapped::PrgRom(self.bank as usize * 0x4000 + (addr & 0x3FFF) as usize),
+            0xC000..=0xFFFF => Mapped::PrgRom(bank_from_end(self.prg_rom_size, 0x4000, 0) * 0x4000 + (addr & 0x3FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        match addr {
+            0x8000..=0xFFFF => {
+                self.bank = data;
+                Mapped::Data(data)
+            },
+            _ => self.peek(addr),
+        }
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(addr as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uxrom() {
+        let layout = Layout { prg_rom_size: 0x20000, prg_ram_size: 0, chr_size: 0x2000, submapper: 0 };
+        let mut mapper = Uxrom::new(layout);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(0x0001));
+        assert_eq!(mapper.read(0xFFFC), Mapped::PrgRom(0x1FFFC));
+        mapper.write(0xC000, 0x05);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(5 * 0x4000 + 1));
+        assert_eq!(mapper.read(0xC000), Mapped::PrgRom(7 * 0x4000));
+        assert_eq!(mapper.read(0x6000), Mapped::None);
+        assert!(mapper.bus_conflicts());
+        assert!(!Uxrom::new(Layout { submapper: 1, ..layout }).bus_conflicts());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout,bank_from_end};
+use crate::nes::mappers::vrc_irq::VrcIrq;
+
+// Mappers 21, 22, 23 and 25 (Konami VRC2 and VRC4)
+// The boards wire different CPU address lines to the two register select
+// pins of the chip. Each mapper number covers two wirings, told apart by
+// the submapper. Without one both are decoded at once.
+//     mapper 21: VRC4a (A1, A2), VRC4c (A6, A7)
+//     mapper 22: VRC2a (A1, A0)
+//     mapper 23: VRC4f (A0, A1), VRC4e (A2, A3), VRC2b (A0, A1)
+//     mapper 25: VRC4b (A1, A0), VRC4d (A3, A2), VRC2c (A1, A0)
+//
+// Registers, by address and register select:
+//     $8000-$8003: PRG bank at $8000 (or $C000 in VRC4 swap mode)
+//     $9000: mirroring  VRC2: V/H, VRC4: V, H, single lower, single upper
+//     $9002: VRC4 PRG swap mode (bit 1)
+//     $A000-$A003: PRG bank at $A000
+//     $B000-$E003: 1K CHR banks, written as low and high nibble
+//     $F000-$F003: VRC4 IRQ latch low/high, control, acknowledge
+// The last two 8K banks are fixed.
+pub struct Vrc4 {
+    prg_rom_size: usize,
+    prg_ram_size: usize,
+    vrc2: bool,
+    // (register bit 0, register bit 1) address lines
+    lines: &'static [(u8, u8)],
+    // VRC2a ignores the lowest CHR bank bit
+    chr_shift: u8,
+    prg_banks: [Byte; 2],
+    prg_swap: bool,
+    chr_banks: [Word; 8],
+    mirror: MirrorMode,
+    // VRC2 boards without PRG RAM have a one bit latch at $6000
+    latch: Byte,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(id: u16, layout: Layout) -> Self {
+        let (lines, vrc2): (&'static [(u8, u8)], bool) = match (id, layout.submapper) {
+            (21, 1) => (&[(1, 2)], false),
+            (21, 2) => (&[(6, 7)], false),
+            (21, _) => (&[(1, 2), (6, 7)], false),
+            (22, _) => (&[(1, 0)], true),
+            (23, 1) => (&[(0, 1)], false),
+            (23, 2) => (&[(2, 3)], false),
+            (23, 3) => (&[(0, 1)], true),
+            (23, _) => (&[(0, 1), (2, 3)], false),
+            (25, 1) => (&[(1, 0)], false),
+            (25, 2) => (&[(3, 2)], false),
+            (25, 3) => (&[(1, 0)], true),
+            (_, _) => (&[(1, 0), (3, 2)], false),
+        };
+        Vrc4 {
+            prg_rom_size: layout.prg_rom_size,
+            prg_ram_size: layout.prg_ram_size,
+            vrc2,
+            lines,
+            chr_shift: if id == 22 { 1 } else { 0 },
+            prg_banks: [0, 1],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirror: MirrorMode::Vertical,
+            latch: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    // register select 0-3 of a CPU address
+    fn register(&self, addr: Addr) -> u8 {
+        self.lines.iter().fold(0, |reg, (bit0, bit1)| {
+            reg | ((addr >> bit0) & 0x01) as u8 | (((addr >> bit1) & 0x01) as u8) << 1
+        })
+    }
+
+    fn prg_rom_index(&self, addr: Addr) -> usize {
+        let second_last = bank_from_end(self.prg_rom_size, 0x2000, 1);
+        let bank = match (addr >> 13) & 0x03 {
+            0 if self.prg_swap => second_last,
+            0 => self.prg_banks[0] as usize,
+            1 => self.prg_banks[1] as usize,
+            2 if self.prg_swap => self.prg_banks[0] as usize,
+            2 => second_last,
+            _ => bank_from_end(self.prg_rom_size, 0x2000, 0),
+        };
+        bank * 0x2000 + (addr & 0x1FFF) as usize
+    }
+
+    fn write_chr_nibble(&mut self, addr: Addr, register: u8, data: Byte) {
+        // $B000: banks 0 and 1, $C000: 2 and 3, ...
+        let bank = ((addr >> 12) as usize - 0xB) * 2 + (register >> 1) as usize;
+        let value = self.chr_banks[bank];
+        self.chr_banks[bank] = if register & 0x01 == 0 {
+            value & 0x1F0 | (data & 0x0F) as Word
+        } else {
+            value & 0x00F | ((data & 0x1F) as Word) << 4
+        };
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 => Mapped::PrgRam((addr - 0x6000) as usize),
+            0x6000..=0x6FFF if self.vrc2 => Mapped::Data(self.latch),
+            0x8000..=0xFFFF => Mapped::PrgRom(self.prg_rom_index(addr)),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        if addr < 0x8000 {
+            if self.prg_ram_size == 0 && self.vrc2 && (0x6000..=0x6FFF).contains(&addr) {
+                self.latch = data & 0x01;
+                return Mapped::Data(data)
+            }
+            return self.peek(addr)
+        }
+        let register = self.register(addr);
+        match (addr & 0xF000, register) {
+            (0x8000, _) => self.prg_banks[0] = data & 0x1F,
+            (0x9000, 0) | (0x9000, 1) if self.vrc2 => {
+                self.mirror = if data & 0x01 == 0 { MirrorMode::Vertical } else { MirrorMode::Horizontal };
+            },
+            (0x9000, 0) => {
+                self.mirror = match data & 0x03 {
+                    0 => MirrorMode::Vertical,
+                    1 => MirrorMode::Horizontal,
+                    2 => MirrorMode::SingleScreenLower,
+                    _ => MirrorMode::SingleScreenUpper,
+                };
+            },
+            (0x9000, 2) if !self.vrc2 => self.prg_swap = data & 0x02 != 0,
+            (0xA000, _) => self.prg_banks[1] = data & 0x1F,
+            (0xB000..=0xE000, _) => self.write_chr_nibble(addr, register, data),
+            (0xF000, 0) if !self.vrc2 => self.irq.write_latch_low(data),
+            (0xF000, 1) if !self.vrc2 => self.irq.write_latch_high(data),
+            (0xF000, 2) if !self.vrc2 => self.irq.write_control(data),
+            (0xF000, 3) if !self.vrc2 => self.irq.acknowledge(),
+            _ => { }
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => {
+                let bank = (self.chr_banks[(addr >> 10) as usize] >> self.chr_shift) as usize;
+                Mapped::Chr(bank * 0x0400 + (addr & 0x03FF) as usize)
+            },
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        Some(self.mirror)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vrc(id: u16, submapper: Byte) -> Vrc4 {
+        Vrc4::new(id, Layout { prg_rom_size: 0x40000, prg_ram_size: 0, chr_size: 0x40000, submapper })
+    }
+
+    #[test]
+    fn test_register_lines() {
+        // VRC4a and VRC4c decoded together
+        let mapper = vrc(21, 0);
+        assert_eq!(mapper.register(0x9004), 2);
+        assert_eq!(mapper.register(0x9080), 2);
+        assert_eq!(mapper.register(0x90C0), 3);
+        assert_eq!(vrc(21, 1).register(0x9080), 0);
+        // VRC2a and VRC4b swap the lines
+        assert_eq!(vrc(22, 0).register(0x9001), 2);
+        assert_eq!(vrc(25, 0).register(0x9008), 1);
+        assert_eq!(vrc(23, 0).register(0x9008), 2);
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = vrc(23, 1);
+        mapper.write(0x8000, 0x03);
+        mapper.write(0xA000, 0x04);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(3 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xA001), Mapped::PrgRom(4 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(30 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xE001), Mapped::PrgRom(31 * 0x2000 + 1));
+        mapper.write(0x9002, 0x02);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(30 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(3 * 0x2000 + 1));
+
+        // CHR bank 3 is $C002 low, $C003 high
+        mapper.write(0xC002, 0x05);
+        mapper.write(0xC003, 0x12);
+        assert_eq!(mapper.read_ppu(0x0C01), Mapped::Chr(0x125 * 0x0400 + 1));
+        mapper.write(0x9000, 0x03);
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::SingleScreenUpper));
+    }
+
+    #[test]
+    fn test_vrc2() {
+        let mut mapper = vrc(22, 0);
+        // VRC2a drops the low CHR bank bit. Register 1 is A0 here
+        mapper.write(0xB000, 0x05);
+        mapper.write(0xB002, 0x01);
+        assert_eq!(mapper.read_ppu(0x0001), Mapped::Chr(0x0A * 0x0400 + 1));
+        // one bit latch instead of PRG RAM
+        mapper.write(0x6000, 0xFF);
+        assert_eq!(mapper.read(0x6000), Mapped::Data(0x01));
+        // no IRQ
+        mapper.write(0xF002, 0x07);
+        for _ in 0..300 {
+            mapper.clock();
+        }
+        assert!(!mapper.irq());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::nes::types::*;
+use crate::nes::cartridge::MirrorMode;
+use crate::nes::mappers::{Mapper,Mapped,Layout,bank_from_end};
+use crate::nes::mappers::vrc_irq::VrcIrq;
+
+// Mappers 24 and 26 (Konami VRC6a and VRC6b, 26 swaps A0 and A1)
+//     $8000-$8003: 16K PRG bank at $8000
+//     $9000-$9002: pulse 1, $9003: audio frequency control
+//     $A000-$A002: pulse 2
+//     $B000-$B002: sawtooth
+//     $B003: banking  R.NNMMPP  R: PRG RAM enable, M: mirroring,
+//                               P: CHR banking mode
+//     $C000-$C003: 8K PRG bank at $C000
+//     $D000-$E003: 1K CHR registers R0-R7
+//     $F000-$F002: IRQ latch, control, acknowledge
+// The last 8K bank is fixed. Nametables from CHR ROM (bits 4 and 5 of
+// $B003) are not supported, the mirroring bits are used in every mode.
+pub struct Vrc6 {
+    prg_ram_size: usize,
+    prg_rom_size: usize,
+    swap_lines: bool,
+    prg_16k: Byte,
+    prg_8k: Byte,
+    chr_banks: [Byte; 8],
+    banking: Byte,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(id: u16, layout: Layout) -> Self {
+        Vrc6 {
+            prg_ram_size: layout.prg_ram_size,
+            prg_rom_size: layout.prg_rom_size,
+            swap_lines: id == 26,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            banking: 0,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    fn chr_bank(&self, addr: Addr) -> usize {
+        let slot = (addr >> 10) as usize;
+        let r = &self.chr_banks;
+        match self.banking & 0x03 {
+            // 1K banks
+            0 => r[slot] as usize,
+            // 2K banks R0-R3, A10 from the PPU
+            1 => (r[slot >> 1] & 0xFE) as usize | slot & 0x01,
+            // 1K banks R0-R3, 2K banks R4-R5
+            _ if slot < 4 => r[slot] as usize,
+            _ => (r[4 + ((slot - 4) >> 1)] & 0xFE) as usize | slot & 0x01,
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn peek(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_size > 0 && self.banking & 0x80 != 0 =>
+                Mapped::PrgRam((addr - 0x6000) as usize),
+            0x8000..=0xBFFF => Mapped::PrgRom(self.prg_16k as usize * 0x4000 + (addr & 0x3FFF) as usize),
+            0xC000..=0xDFFF => Mapped::PrgRom(self.prg_8k as usize * 0x2000 + (addr & 0x1FFF) as usize),
+            0xE000..=0xFFFF => Mapped::PrgRom(bank_from_end(self.prg_rom_size, 0x2000, 0) * 0x2000 + (addr & 0x1FFF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write(&mut self, addr: Addr, data: Byte) -> Mapped {
+        if addr < 0x8000 {
+            return self.peek(addr)
+        }
+        let register = if self.swap_lines {
+            (addr & 0x01) << 1 | (addr >> 1) & 0x01
+        } else {
+            addr & 0x03
+        };
+        match (addr & 0xF000, register) {
+            (0x8000, _) => self.prg_16k = data,
+            (0x9000..=0xB000, 3) if addr & 0xF000 == 0xB000 => self.banking = data,
+            (0x9000..=0xB000, _) => self.audio.writeb(addr & 0xF000 | register, data),
+            (0xC000, _) => self.prg_8k = data,
+            (0xD000, r) => self.chr_banks[r as usize] = data,
+            (0xE000, r) => self.chr_banks[4 + r as usize] = data,
+            (0xF000, 0) => self.irq.write_latch(data),
+            (0xF000, 1) => self.irq.write_control(data),
+            (0xF000, 2) => self.irq.acknowledge(),
+            _ => { }
+        }
+        Mapped::Data(data)
+    }
+
+    fn read_ppu(&self, addr: Addr) -> Mapped {
+        match addr {
+            0x0000..=0x1FFF => Mapped::Chr(self.chr_bank(addr) * 0x0400 + (addr & 0x03FF) as usize),
+            _ => Mapped::None,
+        }
+    }
+
+    fn write_ppu(&mut self, addr: Addr, _data: Byte) -> Mapped {
+        self.read_ppu(addr)
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq
+    }
+
+    fn mirror_mode(&self) -> Option<MirrorMode> {
+        Some(match (self.banking >> 2) & 0x03 {
+            0 => MirrorMode::Vertical,
+            1 => MirrorMode::Horizontal,
+            2 => MirrorMode::SingleScreenLower,
+            _ => MirrorMode::SingleScreenUpper,
+        })
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+// Output of one pulse step at volume 1. A VRC6 pulse at full volume is
+// about as loud as an APU pulse
+const VRC6_LEVEL: f32 = 0.0099;
+
+// Two pulse channels with 16 volume levels and 8 duty cycles, and a
+// sawtooth channel
+struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    // $9003: H..  halt all, F: period >> 4, 256: period >> 8
+    frequency: Byte,
+}
+
+impl Vrc6Audio {
+    fn new() -> Self {
+        Vrc6Audio { pulses: [Vrc6Pulse::new(), Vrc6Pulse::new()], saw: Vrc6Saw::new(), frequency: 0 }
+    }
+
+    // register $9000-$B002
+    fn writeb(&mut self, addr: Addr, data: Byte) {
+        match addr {
+            0x9000..=0x9002 => self.pulses[0].writeb(addr & 0x03, data),
+            0x9003 => self.frequency = data,
+            0xA000..=0xA002 => self.pulses[1].writeb(addr & 0x03, data),
+            0xB000..=0xB002 => self.saw.writeb(addr & 0x03, data),
+            _ => { }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.frequency & 0x01 != 0 {
+            return
+        }
+        let shift = if self.frequency & 0x04 != 0 { 8 } else if self.frequency & 0x02 != 0 { 4 } else { 0 };
+        self.pulses[0].clock(shift);
+        self.pulses[1].clock(shift);
+        self.saw.clock(shift);
+    }
+
+    fn output(&self) -> f32 {
+        let level = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        level as f32 * VRC6_LEVEL
+    }
+}
+
+struct Vrc6Pulse {
+    volume: Byte,
+    duty: Byte,
+    // ignore the duty, output the volume all the time
+    constant: bool,
+    enabled: bool,
+    period: Word,
+    timer: Word,
+    step: Byte,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Self {
+        Vrc6Pulse { volume: 0, duty: 0, constant: false, enabled: false, period: 0, timer: 0, step: 0 }
+    }
+
+    fn writeb(&mut self, register: Addr, data: Byte) {
+        match register {
+            // MDDD VVVV
+            0 => {
+                self.constant = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0F;
+            },
+            1 => self.period = self.period & 0x0F00 | data as Word,
+            // E... PPPP
+            _ => {
+                self.period = self.period & 0x00FF | ((data & 0x0F) as Word) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            },
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> Byte {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+struct Vrc6Saw {
+    // added to the accumulator every second step
+    rate: Byte,
+    enabled: bool,
+    period: Word,
+    timer: Word,
+    step: Byte,
+    accumulator: Byte,
+}
+
+impl Vrc6Saw {
+    fn new() -> Self {
+        Vrc6Saw { rate: 0, enabled: false, period: 0, timer: 0, step: 0, accumulator: 0 }
+    }
+
+    fn writeb(&mut self, register: Addr, data: Byte) {
+        match register {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = self.period & 0x0F00 | data as Word,
+            _ => {
+                self.period = self.period & 0x00FF | ((data & 0x0F) as Word) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            },
+        }
+    }
+
+    // The accumulator grows by the rate on every second of 14 steps and
+    // is cleared after the last one
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 0x01 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> Byte {
+        self.accumulator >> 3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vrc6(id: u16) -> Vrc6 {
+        Vrc6::new(id, Layout { prg_rom_size: 0x40000, prg_ram_size: 0x2000, chr_size: 0x40000, submapper: 0 })
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mapper = vrc6(24);
+        mapper.write(0x8000, 0x02);
+        mapper.write(0xC000, 0x07);
+        assert_eq!(mapper.read(0x8001), Mapped::PrgRom(2 * 0x4000 + 1));
+        assert_eq!(mapper.read(0xC001), Mapped::PrgRom(7 * 0x2000 + 1));
+        assert_eq!(mapper.read(0xE001), Mapped::PrgRom(31 * 0x2000 + 1));
+        mapper.write(0xE001, 0x21);
+        assert_eq!(mapper.read_ppu(0x1401), Mapped::Chr(0x21 * 0x0400 + 1));
+        // PRG RAM and mirroring
+        assert_eq!(mapper.read(0x6000), Mapped::None);
+        mapper.write(0xB003, 0x84);
+        assert_eq!(mapper.read(0x6000), Mapped::PrgRam(0));
+        assert_eq!(mapper.mirror_mode(), Some(MirrorMode::Horizontal));
+
+        // VRC6b swaps A0 and A1
+        let mut mapper = vrc6(26);
+        mapper.write(0xE001, 0x21);
+        assert_eq!(mapper.read_ppu(0x1801), Mapped::Chr(0x21 * 0x0400 + 1));
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut audio = Vrc6Audio::new();
+        assert_eq!(audio.output(), 0.0);
+        // 50% duty at volume 15, period 0
+        audio.writeb(0x9000, 0x7F);
+        audio.writeb(0x9002, 0x80);
+        let mut high = 0;
+        for _ in 0..16 {
+            audio.clock();
+            if audio.output() > 0.0 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 8);
+        // constant volume
+        audio.writeb(0x9000, 0x8F);
+        assert!((audio.output() - 15.0 * VRC6_LEVEL).abs() < 1e-6);
+
+        // the sawtooth rises by the rate every second step
+        let mut saw = Vrc6Saw::new();
+        saw.writeb(0, 0x08);
+        saw.writeb(2, 0x80);
+        let levels: Vec<Byte> = (0..14).map(|_| { saw.clock(0); saw.output() }).collect();
+        assert_eq!(levels, vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::nes::types::*;
+
+// IRQ counter of the Konami VRC4, VRC6 and VRC7. An 8 bit counter counts
+// up to 0xFF, raises the IRQ and reloads from the latch. In cycle mode it
+// counts CPU cycles. In scanline mode a prescaler divides them by 113.667,
+// one scanline, without looking at the PPU at all.
+pub struct VrcIrq {
+    latch: Byte,
+    counter: Byte,
+    prescaler: i16,
+    // enable after acknowledge
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    pub irq: bool,
+}
+
+// the prescaler counts down 3 per CPU cycle from 341, the PPU dots of a
+// scanline
+const PRESCALER_PERIOD: i16 = 341;
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enable_after_ack: false,
+            enabled: false,
+            cycle_mode: false,
+            irq: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, data: Byte) {
+        self.latch = data;
+    }
+
+    // VRC4 writes the latch in two nibbles
+    pub fn write_latch_low(&mut self, data: Byte) {
+        self.latch = self.latch & 0xF0 | data & 0x0F;
+    }
+
+    pub fn write_latch_high(&mut self, data: Byte) {
+        self.latch = self.latch & 0x0F | (data & 0x0F) << 4;
+    }
+
+    // .....MEA  M: cycle mode, E: enable, A: enable after acknowledge
+    pub fn write_control(&mut self, data: Byte) {
+        self.enable_after_ack = data & 0x01 != 0;
+        self.enabled = data & 0x02 != 0;
+        self.cycle_mode = data & 0x04 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+        self.irq = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.irq = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    // One CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.irq = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vrc_irq() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch_low(0x0E);
+        irq.write_latch_high(0x0F);
+        // cycle mode: 0xFE, 0xFF, then reload and IRQ
+        irq.write_control(0x07);
+        irq.clock();
+        assert!(!irq.irq);
+        irq.clock();
+        assert!(irq.irq);
+        assert_eq!(irq.counter, 0xFE);
+        // stays enabled after acknowledge
+        irq.acknowledge();
+        assert!(!irq.irq && irq.enabled);
+
+        // scanline mode: 341 dots are 113 or 114 CPU cycles
+        irq.write_latch(0xFF);
+        irq.write_control(0x02);
+        for _ in 0..113 {
+            irq.clock();
+        }
+        assert!(!irq.irq);
+        irq.clock();
+        assert!(irq.irq);
+        irq.acknowledge();
+        assert!(!irq.enabled);
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs;
+use std::path::Path;
+use failure::Error;
+use crate::nes::types::*;
+use crate::nes::input::{Input,InputKind,Buttons};
+
+pub mod fm2;
+
+// FM2 commands of a frame
+pub const COMMAND_RESET: Byte = 0x01;
+pub const COMMAND_POWER: Byte = 0x02;
+
+// Zapper position recorded when it aims at nothing. Zapper::aim_at takes
+// it as off screen again
+const ZAPPER_OFF_SCREEN: (i32, i32) = (-1, -1);
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct ZapperInput {
+    pub x: i32,
+    pub y: i32,
+    pub trigger: bool,
+}
+
+// State of all input devices during one frame
+#[derive(Clone,Debug,PartialEq)]
+pub struct FrameInput {
+    pub commands: Byte,
+    // players 1-4. Players 3 and 4 only with a four score
+    pub controllers: [Buttons; 4],
+    pub zapper: [Option<ZapperInput>; 2],
+    // power pads in the ports. Bit n-1: button n is pressed
+    pub power_pads: [u16; 2],
+}
+
+impl FrameInput {
+    pub fn new() -> Self {
+        FrameInput { commands: 0, controllers: [Buttons::empty(); 4], zapper: [None, None], power_pads: [0; 2] }
+    }
+
+    // Read the current state of the devices
+    pub fn capture(inputs: &[Input; 2]) -> Self {
+        let mut frame = FrameInput::new();
+        for (port, input) in inputs.iter().enumerate() {
+            match input {
+                Input::Controller(controller) => frame.controllers[port] = controller.borrow().buttons,
+                Input::FourScore(four_score) => {
+                    let four_score = four_score.borrow();
+                    frame.controllers[port] = four_score.controllers[0].buttons;
+                    frame.controllers[port + 2] = four_score.controllers[1].buttons;
+                },
+                Input::Zapper(zapper) => {
+                    let zapper = zapper.borrow();
+                    let (x, y) = zapper.aim.map_or(ZAPPER_OFF_SCREEN, |(x, y)| (x as i32, y as i32));
+                    frame.zapper[port] = Some(ZapperInput { x, y, trigger: zapper.trigger });
+                },
+                Input::PowerPad(power_pad) => frame.power_pads[port] = power_pad.borrow().buttons,
+                Input::None => { },
+            }
+        }
+        frame
+    }
+
+    // Set the devices to the state of this frame
+    pub fn apply(&self, inputs: &[Input; 2]) {
+        for (port, input) in inputs.iter().enumerate() {
+            match input {
+                Input::Controller(controller) => controller.borrow_mut().buttons = self.controllers[port],
+                Input::FourScore(four_score) => {
+                    let mut four_score = four_score.borrow_mut();
+                    four_score.controllers[0].buttons = self.controllers[port];
+                    four_score.controllers[1].buttons = self.controllers[port + 2];
+                },
+                Input::Zapper(zapper) => {
+                    if let Some(input) = self.zapper[port] {
+                        let mut zapper = zapper.borrow_mut();
+                        zapper.aim_at(input.x, input.y);
+                        zapper.trigger = input.trigger;
+                    }
+                },
+                Input::PowerPad(power_pad) => power_pad.borrow_mut().buttons = self.power_pads[port],
+                Input::None => { },
+            }
+        }
+    }
+}
+
+// Controller input of a run, one entry per frame from power on
+#[derive(Clone,Debug)]
+pub struct Movie {
+    pub rom_filename: String,
+    pub four_score: bool,
+    pub ports: [InputKind; 2],
+    pub frames: Vec<FrameInput>,
+    // header lines without meaning for jane. Kept for export
+    pub extra_header: Vec<(String, String)>,
+}
+
+impl Movie {
+    pub fn new(port1: InputKind, port2: InputKind) -> Self {
+        Movie {
+            rom_filename: String::new(),
+            four_score: port1 == InputKind::FourScore && port2 == InputKind::FourScore,
+            ports: [port1, port2],
+            frames: vec![],
+            extra_header: vec![],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        fm2::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, fm2::write(self))?;
+        Ok(())
+    }
+
+    // Devices the movie was recorded with
+    pub fn port_kinds(&self) -> [InputKind; 2] {
+        if self.four_score {
+            [InputKind::FourScore, InputKind::FourScore]
+        } else {
+            self.ports
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum MovieMode {
+    Record,
+    Play,
+}
+
+// A movie being recorded or played back. Input is taken or applied at
+// the start of every NES::clock_frame
+pub struct MovieSession {
+    pub movie: Movie,
+    pub mode: MovieMode,
+    pub frame: usize,
+    // commands for the next recorded frame
+    pending_commands: Byte,
+}
+
+impl MovieSession {
+    pub fn new(movie: Movie, mode: MovieMode) -> Self {
+        MovieSession { movie, mode, frame: 0, pending_commands: 0 }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.mode == MovieMode::Play && self.frame >= self.movie.frames.len()
+    }
+
+    // Remember a command that happened outside of the movie (recording)
+    pub fn command(&mut self, command: Byte) {
+        if self.mode == MovieMode::Record {
+            self.pending_commands |= command;
+        }
+    }
+
+    // Record or play back the input of the next frame. Returns the
+    // commands of the frame
+    pub fn next_frame(&mut self, inputs: &[Input; 2]) -> Byte {
+        match self.mode {
+            MovieMode::Record => {
+                let mut frame = FrameInput::capture(inputs);
+                frame.commands = self.pending_commands;
+                self.pending_commands = 0;
+                self.movie.frames.push(frame);
+                self.frame += 1;
+                0
+            },
+            MovieMode::Play => match self.movie.frames.get(self.frame) {
+                Some(frame) => {
+                    frame.apply(inputs);
+                    self.frame += 1;
+                    frame.commands
+                },
+                None => 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash,Hasher};
+
+    const ROM_PATH: &str = "test_roms/nestest.nes";
+    const FRAMES: usize = 90;
+
+    fn power_on() -> NES {
+        let mut nes = NES::new();
+        nes.insert_cartridge(Cartridge::new(Path::new(ROM_PATH)).unwrap());
+        nes.start();
+        nes
+    }
+
+    fn frame_hash(nes: &NES) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        nes.ppu.borrow().canvas_main.iter().for_each(|byte| byte.hash(&mut hasher));
+        hasher.finish()
+    }
+
+    // the nestest menu: move the cursor down, start a test and reset
+    fn press_buttons(nes: &mut NES, frame: usize) {
+        let buttons = match frame {
+            30..=35 | 40..=45 => Buttons::DOWN,
+            55..=60 => Buttons::START,
+            _ => Buttons::empty(),
+        };
+        if let Input::Controller(controller) = &nes.inputs[0] {
+            controller.borrow_mut().buttons = buttons;
+        }
+        if frame == 80 {
+            nes.reset();
+        }
+    }
+
+    fn record() -> (Movie, Vec<u64>) {
+        let mut nes = power_on();
+        nes.start_movie(Movie::new(InputKind::Controller, InputKind::Controller), MovieMode::Record);
+        let mut hashes = vec![];
+        for frame in 0..FRAMES {
+            press_buttons(&mut nes, frame);
+            let _ = nes.clock_frame();
+            hashes.push(frame_hash(&nes));
+        }
+        (nes.stop_movie().unwrap(), hashes)
+    }
+
+    fn play(movie: Movie) -> Vec<u64> {
+        let mut nes = power_on();
+        // the movie starts with a power cycle, whatever ran before
+        for frame in 50..70 {
+            press_buttons(&mut nes, frame);
+            let _ = nes.clock_frame();
+        }
+        nes.start_movie(movie, MovieMode::Play);
+        (0..FRAMES).map(|_| {
+            let _ = nes.clock_frame();
+            frame_hash(&nes)
+        }).collect()
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let (movie, recorded) = record();
+        assert_eq!(movie.frames.len(), FRAMES);
+        assert_eq!(movie.frames[80].commands, COMMAND_RESET);
+
+        // through the fm2 format and back
+        let movie = fm2::parse(&fm2::write(&movie)).unwrap();
+        assert_eq!(play(movie.clone()), recorded);
+
+        // the input makes a difference
+        let mut no_input = movie;
+        no_input.frames.iter_mut().for_each(|frame| frame.controllers[0] = Buttons::empty());
+        assert_ne!(play(no_input), recorded);
+    }
+
+    #[test]
+    fn test_power_pad() {
+        let pressed: Vec<u16> = (0..10).map(|frame| (frame * 0x0155) & 0x0FFF).collect();
+        let mut nes = power_on();
+        nes.connect_input(1, InputKind::PowerPad);
+        nes.start_movie(Movie::new(InputKind::Controller, InputKind::PowerPad), MovieMode::Record);
+        for &buttons in pressed.iter() {
+            if let Input::PowerPad(power_pad) = &nes.inputs[1] {
+                power_pad.borrow_mut().buttons = buttons;
+            }
+            let _ = nes.clock_frame();
+        }
+        let movie = fm2::parse(&fm2::write(&nes.stop_movie().unwrap())).unwrap();
+        assert_eq!(movie.ports[1], InputKind::PowerPad);
+
+        let mut nes = power_on();
+        nes.start_movie(movie, MovieMode::Play);
+        for &buttons in pressed.iter() {
+            let _ = nes.clock_frame();
+            match &nes.inputs[1] {
+                Input::PowerPad(power_pad) => assert_eq!(power_pad.borrow().buttons, buttons),
+                _ => panic!("expected a power pad"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_zapper_aim() {
+        let mut nes = NES::new();
+        nes.connect_input(1, InputKind::Zapper);
+        let zapper = match &nes.inputs[1] {
+            Input::Zapper(zapper) => zapper.clone(),
+            _ => panic!("expected a zapper"),
+        };
+        let off_screen = FrameInput::capture(&nes.inputs);
+        zapper.borrow_mut().aim_at(10, 20);
+        let aimed = FrameInput::capture(&nes.inputs);
+
+        // through the fm2 format, then played back on the zapper
+        let mut movie = Movie::new(InputKind::Controller, InputKind::Zapper);
+        movie.frames = vec![off_screen, aimed];
+        let movie = fm2::parse(&fm2::write(&movie)).unwrap();
+        movie.frames[0].apply(&nes.inputs);
+        assert_eq!(zapper.borrow().aim, None);
+        movie.frames[1].apply(&nes.inputs);
+        assert_eq!(zapper.borrow().aim, Some((10, 20)));
+    }
+}
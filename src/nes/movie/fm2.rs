@@ -0,0 +1,239 @@
+// FCEUX movie format (FM2), text variant. A header of "key value" lines,
+// followed by one line per frame:
+//   |commands|port0|port1|port2|
+// Gamepads are logged as RLDUTSBA, a '.' or ' ' for released buttons and
+// any other character for pressed ones. A zapper is logged as "x y b q z",
+// jane logs a zapper aimed off screen at -1 -1. A power pad (side B) is
+// logged as its buttons 1-12, '.' for released ones.
+// With the fourscore flag the two ports are replaced by four gamepads.
+use std::fmt::Write;
+use failure::Error;
+use crate::nes::types::*;
+use crate::nes::input::{Buttons,InputKind};
+use crate::nes::movie::{Movie,FrameInput,ZapperInput};
+
+const GAMEPAD_CHARS: &[u8; 8] = b"RLDUTSBA";
+// power pad buttons 1-12
+const POWER_PAD_CHARS: &[u8; 12] = b"123456789ABC";
+
+// port0/port1 header values
+const SI_NONE: u8 = 0;
+const SI_GAMEPAD: u8 = 1;
+const SI_ZAPPER: u8 = 2;
+const SI_POWERPADB: u8 = 4;
+
+pub fn parse(text: &str) -> Result<Movie, Error> {
+    let mut movie = Movie::new(InputKind::Controller, InputKind::Controller);
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue
+        }
+        if line.starts_with('|') {
+            let frame = parse_frame(&movie, line)
+                .map_err(|e| format_err!("FM2 line {}: {}", i + 1, e))?;
+            movie.frames.push(frame);
+            continue
+        }
+        let (key, value) = match line.find(' ') {
+            Some(split) => (&line[..split], &line[split + 1..]),
+            None => (line, ""),
+        };
+        match key {
+            "version" if value != "3" => bail!("Unsupported FM2 version {}", value),
+            "binary" if value == "1" => bail!("Binary FM2 movies are not supported"),
+            "palFlag" if value == "1" => bail!("PAL movies are not supported"),
+            "romFilename" => movie.rom_filename = value.to_string(),
+            "fourscore" => movie.four_score = value == "1",
+            "port0" => movie.ports[0] = port_kind(value)?,
+            "port1" => movie.ports[1] = port_kind(value)?,
+            "version" | "emuVersion" | "palFlag" | "binary" => { },
+            _ => movie.extra_header.push((key.to_string(), value.to_string())),
+        }
+    }
+    Ok(movie)
+}
+
+fn port_kind(value: &str) -> Result<InputKind, Error> {
+    match value.parse::<u8>()? {
+        SI_NONE => Ok(InputKind::None),
+        SI_GAMEPAD => Ok(InputKind::Controller),
+        SI_ZAPPER => Ok(InputKind::Zapper),
+        SI_POWERPADB => Ok(InputKind::PowerPad),
+        _ => bail!("Unsupported input device {}", value),
+    }
+}
+
+fn parse_frame(movie: &Movie, line: &str) -> Result<FrameInput, Error> {
+    let fields: Vec<&str> = line.split('|').collect();
+    // an empty field before the first and after the last '|'
+    if fields.len() < 4 {
+        bail!("Expected |commands|port0|port1|port2|");
+    }
+    let mut frame = FrameInput::new();
+    frame.commands = fields[1].trim().parse::<Byte>()
+        .map_err(|_| format_err!("Invalid commands '{}'", fields[1]))?;
+    if movie.four_score {
+        for (player, field) in fields[2..].iter().take(4).enumerate() {
+            frame.controllers[player] = parse_gamepad(field)?;
+        }
+        return Ok(frame)
+    }
+    for port in 0..2 {
+        let field = fields[2 + port];
+        match movie.ports[port] {
+            InputKind::Zapper => frame.zapper[port] = Some(parse_zapper(field)?),
+            InputKind::PowerPad => frame.power_pads[port] = parse_power_pad(field)?,
+            InputKind::None => { },
+            _ => frame.controllers[port] = parse_gamepad(field)?,
+        }
+    }
+    Ok(frame)
+}
+
+fn parse_gamepad(field: &str) -> Result<Buttons, Error> {
+    if field.is_empty() {
+        return Ok(Buttons::empty())
+    }
+    if field.len() != GAMEPAD_CHARS.len() {
+        bail!("Gamepad input '{}' must have 8 characters", field);
+    }
+    let mut bits = 0;
+    for (i, c) in field.bytes().enumerate() {
+        if c != b'.' && c != b' ' {
+            bits |= 1 << (7 - i);
+        }
+    }
+    Ok(Buttons::from_bits_truncate(bits))
+}
+
+fn parse_power_pad(field: &str) -> Result<u16, Error> {
+    if field.len() != POWER_PAD_CHARS.len() {
+        bail!("Power pad input '{}' must have 12 characters", field);
+    }
+    let mut buttons = 0;
+    for (i, c) in field.bytes().enumerate() {
+        if c != b'.' && c != b' ' {
+            buttons |= 1 << i;
+        }
+    }
+    Ok(buttons)
+}
+
+fn parse_zapper(field: &str) -> Result<ZapperInput, Error> {
+    let values = field.split_whitespace()
+        .map(|value| value.parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.len() < 3 {
+        bail!("Zapper input '{}' must be 'x y button'", field);
+    }
+    Ok(ZapperInput { x: values[0], y: values[1], trigger: values[2] != 0 })
+}
+
+pub fn write(movie: &Movie) -> String {
+    let mut text = String::new();
+    let port_value = |kind| match kind {
+        InputKind::Zapper => SI_ZAPPER,
+        InputKind::PowerPad => SI_POWERPADB,
+        InputKind::None => SI_NONE,
+        _ => SI_GAMEPAD,
+    };
+    writeln!(text, "version 3").unwrap();
+    writeln!(text, "emuVersion 0").unwrap();
+    writeln!(text, "palFlag 0").unwrap();
+    writeln!(text, "romFilename {}", movie.rom_filename).unwrap();
+    writeln!(text, "fourscore {}", movie.four_score as u8).unwrap();
+    writeln!(text, "port0 {}", port_value(movie.ports[0])).unwrap();
+    writeln!(text, "port1 {}", port_value(movie.ports[1])).unwrap();
+    writeln!(text, "port2 0").unwrap();
+    for (key, value) in movie.extra_header.iter().filter(|(key, _)| key != "port2") {
+        writeln!(text, "{} {}", key, value).unwrap();
+    }
+
+    for frame in movie.frames.iter() {
+        write!(text, "|{}|", frame.commands).unwrap();
+        if movie.four_score {
+            for &buttons in frame.controllers.iter() {
+                write!(text, "{}|", gamepad(buttons)).unwrap();
+            }
+        } else {
+            for port in 0..2 {
+                match (movie.ports[port], frame.zapper[port]) {
+                    (InputKind::Zapper, Some(z)) => write!(text, "{} {} {} 0 0|", z.x, z.y, z.trigger as u8).unwrap(),
+                    (InputKind::Zapper, None) => write!(text, "0 0 0 0 0|").unwrap(),
+                    (InputKind::PowerPad, _) => write!(text, "{}|", power_pad(frame.power_pads[port])).unwrap(),
+                    (InputKind::None, _) => write!(text, "|").unwrap(),
+                    _ => write!(text, "{}|", gamepad(frame.controllers[port])).unwrap(),
+                }
+            }
+        }
+        writeln!(text, "|").unwrap();
+    }
+    text
+}
+
+fn gamepad(buttons: Buttons) -> String {
+    GAMEPAD_CHARS.iter().enumerate()
+        .map(|(i, &c)| if buttons.bits() & (1 << (7 - i)) > 0 { c as char } else { '.' })
+        .collect()
+}
+
+fn power_pad(buttons: u16) -> String {
+    POWER_PAD_CHARS.iter().enumerate()
+        .map(|(i, &c)| if buttons & (1 << i) > 0 { c as char } else { '.' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOVIE: &str = "version 3\n\
+        emuVersion 22020\n\
+        rerecordCount 3\n\
+        palFlag 0\n\
+        romFilename smb\n\
+        guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n\
+        fourscore 0\n\
+        port0 1\n\
+        port1 2\n\
+        port2 0\n\
+        |1|........|0 0 0 0 0||\n\
+        |0|R..U...A|128 100 1 0 0||\n";
+
+    #[test]
+    fn test_parse() {
+        let movie = parse(MOVIE).unwrap();
+        assert_eq!(movie.rom_filename, "smb");
+        assert_eq!(movie.ports, [InputKind::Controller, InputKind::Zapper]);
+        assert_eq!(movie.frames.len(), 2);
+        assert_eq!(movie.frames[0].commands, 1);
+        assert_eq!(movie.frames[1].controllers[0], Buttons::RIGHT | Buttons::UP | Buttons::A);
+        assert_eq!(movie.frames[1].zapper[1], Some(ZapperInput { x: 128, y: 100, trigger: true }));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let movie = parse(MOVIE).unwrap();
+        let text = write(&movie);
+        assert!(text.contains("guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n"));
+        assert!(text.ends_with("|0|R..U...A|128 100 1 0 0||\n"));
+        assert_eq!(parse(&text).unwrap().frames, movie.frames);
+
+        let mut movie = Movie::new(InputKind::FourScore, InputKind::FourScore);
+        let mut frame = FrameInput::new();
+        frame.controllers[3] = Buttons::START;
+        movie.frames.push(frame);
+        let text = write(&movie);
+        assert!(text.ends_with("|0|........|........|........|....T...||\n"));
+        assert_eq!(parse(&text).unwrap().frames, movie.frames);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("version 2\n").is_err());
+        assert!(parse("version 3\n|0|RLDU|........||\n").is_err());
+        assert!(parse("port0 7\n").is_err());
+        assert!(parse("version 3\n|x|........|........||\n").is_err());
+    }
+}
@@ -0,0 +1,163 @@
+// Golden log conformance test for the CPU. Runs test_roms/nestest.nes in
+// automation mode (PC = 0xC000) and compares the CPU state before each
+// instruction with test_roms/nestest.log.
+use crate::nes::*;
+use crate::nes::cpu::ExecMode;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+const ROM_PATH: &str = "test_roms/nestest.nes";
+const LOG_PATH: &str = "test_roms/nestest.log";
+
+// nestest entry point when run without a PPU
+const START_ADDR: Addr = 0xC000;
+
+// number of successfully compared lines to show above a divergence
+const CONTEXT_LINES: usize = 8;
+
+// The part of a trace line that is compared against the golden log.
+#[derive(Debug,PartialEq)]
+struct TraceState {
+    pc: Addr,
+    a: Byte,
+    x: Byte,
+    y: Byte,
+    p: Byte,
+    sp: Byte,
+    cyc: u64,
+}
+
+impl TraceState {
+    // Parse a trace line. Works for both nestest.log and CPU::trace lines,
+    // since both start with the pc and contain the same register fields.
+    fn parse(line: &str) -> Option<Self> {
+        let pc = Addr::from_str_radix(line.get(0..4)?, 16).ok()?;
+        let regs = &line[line.find("A:")?..];
+        Some(TraceState {
+            pc,
+            a: Byte::from_str_radix(TraceState::field(regs, "A:")?, 16).ok()?,
+            x: Byte::from_str_radix(TraceState::field(regs, "X:")?, 16).ok()?,
+            y: Byte::from_str_radix(TraceState::field(regs, "Y:")?, 16).ok()?,
+            p: Byte::from_str_radix(TraceState::field(regs, "P:")?, 16).ok()?,
+            sp: Byte::from_str_radix(TraceState::field(regs, "SP:")?, 16).ok()?,
+            cyc: TraceState::field(regs, "CYC:")?.parse().ok()?,
+        })
+    }
+
+    // value of a whitespace separated "NAME:value" field
+    fn field<'a>(regs: &'a str, name: &str) -> Option<&'a str> {
+        regs.split_whitespace()
+            .find(|token| token.starts_with(name))
+            .map(|token| &token[name.len()..])
+    }
+
+    // names of all fields that differ from other
+    fn diff(&self, other: &TraceState) -> Vec<String> {
+        let mut diffs = Vec::new();
+        if self.pc != other.pc {
+            diffs.push(format!("PC: expected {:04X}, got {:04X}", self.pc, other.pc));
+        }
+        if self.a != other.a {
+            diffs.push(format!("A: expected {:02X}, got {:02X}", self.a, other.a));
+        }
+        if self.x != other.x {
+            diffs.push(format!("X: expected {:02X}, got {:02X}", self.x, other.x));
+        }
+        if self.y != other.y {
+            diffs.push(format!("Y: expected {:02X}, got {:02X}", self.y, other.y));
+        }
+        if self.p != other.p {
+            diffs.push(format!("P: expected {:02X} ({:08b}), got {:02X} ({:08b})",
+                self.p, self.p, other.p, other.p));
+        }
+        if self.sp != other.sp {
+            diffs.push(format!("SP: expected {:02X}, got {:02X}", self.sp, other.sp));
+        }
+        if self.cyc != other.cyc {
+            diffs.push(format!("CYC: expected {}, got {}", self.cyc, other.cyc));
+        }
+        diffs
+    }
+}
+
+// A log line from nestest.log and the matching line produced by the CPU
+struct TracePair {
+    line_nr: usize,
+    expected: String,
+    actual: String,
+}
+
+impl fmt::Display for TracePair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:5} expected: {}\n      actual:   {}",
+            self.line_nr, self.expected, self.actual)
+    }
+}
+
+// Run nestest and compare every instruction with the log
+fn run_nestest(mode: ExecMode) {
+    let log = fs::read_to_string(LOG_PATH).unwrap();
+
+    let mut nes = NES::new();
+    nes.insert_cartridge(Cartridge::new(Path::new(ROM_PATH)).unwrap());
+    nes.start();
+    nes.cpu.mode = mode;
+    nes.cpu.regs.pc = START_ADDR;
+
+    let mut history: VecDeque<TracePair> = VecDeque::with_capacity(CONTEXT_LINES);
+    for (idx, expected) in log.lines().enumerate() {
+        let actual = nes.cpu.trace(&nes.bus);
+        let pair = TracePair {
+            line_nr: idx + 1,
+            expected: expected.to_string(),
+            actual,
+        };
+
+        let expected_state = TraceState::parse(&pair.expected)
+            .unwrap_or_else(|| panic!("Malformed log line {}: {}", pair.line_nr, pair.expected));
+        let actual_state = TraceState::parse(&pair.actual)
+            .unwrap_or_else(|| panic!("Malformed trace line: {}", pair.actual));
+
+        let diffs = expected_state.diff(&actual_state);
+        if !diffs.is_empty() {
+            let context: Vec<String> = history.iter().map(|p| p.to_string()).collect();
+            panic!("CPU diverged from nestest.log at line {}:\n{}\n\nContext:\n{}\n{}",
+                pair.line_nr, diffs.join("\n"), context.join("\n"), pair);
+        }
+
+        if history.len() == CONTEXT_LINES {
+            history.pop_front();
+        }
+        history.push_back(pair);
+        assert_eq!(nes.clock_instruction(), StepResult::Running);
+    }
+
+    // nestest stores the result codes of the official and unofficial
+    // opcode tests in 0x02 and 0x03. 0x00 means everything passed.
+    assert_eq!(nes.bus.readb(0x0002), 0x00, "official opcode test failed");
+    assert_eq!(nes.bus.readb(0x0003), 0x00, "unofficial opcode test failed");
+}
+
+#[test]
+fn test_nestest_golden_log() {
+    run_nestest(ExecMode::Instruction);
+}
+
+#[test]
+fn test_nestest_golden_log_cycle_mode() {
+    run_nestest(ExecMode::Cycle);
+}
+
+#[test]
+fn test_trace_state_parse() {
+    let line = "C5F7  86 00     STX $00 = 00                    A:00 X:01 Y:02 P:26 SP:FD PPU: 15,  0 CYC:12";
+    let state = TraceState::parse(line).unwrap();
+    assert_eq!(state, TraceState { pc: 0xC5F7, a: 0x00, x: 0x01, y: 0x02, p: 0x26, sp: 0xFD, cyc: 12 });
+
+    // LSR A must not be confused with the A register
+    let line = "CF2E  4A        LSR A                           A:01 X:00 Y:00 P:24 SP:FB PPU: 15,  0 CYC:12";
+    let state = TraceState::parse(line).unwrap();
+    assert_eq!(state.a, 0x01);
+}
@@ -1,9 +1,15 @@
-use crate::nes::ppubus::PPUMemory;
+use crate::nes::ppubus::{PPUMemory,PALETTE_ADDR_RANGE};
 use crate::nes::types::*;
 use image::{ImageBuffer, Rgba};
 use palette::PALETTE;
+use loopy::VramAddr;
+use background::Background;
+use sprites::{Sprites,OAM_SIZE};
 
 pub mod palette;
+pub mod loopy;
+mod background;
+mod sprites;
 
 pub type Pixel = Rgba<u8>;
 pub type Sprite = ImageBuffer<Pixel, Vec<u8>>;
@@ -67,10 +73,7 @@ pub struct Registers {
     pub oam_addr: Byte,
     // 0x2004
     pub oam_data: Byte,
-    // 0x2005
-    pub scroll: Byte,
-    // 0x2006
-    pub addr: Addr,
+    // 0x2005 and 0x2006 write into the loopy registers of the PPU
     // 0x2007
     pub data: Byte, 
     // 0x2008
@@ -85,14 +88,20 @@ impl Registers {
            status: Status::from_bits(0x00).unwrap(),
            oam_addr: 0x00,
            oam_data: 0x00,
-           scroll: 0x00,
-           addr: 0x00,
            data: 0x00,
            dma: 0x00, 
         }
     }
 }
 
+// last dot and scanline of a frame. Scanline 261 is the pre-render line
+const LAST_CYCLE: u16 = 340;
+const PRE_RENDER_LINE: u16 = 261;
+const VISIBLE_LINES: u16 = 240;
+
+// Frames until an unrefreshed bit of the I/O latch decays to 0. Measured
+// values are around 600ms
+const IO_LATCH_DECAY_FRAMES: u64 = 36;
 
 pub struct PPU {
     pub regs: Registers,
@@ -103,8 +112,25 @@ pub struct PPU {
     pub canvas_main: Sprite,
     pub pattern_tables: [Sprite; 2],
     pub palettes: [Sprite; 8],
+    // loopy registers: v, t, fine x scroll and the shared write latch w
+    // of $2005/$2006
+    pub vram_addr: VramAddr,
+    pub tram_addr: VramAddr,
+    pub fine_x: Byte,
     addr_latch_set: bool,
     data_buffer: Byte,
+    odd_frame: bool,
+    bg: Background,
+    // object attribute memory: 64 sprites of 4 bytes (y, tile, attributes, x)
+    pub oam: [Byte; OAM_SIZE],
+    sprites: Sprites,
+    // The I/O latch holds the last value written to or read from a PPU
+    // register. Reads of write-only registers and of unused bits return
+    // it. Each bit decays on its own if it is not refreshed.
+    io_latch: Byte,
+    io_latch_refresh: [u64; 8],  // frame of the last refresh of each bit
+    pub io_latch_decay: bool,
+    frame_count: u64,
 }
 
 impl PPU {
@@ -130,13 +156,29 @@ impl PPU {
                 ImageBuffer::from_pixel(4, 1, PALETTE[&0x00]),
                 ImageBuffer::from_pixel(4, 1, PALETTE[&0x00]),
             ], 
+            vram_addr: VramAddr(0),
+            tram_addr: VramAddr(0),
+            fine_x: 0,
             addr_latch_set: false,
-            data_buffer: 0
+            data_buffer: 0,
+            odd_frame: false,
+            bg: Background::new(),
+            oam: [0; OAM_SIZE],
+            sprites: Sprites::new(),
+            io_latch: 0x00,
+            io_latch_refresh: [0; 8],
+            io_latch_decay: true,
+            frame_count: 0,
         }
     }
 
     pub fn reset(&mut self) {
         self.regs = Registers::new();
+        self.tram_addr = VramAddr(0);
+        self.fine_x = 0;
+        self.addr_latch_set = false;
+        self.data_buffer = 0;
+        self.odd_frame = false;
     }
 
     fn set_status(&mut self, flag: Status, val: bool) {
@@ -163,6 +205,15 @@ impl PPU {
         self.regs.ctrl.contains(flag)
     }
 
+    // background or sprites are enabled
+    fn rendering_enabled(&self) -> bool {
+        self.regs.mask.intersects(Mask::RENDER_BG | Mask::RENDER_SPRITES)
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.get_control(Control::SPRITE_SIZE) { 16 } else { 8 }
+    }
+
     
 
     // PPU renders 262 scanlines with 341 clocks per line. One px per clock
@@ -176,12 +227,18 @@ impl PPU {
     // Scanline 240: PPU idle
     // Scanline 241-260: Vblack. Flag is set during second clock of 241 together
     // with NMI 
-    pub fn clock<T: PPUMemory>(&mut self, _mem: &mut T) {
-        if self.cycle == 340 {
+    // Scanline 261: Pre-render line. Same fetches as a visible line, but no
+    // pixels. The last clock is skipped on odd frames while rendering.
+    pub fn clock<T: PPUMemory>(&mut self, mem: &mut T) {
+        let skip_last_cycle = self.odd_frame && self.rendering_enabled()
+            && self.scanline == PRE_RENDER_LINE && self.cycle == LAST_CYCLE - 1;
+        if self.cycle == LAST_CYCLE || skip_last_cycle {
             self.cycle = 0;
-            if self.scanline == 261 {
+            if self.scanline == PRE_RENDER_LINE {
                 self.scanline = 0;
                 self.frame_ready = true;
+                self.frame_count += 1;
+                self.odd_frame = !self.odd_frame;
             } else {
                 self.scanline += 1;
             }
@@ -189,6 +246,14 @@ impl PPU {
             self.cycle += 1;
         };
 
+        if self.scanline < VISIBLE_LINES || self.scanline == PRE_RENDER_LINE {
+            self.clock_background(mem);
+            self.clock_sprites(mem);
+        }
+        if self.scanline < VISIBLE_LINES && (1..=256).contains(&self.cycle) {
+            self.render_pixel(mem);
+        }
+
         // set/clear vblank flag
         if self.scanline == 241 && self.cycle == 1 {
             self.set_status(Status::VERTICAL_BLANK, true);
@@ -198,17 +263,140 @@ impl PPU {
 
         } else if self.scanline == 261 && self.cycle == 1 {
             self.set_status(Status::VERTICAL_BLANK, false);
+            self.set_status(Status::SPRITE_ZERO_HIT, false);
+            self.set_status(Status::SPRITE_OVERFOLW, false);
+        }
+    }
+
+    // Background fetches and scrolling of visible and pre-render lines.
+    //      Cycle 1-256 and 321-336: shift and fetch the next tile every 8
+    //      dots, then move v to the next tile
+    //      Cycle 256: move v to the next pixel row
+    //      Cycle 257: reset the horizontal position of v from t
+    //      Cycle 280-304 (pre-render only): reset the vertical position
+    //      Cycle 337-340: two unused nametable fetches
+    fn clock_background<T: PPUMemory>(&mut self, mem: &T) {
+        if !self.rendering_enabled() {
+            return
+        }
+
+        let cycle = self.cycle;
+        if (2..=257).contains(&cycle) || (321..=337).contains(&cycle) {
+            self.bg.shift();
+            let pattern_base = if self.get_control(Control::PATTERN_BG_ADDR) { 0x1000 } else { 0x0000 };
+            let step = (cycle - 1) % 8;
+            self.bg.fetch(mem, step, self.vram_addr, pattern_base);
+            if step == 7 {
+                self.vram_addr.increment_x();
+            }
+        }
+        if cycle == 256 {
+            self.vram_addr.increment_y();
+        }
+        if cycle == 257 {
+            self.vram_addr.copy_horizontal(self.tram_addr);
+        }
+        if cycle == 338 || cycle == LAST_CYCLE {
+            self.bg.fetch_unused(mem, self.vram_addr);
+        }
+        if self.scanline == PRE_RENDER_LINE && (280..=304).contains(&cycle) {
+            self.vram_addr.copy_vertical(self.tram_addr);
+        }
+    }
+
+    // Sprite evaluation and fetches of visible and pre-render lines.
+    //      Cycle 1-64: clear secondary OAM
+    //      Cycle 65-256: find the sprites of the next line (visible only)
+    //      Cycle 257-320: fetch their patterns. OAM addr is reset to 0
+    fn clock_sprites<T: PPUMemory>(&mut self, mem: &T) {
+        if !self.rendering_enabled() {
+            return
+        }
+
+        let cycle = self.cycle;
+        let height = self.sprite_height();
+        match cycle {
+            1 => self.sprites.clear_secondary_oam(),
+            65 => {
+                if self.scanline < VISIBLE_LINES {
+                    self.sprites.start_evaluation();
+                } else {
+                    self.sprites.skip_evaluation();
+                }
+            },
+            // one read/write pair every second dot
+            66..=256 if cycle & 0x01 == 0 => {
+                let overflow = self.sprites.evaluate(&self.oam, self.scanline, height);
+                if overflow {
+                    self.set_status(Status::SPRITE_OVERFOLW, true);
+                }
+            },
+            257..=320 => {
+                self.regs.oam_addr = 0;
+                if (cycle - 257) % 8 == 7 {
+                    let slot = ((cycle - 257) / 8) as usize;
+                    let pattern_base = if self.get_control(Control::PATTERN_SPRITE_ADDR) { 0x1000 } else { 0x0000 };
+                    self.sprites.fetch(mem, slot, self.scanline, height, pattern_base);
+                }
+            },
+            _ => { }
         }
     }
 
+    // Draw the pixel of the current dot into the main canvas
+    fn render_pixel<T: PPUMemory>(&mut self, mem: &T) {
+        let x = self.cycle - 1;
+        let show_bg = self.get_mask(Mask::RENDER_BG)
+            && (x >= 8 || self.get_mask(Mask::RENDER_BG_LEFT));
+        let show_sprites = self.get_mask(Mask::RENDER_SPRITES)
+            && (x >= 8 || self.get_mask(Mask::RENDER_SPRITES_LEFT));
+        let (bg_pixel, bg_palette) = if show_bg {
+            self.bg.pixel(self.fine_x)
+        } else {
+            (0, 0)
+        };
+        let sprite = if show_sprites { self.sprites.pixel(x) } else { None };
+
+        let (pixel, palette) = match sprite {
+            Some(sprite) => {
+                // sprite zero hit: opaque sprite 0 pixel over an opaque
+                // background pixel. Never on the last pixel of the line
+                if sprite.sprite_zero && bg_pixel > 0 && x != 255 {
+                    self.set_status(Status::SPRITE_ZERO_HIT, true);
+                }
+                if bg_pixel == 0 || !sprite.behind_bg {
+                    (sprite.pixel, sprite.palette)
+                } else {
+                    (bg_pixel, bg_palette)
+                }
+            },
+            None => (bg_pixel, bg_palette),
+        };
+
+        // pixel value 0 is transparent and shows the backdrop color 0x3F00
+        let color = if pixel == 0 {
+            self.get_output_color(mem, 0, 0)
+        } else {
+            self.get_output_color(mem, palette, pixel)
+        };
+        self.canvas_main.put_pixel(x as u32, self.scanline as u32, color);
+    }
+
+    fn get_mask(&self, flag: Mask) -> bool {
+        self.regs.mask.contains(flag)
+    }
+
     // read from the main bus
     pub fn readb<T: PPUMemory>(&mut self, mem: &T, addr: Addr) -> Byte {
        // Only certain registers of the PPU can actually by read
-       // remaining registers and read attemps will return garbage
+       // remaining registers and read attemps will return the I/O latch
+        let latch = self.io_latch();
         match addr {
             // status
             0x2002 => {
-                let status = self.regs.status.bits();
+                // only the upper 3 bits are driven
+                let status = self.regs.status.bits() & 0xE0 | latch & 0x1F;
+                self.refresh_io_latch(status, 0xE0);
                 // Reading the status register also clears VBLANK and the
                 // address latch
                 self.set_status(Status::VERTICAL_BLANK, false);
@@ -216,74 +404,148 @@ impl PPU {
                 status
             },
             // oam data 
-            0x2004 => { /* TODO */ 0x00 },
+            0x2004 => {
+                let mut data = self.oam[self.regs.oam_addr as usize];
+                // bits 2-4 of the attribute byte do not exist
+                if self.regs.oam_addr & 0x03 == 0x02 {
+                    data &= 0xE3;
+                }
+                self.refresh_io_latch(data, 0xFF);
+                data
+            },
             // ppu data
             0x2007 => { 
                 // ppu reads are delayed by one clock. Therefore, this uses
                 // a buffer variable to return the data from the previous
                 // read, and then set the new data to the buffer. However,
                 // because the PPU is weird, this does not apply for the 
-                // palette memory
-                let data = self.data_buffer;
-                self.data_buffer = mem.readb_ppu(addr);
-
-                if addr > 0x3F00 {  // everything above 0x3F00 is palette
-                   self.data_buffer 
+                // palette memory: Palette reads return immediately and
+                // fill the buffer with the nametable byte "below" the
+                // palette
+                let addr = self.vram_addr.0 & 0x3FFF;
+                let data = if addr >= PALETTE_ADDR_RANGE[0] {
+                    // palette entries are 6 bit. The upper bits are
+                    // open bus
+                    self.data_buffer = mem.readb_ppu(addr - 0x1000);
+                    let data = mem.readb_ppu(addr) & 0x3F | latch & 0xC0;
+                    self.refresh_io_latch(data, 0x3F);
+                    data
                 } else {
+                    let data = self.data_buffer;
+                    self.data_buffer = mem.readb_ppu(addr);
+                    self.refresh_io_latch(data, 0xFF);
                     data
-                }
+                };
+                self.increment_vram_addr();
+                self.regs.data = data;
+                data
             },
-            _ => 0x00,  // unmapped reads                      
+            _ => latch,  // write-only registers
         } 
     }
 
     // // write to the main bus
     pub fn writeb<T: PPUMemory>(&mut self, mem: &mut T, addr: Addr, data: Byte) {
+        // every write fills the I/O latch, even to read-only registers
+        self.refresh_io_latch(data, 0xFF);
+
         // Only some of the PPU regs can be written to
         match addr {
             // Control 
             0x2000 => { 
-                self.regs.ctrl = Control::from_bits(data).unwrap()
+                self.regs.ctrl = Control::from_bits(data).unwrap();
+                // the nametable bits are the high bits of the scroll position
+                self.tram_addr.set_nametable(data as Addr & 0x03);
             },
             // Mask 
             0x2001 => {
                 self.regs.mask = Mask::from_bits(data).unwrap()
             },
             // OAM address
-            0x2003 => { /* TODO */ },
+            0x2003 => {
+                self.regs.oam_addr = data;
+            },
             // OAM data
-            0x2004 => { /* TODO */ },
+            0x2004 => {
+                self.regs.oam_data = data;
+                self.oam[self.regs.oam_addr as usize] = data;
+                self.regs.oam_addr = self.regs.oam_addr.wrapping_add(1);
+            },
             // Scroll
-            0x2005 => { /* TODO */ },
+            0x2005 => {
+                // first write is the x scroll, second write the y scroll.
+                // Both are split into the tile (coarse) and the pixel
+                // within the tile (fine)
+                if !self.addr_latch_set {
+                    self.tram_addr.set_coarse_x(data as Addr >> 3);
+                    self.fine_x = data & 0x07;
+                } else {
+                    self.tram_addr.set_coarse_y(data as Addr >> 3);
+                    self.tram_addr.set_fine_y(data as Addr & 0x07);
+                }
+                self.addr_latch_set = !self.addr_latch_set;
+            },
             // Addr
             0x2006 => {
                 // To write a 16bit addr to the ppu, two consecutive writes are 
                 // required to set the hi and lo byte of the address.
                 // addr_latch_set indicates wether the hi byte is already
-                // set or not
+                // set or not. The address is built in t and copied to v
+                // after the second write.
                 if !self.addr_latch_set {
-                    self.regs.addr = self.regs.addr & 0x00FF | (data as Word) << 8;
+                    self.tram_addr.0 = self.tram_addr.0 & 0x00FF | (data as Word & 0x3F) << 8;
                 } else {
-                    self.regs.addr = self.regs.addr & 0xFF00 | data as Word;
+                    self.tram_addr.0 = self.tram_addr.0 & 0xFF00 | data as Word;
+                    self.vram_addr = self.tram_addr;
                 }
                 self.addr_latch_set = !self.addr_latch_set;
             }
             // write data to the ppu addr bus
             0x2007 => {
-                mem.writeb_ppu(self.regs.addr, data);
-                // after write, increment vram addr for next write.
-                // The increment value is determined by the vertical mode
-                // flag of the status reg 0: +1, 1: +32
-                self.regs.addr += if self.get_control(Control::INCREMENT_MODE) {
-                    32 
-                } else {
-                    1
-                }
+                self.regs.data = data;
+                mem.writeb_ppu(self.vram_addr.0 & 0x3FFF, data);
+                self.increment_vram_addr();
             },
             _ => { } // unwriteable addr, do nothing
         }
     }
 
+    // Current value of the I/O latch with decayed bits cleared
+    fn io_latch(&self) -> Byte {
+        if !self.io_latch_decay {
+            return self.io_latch
+        }
+        let mut latch = self.io_latch;
+        for (bit, refresh) in self.io_latch_refresh.iter().enumerate() {
+            if self.frame_count - refresh >= IO_LATCH_DECAY_FRAMES {
+                latch &= !(1 << bit);
+            }
+        }
+        latch
+    }
+
+    // Put the bits of data selected by mask into the I/O latch
+    fn refresh_io_latch(&mut self, data: Byte, mask: Byte) {
+        self.io_latch = self.io_latch() & !mask | data & mask;
+        for (bit, refresh) in self.io_latch_refresh.iter_mut().enumerate() {
+            if mask & (1 << bit) > 0 {
+                *refresh = self.frame_count;
+            }
+        }
+    }
+
+    // after a read or write of 0x2007, increment vram addr for the next
+    // access. The increment value is determined by the vertical mode
+    // flag of the control reg 0: +1, 1: +32
+    fn increment_vram_addr(&mut self) {
+        let increment = if self.get_control(Control::INCREMENT_MODE) {
+            32 
+        } else {
+            1
+        };
+        self.vram_addr.0 = self.vram_addr.0.wrapping_add(increment) & 0x7FFF;
+    }
+
     // get a colored pixel using the NES color palette for given palette_id
     // and pixel value
     fn get_color_from_ram<T: PPUMemory>(&self, mem: &T, palette_id: u8, pixel: u8) -> Pixel {
@@ -297,6 +559,17 @@ impl PPU {
         PALETTE[&palette_idx]
     }
 
+    // Color of a rendered pixel. Applies the grayscale bit of the mask
+    // register
+    fn get_output_color<T: PPUMemory>(&self, mem: &T, palette_id: u8, pixel: u8) -> Pixel {
+        let palette_idx_addr = 0x3F00 + ((palette_id as Word) << 2) + pixel as Word;
+        let mut palette_idx = mem.readb_ppu(palette_idx_addr) & 0x3F;
+        if self.get_mask(Mask::GRAYSCALE) {
+            palette_idx &= 0x30;
+        }
+        PALETTE[&palette_idx]
+    }
+
     // updates the palette from VRAM and returns a sprite with the 4 colors
     pub fn get_palette<T: PPUMemory>(&mut self, mem: &T, palette_id: u8) -> &Sprite {
         for i in 0..4 {
@@ -337,8 +610,8 @@ impl PPU {
                     // two least significant bits of the color (value between
                     // 0-3). Two bitplanes in memory each having one byte per
                     // row 
-                    let mut tile_lsb = mem.readb_ppu(tile_addr);
-                    let mut tile_msb = mem.readb_ppu(tile_addr + 8);
+                    let mut tile_lsb = mem.peekb_ppu(tile_addr);
+                    let mut tile_msb = mem.peekb_ppu(tile_addr + 8);
                     for col in 0..8 {
                         let pixel = (tile_lsb & 0x01) + (tile_msb & 0x01);
                         self.pattern_tables[index].put_pixel(
@@ -406,12 +679,399 @@ mod tests {
     fn test_write_addr() {
         let mut ppu = PPU::new();
         let mut ppu_bus = PPUBus::new();
-        assert_eq!(ppu.regs.addr, 0x0000);
+        assert_eq!(ppu.vram_addr.0, 0x0000);
         ppu.writeb(&mut ppu_bus, 0x2006, 0x12);
-        assert_eq!(ppu.regs.addr, 0x1200);
+        assert_eq!(ppu.tram_addr.0, 0x1200);
+        assert_eq!(ppu.vram_addr.0, 0x0000);
         ppu.writeb(&mut ppu_bus, 0x2006, 0x34);
-        assert_eq!(ppu.regs.addr, 0x1234);
+        assert_eq!(ppu.vram_addr.0, 0x1234);
+        // bit 14 is cleared by the first write
         ppu.writeb(&mut ppu_bus, 0x2006, 0x56);
-        assert_eq!(ppu.regs.addr, 0x5634);
+        assert_eq!(ppu.tram_addr.0, 0x1634);
+        assert_eq!(ppu.vram_addr.0, 0x1234);
+        ppu.writeb(&mut ppu_bus, 0x2006, 0x78);
+        assert_eq!(ppu.vram_addr.0, 0x1678);
+    }
+
+    // point v to addr
+    fn set_vram_addr<T: PPUMemory>(ppu: &mut PPU, mem: &mut T, addr: Addr) {
+        ppu.writeb(mem, 0x2006, (addr >> 8) as Byte);
+        ppu.writeb(mem, 0x2006, addr as Byte);
+    }
+
+    #[test]
+    fn test_read_data_buffered() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x2100] = 0x11;
+        vram.data[0x2101] = 0x22;
+        vram.data[0x2102] = 0x33;
+        ppu.data_buffer = 0xAB;
+
+        // the first read returns the stale buffer
+        set_vram_addr(&mut ppu, &mut vram, 0x2100);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0xAB);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x11);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x22);
+        assert_eq!(ppu.vram_addr.0, 0x2103);
+
+        // changing the address does not refill the buffer
+        set_vram_addr(&mut ppu, &mut vram, 0x2100);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x33);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x11);
+    }
+
+    #[test]
+    fn test_read_data_palette() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x2F01] = 0x55;
+        vram.data[0x2F02] = 0x66;
+        vram.data[0x3F02] = 0x27;
+
+        // palette reads are not delayed but fill the buffer with the
+        // nametable byte below
+        set_vram_addr(&mut ppu, &mut vram, 0x3F01);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x30);
+        assert_eq!(ppu.data_buffer, 0x55);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x27);
+        assert_eq!(ppu.data_buffer, 0x66);
+
+        set_vram_addr(&mut ppu, &mut vram, 0x2000);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_read_data_increment() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x2020] = 0x01;
+        vram.data[0x2040] = 0x02;
+
+        ppu.writeb(&mut vram, 0x2000, Control::INCREMENT_MODE.bits());
+        set_vram_addr(&mut ppu, &mut vram, 0x2000);
+        ppu.readb(&vram, 0x2007);
+        assert_eq!(ppu.vram_addr.0, 0x2020);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x00);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x01);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0x02);
+        assert_eq!(ppu.vram_addr.0, 0x2080);
+
+        // writes increment the same way
+        ppu.writeb(&mut vram, 0x2000, 0x00);
+        ppu.writeb(&mut vram, 0x2007, 0x03);
+        ppu.writeb(&mut vram, 0x2007, 0x04);
+        assert_eq!(ppu.vram_addr.0, 0x2082);
+        assert_eq!(vram.data[0x2080..0x2082], [0x03, 0x04]);
+
+        // addresses above 0x3FFF mirror down
+        set_vram_addr(&mut ppu, &mut vram, 0x3FFF);
+        ppu.writeb(&mut vram, 0x2007, 0x05);
+        assert_eq!(vram.data[0x3FFF], 0x05);
+        assert_eq!(ppu.vram_addr.0, 0x4000);
+        ppu.writeb(&mut vram, 0x2007, 0x06);
+        assert_eq!(vram.data[0x0000], 0x06);
+    }
+
+    #[test]
+    fn test_io_latch() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+
+        // write-only registers return the last written value
+        ppu.writeb(&mut vram, 0x2002, 0xDF);
+        assert_eq!(ppu.readb(&vram, 0x2000), 0xDF);
+        assert_eq!(ppu.readb(&vram, 0x2005), 0xDF);
+
+        // status only drives the upper 3 bits
+        ppu.set_status(Status::SPRITE_ZERO_HIT, true);
+        assert_eq!(ppu.readb(&vram, 0x2002), 0x5F);
+        assert_eq!(ppu.readb(&vram, 0x2001), 0x5F);
+
+        // palette reads keep the upper 2 bits
+        ppu.writeb(&mut vram, 0x2006, 0x3F);
+        ppu.writeb(&mut vram, 0x2006, 0x01);
+        ppu.writeb(&mut vram, 0x2002, 0xC0);
+        assert_eq!(ppu.readb(&vram, 0x2007), 0xF0);
+        assert_eq!(ppu.readb(&vram, 0x2000), 0xF0);
+    }
+
+    #[test]
+    fn test_io_latch_decay() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        ppu.writeb(&mut vram, 0x2000, 0xFF);
+        ppu.frame_count = IO_LATCH_DECAY_FRAMES - 1;
+        // only the upper bits are refreshed by the status read
+        assert_eq!(ppu.readb(&vram, 0x2002), 0x1F);
+        assert_eq!(ppu.readb(&vram, 0x2003), 0x1F);
+        ppu.frame_count = IO_LATCH_DECAY_FRAMES;
+        assert_eq!(ppu.readb(&vram, 0x2003), 0x00);
+
+        // no decay
+        ppu.io_latch_decay = false;
+        ppu.writeb(&mut vram, 0x2000, 0xFF);
+        ppu.frame_count += 100;
+        assert_eq!(ppu.readb(&vram, 0x2003), 0xFF);
+    }
+
+    #[test]
+    fn test_write_scroll() {
+        // register sequence from the nesdev wiki scrolling article
+        let mut ppu = PPU::new();
+        let mut ppu_bus = PPUBus::new();
+        ppu.writeb(&mut ppu_bus, 0x2000, 0x00);
+        assert_eq!(ppu.tram_addr.0, 0x0000);
+        ppu.readb(&ppu_bus, 0x2002);
+        assert!(!ppu.addr_latch_set);
+        ppu.writeb(&mut ppu_bus, 0x2005, 0x7D);
+        assert_eq!(ppu.tram_addr.0, 0x000F);
+        assert_eq!(ppu.fine_x, 0x05);
+        ppu.writeb(&mut ppu_bus, 0x2005, 0x5E);
+        assert_eq!(ppu.tram_addr.0, 0x616F);
+        ppu.writeb(&mut ppu_bus, 0x2006, 0x3D);
+        assert_eq!(ppu.tram_addr.0, 0x3D6F);
+        ppu.writeb(&mut ppu_bus, 0x2006, 0xF0);
+        assert_eq!(ppu.tram_addr.0, 0x3DF0);
+        assert_eq!(ppu.vram_addr.0, 0x3DF0);
+
+        // nametable select
+        ppu.writeb(&mut ppu_bus, 0x2000, 0x03);
+        assert_eq!(ppu.tram_addr.nametable(), 3);
+        assert_eq!(ppu.regs.ctrl.bits(), 0x03);
+    }
+
+    // Flat 16kb vram without mirroring
+    struct TestVram {
+        data: Vec<Byte>,
+    }
+
+    impl PPUMemory for TestVram {
+        fn readb_ppu(&self, addr: Addr) -> Byte {
+            self.data[(addr & 0x3FFF) as usize]
+        }
+        fn writeb_ppu(&mut self, addr: Addr, data: Byte) {
+            self.data[(addr & 0x3FFF) as usize] = data;
+        }
+    }
+
+    // vram with a solid tile 1 (pixel value 1) and tile 2 (pixel value 3)
+    // in the first pattern table
+    fn test_vram() -> TestVram {
+        let mut vram = TestVram { data: vec![0; 0x4000] };
+        for row in 0..8 {
+            vram.data[0x0010 + row] = 0xFF;
+            vram.data[0x0020 + row] = 0xFF;
+            vram.data[0x0028 + row] = 0xFF;
+        }
+        // backdrop and palettes
+        vram.data[0x3F00] = 0x0F;
+        vram.data[0x3F01] = 0x30;
+        vram.data[0x3F03] = 0x16;
+        vram.data[0x3F0D] = 0x2A;
+        vram
+    }
+
+    fn render_frames(ppu: &mut PPU, vram: &mut TestVram, frames: usize) {
+        for _ in 0..frames {
+            while !ppu.frame_ready {
+                ppu.clock(vram);
+            }
+            ppu.frame_ready = false;
+        }
+    }
+
+    #[test]
+    fn test_render_background() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x2000] = 0x01;  // tile (0, 0)
+        vram.data[0x2021] = 0x02;  // tile (1, 1)
+        vram.data[0x23C8] = 0x03;  // palette 3 for tiles (0-1, 4-5)
+        vram.data[0x2080] = 0x01;  // tile (0, 4)
+        ppu.writeb(&mut vram, 0x2001, (Mask::RENDER_BG | Mask::RENDER_BG_LEFT).bits());
+        render_frames(&mut ppu, &mut vram, 2);
+
+        let backdrop = PALETTE[&0x0F];
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(*ppu.canvas_main.get_pixel(x, y), PALETTE[&0x30], "({}, {})", x, y);
+                assert_eq!(*ppu.canvas_main.get_pixel(x + 8, y + 8), PALETTE[&0x16], "({}, {})", x, y);
+                assert_eq!(*ppu.canvas_main.get_pixel(x, y + 32), PALETTE[&0x2A], "({}, {})", x, y);
+            }
+            assert_eq!(*ppu.canvas_main.get_pixel(8, y), backdrop);
+        }
+        assert_eq!(*ppu.canvas_main.get_pixel(255, 239), backdrop);
+
+        // leftmost 8 pixels hidden
+        ppu.writeb(&mut vram, 0x2001, Mask::RENDER_BG.bits());
+        render_frames(&mut ppu, &mut vram, 1);
+        assert_eq!(*ppu.canvas_main.get_pixel(0, 0), backdrop);
+        assert_eq!(*ppu.canvas_main.get_pixel(8, 8), PALETTE[&0x16]);
+    }
+
+    #[test]
+    fn test_render_background_scroll() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x2000] = 0x01;
+        vram.data[0x2400] = 0x01;  // tile (0, 0) of the right nametable
+        ppu.writeb(&mut vram, 0x2001, (Mask::RENDER_BG | Mask::RENDER_BG_LEFT).bits());
+
+        // scroll 4 pixels to the right and 2 pixels down
+        ppu.writeb(&mut vram, 0x2005, 4);
+        ppu.writeb(&mut vram, 0x2005, 2);
+        render_frames(&mut ppu, &mut vram, 2);
+        assert_eq!(*ppu.canvas_main.get_pixel(3, 5), PALETTE[&0x30]);
+        assert_eq!(*ppu.canvas_main.get_pixel(4, 5), PALETTE[&0x0F]);
+        assert_eq!(*ppu.canvas_main.get_pixel(3, 6), PALETTE[&0x0F]);
+        // the right nametable scrolls into view
+        assert_eq!(*ppu.canvas_main.get_pixel(252, 0), PALETTE[&0x30]);
+        assert_eq!(*ppu.canvas_main.get_pixel(251, 0), PALETTE[&0x0F]);
+    }
+
+    // clock until the start of scanline
+    fn clock_until(ppu: &mut PPU, vram: &mut TestVram, scanline: u16) {
+        while ppu.scanline != scanline {
+            ppu.clock(vram);
+        }
+    }
+
+    fn set_sprite(ppu: &mut PPU, index: usize, y: Byte, tile: Byte, attrib: Byte, x: Byte) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[y, tile, attrib, x]);
+    }
+
+    #[test]
+    fn test_oam_access() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        ppu.writeb(&mut vram, 0x2003, 0x10);
+        for data in [0x01, 0x02, 0xFF, 0x04].iter() {
+            ppu.writeb(&mut vram, 0x2004, *data);
+        }
+        assert_eq!(ppu.oam[0x10..0x14], [0x01, 0x02, 0xFF, 0x04]);
+        assert_eq!(ppu.regs.oam_addr, 0x14);
+
+        // reads do not increment. Unused attribute bits read as 0
+        ppu.writeb(&mut vram, 0x2003, 0x12);
+        assert_eq!(ppu.readb(&vram, 0x2004), 0xE3);
+        assert_eq!(ppu.readb(&vram, 0x2004), 0xE3);
+        ppu.writeb(&mut vram, 0x2003, 0x13);
+        assert_eq!(ppu.readb(&vram, 0x2004), 0x04);
+    }
+
+    #[test]
+    fn test_render_sprites() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x0030] = 0x80;  // tile 3: only the top left pixel
+        vram.data[0x3F11] = 0x21;
+        vram.data[0x3F13] = 0x22;
+        vram.data[0x3F17] = 0x23;
+        vram.data[0x2026] = 0x01;  // bg tile (6, 1)
+
+        set_sprite(&mut ppu, 0, 9, 0x01, 0x00, 20);
+        set_sprite(&mut ppu, 1, 9, 0x03, 0x40, 40);  // flipped horizontally
+        set_sprite(&mut ppu, 2, 9, 0x03, 0x80, 30);  // flipped vertically
+        set_sprite(&mut ppu, 3, 9, 0x02, 0x20, 48);  // behind bg
+        set_sprite(&mut ppu, 4, 9, 0x02, 0x01, 60);  // palette 5
+        ppu.writeb(&mut vram, 0x2001, 0x1E);
+        render_frames(&mut ppu, &mut vram, 2);
+
+        let pixel = |ppu: &PPU, x, y| *ppu.canvas_main.get_pixel(x, y);
+        // sprites are drawn one line below their y
+        assert_eq!(pixel(&ppu, 20, 9), PALETTE[&0x0F]);
+        assert_eq!(pixel(&ppu, 20, 10), PALETTE[&0x21]);
+        assert_eq!(pixel(&ppu, 27, 17), PALETTE[&0x21]);
+        assert_eq!(pixel(&ppu, 28, 17), PALETTE[&0x0F]);
+        assert_eq!(pixel(&ppu, 20, 18), PALETTE[&0x0F]);
+
+        assert_eq!(pixel(&ppu, 40, 10), PALETTE[&0x0F]);
+        assert_eq!(pixel(&ppu, 47, 10), PALETTE[&0x21]);
+        assert_eq!(pixel(&ppu, 30, 10), PALETTE[&0x0F]);
+        assert_eq!(pixel(&ppu, 30, 17), PALETTE[&0x21]);
+
+        // background wins over a sprite behind it, but only if opaque
+        assert_eq!(pixel(&ppu, 48, 10), PALETTE[&0x30]);
+        assert_eq!(pixel(&ppu, 48, 16), PALETTE[&0x22]);
+        assert_eq!(pixel(&ppu, 60, 10), PALETTE[&0x23]);
+    }
+
+    #[test]
+    fn test_render_sprites_8x16() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        for row in 0..8 {
+            vram.data[0x1050 + row] = 0xFF;  // tile 5 of the second table
+        }
+        vram.data[0x3F11] = 0x21;
+        // tile 0x05 selects tiles 4 and 5 of pattern table 1
+        set_sprite(&mut ppu, 0, 9, 0x05, 0x00, 100);
+        set_sprite(&mut ppu, 1, 9, 0x05, 0x80, 120);
+        ppu.writeb(&mut vram, 0x2000, Control::SPRITE_SIZE.bits());
+        ppu.writeb(&mut vram, 0x2001, 0x1E);
+        render_frames(&mut ppu, &mut vram, 2);
+
+        assert_eq!(*ppu.canvas_main.get_pixel(100, 17), PALETTE[&0x0F]);
+        assert_eq!(*ppu.canvas_main.get_pixel(100, 18), PALETTE[&0x21]);
+        assert_eq!(*ppu.canvas_main.get_pixel(100, 25), PALETTE[&0x21]);
+        assert_eq!(*ppu.canvas_main.get_pixel(100, 26), PALETTE[&0x0F]);
+        // flipped vertically: bottom tile on top
+        assert_eq!(*ppu.canvas_main.get_pixel(120, 10), PALETTE[&0x21]);
+        assert_eq!(*ppu.canvas_main.get_pixel(120, 18), PALETTE[&0x0F]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x2042] = 0x01;  // bg tile (2, 2): pixels 16-23, 16-23
+        ppu.writeb(&mut vram, 0x2001, 0x1E);
+
+        // sprite 1 overlaps, sprite 0 does not
+        set_sprite(&mut ppu, 0, 100, 0x01, 0x00, 100);
+        set_sprite(&mut ppu, 1, 15, 0x01, 0x00, 16);
+        render_frames(&mut ppu, &mut vram, 1);
+        clock_until(&mut ppu, &mut vram, 200);
+        assert!(!ppu.get_status(Status::SPRITE_ZERO_HIT));
+
+        // sprite 0 overlaps from line 20 on
+        set_sprite(&mut ppu, 0, 19, 0x01, 0x20, 20);
+        clock_until(&mut ppu, &mut vram, 20);
+        assert!(!ppu.get_status(Status::SPRITE_ZERO_HIT));
+        clock_until(&mut ppu, &mut vram, 21);
+        assert!(ppu.get_status(Status::SPRITE_ZERO_HIT));
+
+        // cleared on the pre-render line
+        clock_until(&mut ppu, &mut vram, 0);
+        assert!(!ppu.get_status(Status::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = PPU::new();
+        let mut vram = test_vram();
+        vram.data[0x3F11] = 0x21;
+        ppu.writeb(&mut vram, 0x2001, 0x1E);
+        for i in 0..64 {
+            set_sprite(&mut ppu, i, 0xF0, 0x00, 0x00, 0x00);
+        }
+        for i in 0..8 {
+            set_sprite(&mut ppu, i, 50, 0x01, 0x00, i as Byte * 8);
+        }
+        render_frames(&mut ppu, &mut vram, 1);
+        clock_until(&mut ppu, &mut vram, 100);
+        assert!(!ppu.get_status(Status::SPRITE_OVERFOLW));
+
+        // a 9th sprite sets the flag during evaluation of line 50
+        set_sprite(&mut ppu, 8, 50, 0x01, 0x00, 64);
+        clock_until(&mut ppu, &mut vram, 50);
+        assert!(!ppu.get_status(Status::SPRITE_OVERFOLW));
+        clock_until(&mut ppu, &mut vram, 51);
+        assert!(ppu.get_status(Status::SPRITE_OVERFOLW));
+        // only 8 sprites are drawn
+        clock_until(&mut ppu, &mut vram, 52);
+        assert_eq!(*ppu.canvas_main.get_pixel(63, 51), PALETTE[&0x21]);
+        assert_eq!(*ppu.canvas_main.get_pixel(64, 51), PALETTE[&0x0F]);
     }
 }
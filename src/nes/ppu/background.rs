@@ -0,0 +1,101 @@
+use crate::nes::ppubus::PPUMemory;
+use crate::nes::types::*;
+use super::loopy::VramAddr;
+
+// Background part of the rendering pipeline. Every 8 dots the PPU fetches
+// the nametable byte, the attribute byte and the two pattern bytes of the
+// next tile. The tile is loaded into the low byte of 16 bit shift
+// registers that shift once per dot, so the high byte always holds the
+// tile that is currently drawn.
+pub struct Background {
+    next_tile_id: Byte,
+    next_tile_attrib: Byte,
+    next_tile_lsb: Byte,
+    next_tile_msb: Byte,
+    pattern_lo: Word,
+    pattern_hi: Word,
+    attrib_lo: Word,
+    attrib_hi: Word,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Background {
+            next_tile_id: 0,
+            next_tile_attrib: 0,
+            next_tile_lsb: 0,
+            next_tile_msb: 0,
+            pattern_lo: 0,
+            pattern_hi: 0,
+            attrib_lo: 0,
+            attrib_hi: 0,
+        }
+    }
+
+    // Run step 0-7 of the 8 dot fetch cycle for the tile at v. Each memory
+    // access takes 2 dots, so only even steps fetch. Step 0 first loads the
+    // previously fetched tile into the shift registers.
+    pub fn fetch<T: PPUMemory>(&mut self, mem: &T, step: u16, v: VramAddr, pattern_base: Addr) {
+        match step {
+            0 => {
+                self.load();
+                self.next_tile_id = mem.readb_ppu(v.tile_addr());
+            },
+            2 => {
+                // each attribute byte holds the palettes of four 2x2 tile
+                // blocks: bottom right, bottom left, top right, top left
+                let mut attrib = mem.readb_ppu(v.attribute_addr());
+                if v.coarse_y() & 0x02 > 0 {
+                    attrib >>= 4;
+                }
+                if v.coarse_x() & 0x02 > 0 {
+                    attrib >>= 2;
+                }
+                self.next_tile_attrib = attrib & 0x03;
+            },
+            4 => self.next_tile_lsb = mem.readb_ppu(self.pattern_addr(v, pattern_base)),
+            6 => self.next_tile_msb = mem.readb_ppu(self.pattern_addr(v, pattern_base) + 8),
+            _ => { }
+        }
+    }
+
+    // Dummy nametable fetch at the end of the scanline. Nothing uses the
+    // result but mappers may watch the access.
+    pub fn fetch_unused<T: PPUMemory>(&mut self, mem: &T, v: VramAddr) {
+        self.next_tile_id = mem.readb_ppu(v.tile_addr());
+    }
+
+    // Each tile is 16 bytes: 8 rows of the low bitplane followed by 8 rows
+    // of the high bitplane
+    fn pattern_addr(&self, v: VramAddr, pattern_base: Addr) -> Addr {
+        pattern_base + ((self.next_tile_id as Addr) << 4) + v.fine_y()
+    }
+
+    // put the fetched tile into the low byte of the shift registers. The
+    // attribute is the same for all 8 pixels of the tile
+    fn load(&mut self) {
+        self.pattern_lo = self.pattern_lo & 0xFF00 | self.next_tile_lsb as Word;
+        self.pattern_hi = self.pattern_hi & 0xFF00 | self.next_tile_msb as Word;
+        let attrib_lo = if self.next_tile_attrib & 0x01 > 0 { 0xFF } else { 0x00 };
+        let attrib_hi = if self.next_tile_attrib & 0x02 > 0 { 0xFF } else { 0x00 };
+        self.attrib_lo = self.attrib_lo & 0xFF00 | attrib_lo;
+        self.attrib_hi = self.attrib_hi & 0xFF00 | attrib_hi;
+    }
+
+    pub fn shift(&mut self) {
+        self.pattern_lo <<= 1;
+        self.pattern_hi <<= 1;
+        self.attrib_lo <<= 1;
+        self.attrib_hi <<= 1;
+    }
+
+    // pixel value (0-3) and palette (0-3) of the current dot. Fine x
+    // selects the bit, which is how the background scrolls by single pixels
+    pub fn pixel(&self, fine_x: Byte) -> (Byte, Byte) {
+        let mux = 0x8000 >> fine_x;
+        let bit = |register: Word| if register & mux > 0 { 1 } else { 0 };
+        let pixel = bit(self.pattern_hi) << 1 | bit(self.pattern_lo);
+        let palette = bit(self.attrib_hi) << 1 | bit(self.attrib_lo);
+        (pixel, palette)
+    }
+}
@@ -0,0 +1,164 @@
+use crate::nes::types::*;
+
+// Internal VRAM address of the PPU, named after loopy who documented it.
+// The PPU keeps two of them: v, the current address used for rendering and
+// $2007 access, and t, the temporary address written by $2000/$2005/$2006
+// which is copied into v during rendering. Layout:
+//   yyy NN YYYYY XXXXX
+//   ||| || ||||| +++++-- coarse x scroll (tile column)
+//   ||| || +++++-------- coarse y scroll (tile row)
+//   ||| ++-------------- nametable select
+//   +++----------------- fine y scroll (row within the tile)
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct VramAddr(pub Addr);
+
+// bits copied from t to v at the end of each scanline and during the
+// pre-render scanline
+const HORIZONTAL_BITS: Addr = 0x041F;
+const VERTICAL_BITS: Addr = 0x7BE0;
+
+impl VramAddr {
+    pub fn coarse_x(&self) -> Addr {
+        self.0 & 0x001F
+    }
+
+    pub fn coarse_y(&self) -> Addr {
+        (self.0 >> 5) & 0x001F
+    }
+
+    pub fn nametable(&self) -> Addr {
+        (self.0 >> 10) & 0x0003
+    }
+
+    pub fn fine_y(&self) -> Addr {
+        (self.0 >> 12) & 0x0007
+    }
+
+    pub fn set_coarse_x(&mut self, val: Addr) {
+        self.0 = self.0 & !0x001F | (val & 0x1F);
+    }
+
+    pub fn set_coarse_y(&mut self, val: Addr) {
+        self.0 = self.0 & !0x03E0 | (val & 0x1F) << 5;
+    }
+
+    pub fn set_nametable(&mut self, val: Addr) {
+        self.0 = self.0 & !0x0C00 | (val & 0x03) << 10;
+    }
+
+    pub fn set_fine_y(&mut self, val: Addr) {
+        self.0 = self.0 & !0x7000 | (val & 0x07) << 12;
+    }
+
+    // Move to the next tile. Wraps into the horizontally adjacent nametable
+    pub fn increment_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.set_coarse_x(0);
+            self.0 ^= 0x0400;
+        } else {
+            self.set_coarse_x(self.coarse_x() + 1);
+        }
+    }
+
+    // Move to the next pixel row. Row 29 is the last tile row of a
+    // nametable and wraps into the vertically adjacent nametable. Rows 30
+    // and 31 point into the attribute table and wrap without switching.
+    pub fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.set_fine_y(self.fine_y() + 1);
+            return
+        }
+        self.set_fine_y(0);
+        match self.coarse_y() {
+            29 => {
+                self.set_coarse_y(0);
+                self.0 ^= 0x0800;
+            },
+            31 => self.set_coarse_y(0),
+            y => self.set_coarse_y(y + 1),
+        }
+    }
+
+    pub fn copy_horizontal(&mut self, t: VramAddr) {
+        self.0 = self.0 & !HORIZONTAL_BITS | t.0 & HORIZONTAL_BITS;
+    }
+
+    pub fn copy_vertical(&mut self, t: VramAddr) {
+        self.0 = self.0 & !VERTICAL_BITS | t.0 & VERTICAL_BITS;
+    }
+
+    // address of the current tile in the nametables
+    pub fn tile_addr(&self) -> Addr {
+        0x2000 | (self.0 & 0x0FFF)
+    }
+
+    // address of the attribute byte of the current tile. Each attribute
+    // byte covers 4x4 tiles
+    pub fn attribute_addr(&self) -> Addr {
+        0x23C0 | self.nametable() << 10 | ((self.coarse_y() >> 2) << 3) | (self.coarse_x() >> 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_increment_x() {
+        let mut v = VramAddr(0);
+        v.increment_x();
+        assert_eq!(v.coarse_x(), 1);
+
+        // wrap into the next nametable
+        v.set_coarse_x(31);
+        v.increment_x();
+        assert_eq!(v.coarse_x(), 0);
+        assert_eq!(v.nametable(), 1);
+        v.set_coarse_x(31);
+        v.increment_x();
+        assert_eq!(v.nametable(), 0);
+    }
+
+    #[test]
+    fn test_increment_y() {
+        let mut v = VramAddr(0);
+        v.set_fine_y(6);
+        v.increment_y();
+        assert_eq!((v.fine_y(), v.coarse_y()), (7, 0));
+        v.increment_y();
+        assert_eq!((v.fine_y(), v.coarse_y()), (0, 1));
+
+        // row 29 switches the vertical nametable
+        v.set_coarse_y(29);
+        v.set_fine_y(7);
+        v.increment_y();
+        assert_eq!((v.fine_y(), v.coarse_y(), v.nametable()), (0, 0, 2));
+
+        // rows 30 and 31 wrap without switching
+        v.set_coarse_y(31);
+        v.set_fine_y(7);
+        v.increment_y();
+        assert_eq!((v.fine_y(), v.coarse_y(), v.nametable()), (0, 0, 2));
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut v = VramAddr(0);
+        let t = VramAddr(0x7FFF);
+        v.copy_horizontal(t);
+        assert_eq!(v.0, 0x041F);
+        v.copy_vertical(t);
+        assert_eq!(v.0, 0x7FFF);
+    }
+
+    #[test]
+    fn test_fetch_addrs() {
+        let mut v = VramAddr(0);
+        v.set_nametable(3);
+        v.set_coarse_x(5);
+        v.set_coarse_y(9);
+        v.set_fine_y(2);
+        assert_eq!(v.tile_addr(), 0x2C00 + 9 * 32 + 5);
+        assert_eq!(v.attribute_addr(), 0x2FC0 + 2 * 8 + 1);
+    }
+}
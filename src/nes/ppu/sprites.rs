@@ -0,0 +1,281 @@
+use crate::nes::ppubus::PPUMemory;
+use crate::nes::types::*;
+
+pub const OAM_SIZE: usize = 256;
+const SECONDARY_OAM_SIZE: usize = 32;
+// sprites per scanline
+const MAX_SPRITES: usize = 8;
+
+// Sprite attribute byte (byte 2 of an OAM entry)
+bitflags! {
+    pub struct Attributes: Byte {
+        const PALETTE_LO      = 1 << 0;
+        const PALETTE_HI      = 1 << 1;
+        const BEHIND_BG       = 1 << 5;
+        const FLIP_HORIZONTAL = 1 << 6;
+        const FLIP_VERTICAL   = 1 << 7;
+    }
+}
+
+// A sprite fetched for the current scanline
+#[derive(Clone,Copy)]
+struct SpriteUnit {
+    x: Byte,
+    attrib: Attributes,
+    pattern_lo: Byte,
+    pattern_hi: Byte,
+}
+
+impl SpriteUnit {
+    fn new() -> Self {
+        SpriteUnit { x: 0xFF, attrib: Attributes::empty(), pattern_lo: 0, pattern_hi: 0 }
+    }
+}
+
+// A visible sprite pixel
+pub struct SpritePixel {
+    pub pixel: Byte,  // 1-3
+    pub palette: Byte,  // 4-7
+    pub behind_bg: bool,
+    pub sprite_zero: bool,
+}
+
+// Sprite part of the rendering pipeline. During a visible scanline the PPU
+// searches primary OAM for the sprites of the next scanline and copies up
+// to 8 of them into secondary OAM (dots 1-256). Their pattern data is
+// fetched in dots 257-320 and drawn during the next scanline.
+pub struct Sprites {
+    secondary_oam: [Byte; SECONDARY_OAM_SIZE],
+    // evaluation state: sprite n, byte m, sprites found and bytes left to
+    // copy of an in-range sprite
+    n: usize,
+    m: usize,
+    found: usize,
+    copying: usize,
+    done: bool,
+    sprite_zero_next: bool,
+    // sprites of the current scanline
+    units: [SpriteUnit; MAX_SPRITES],
+    count: usize,
+    sprite_zero_line: bool,
+}
+
+impl Sprites {
+    pub fn new() -> Self {
+        Sprites {
+            secondary_oam: [0xFF; SECONDARY_OAM_SIZE],
+            n: 0,
+            m: 0,
+            found: 0,
+            copying: 0,
+            done: true,
+            sprite_zero_next: false,
+            units: [SpriteUnit::new(); MAX_SPRITES],
+            count: 0,
+            sprite_zero_line: false,
+        }
+    }
+
+    // Dots 1-64
+    pub fn clear_secondary_oam(&mut self) {
+        self.secondary_oam = [0xFF; SECONDARY_OAM_SIZE];
+    }
+
+    // Dot 65
+    pub fn start_evaluation(&mut self) {
+        self.n = 0;
+        self.m = 0;
+        self.found = 0;
+        self.copying = 0;
+        self.done = false;
+        self.sprite_zero_next = false;
+    }
+
+    // Nothing is found when rendering is off or on the pre-render line
+    pub fn skip_evaluation(&mut self) {
+        self.found = 0;
+        self.done = true;
+        self.sprite_zero_next = false;
+    }
+
+    // One read/write pair of the evaluation (every second dot of 66-256).
+    // Returns true if the sprite overflow flag is set by this step.
+    pub fn evaluate(&mut self, oam: &[Byte; OAM_SIZE], scanline: u16, height: u16) -> bool {
+        if self.done {
+            return false
+        }
+        let in_range = |y: Byte| {
+            let row = scanline as i32 - y as i32;
+            row >= 0 && row < height as i32
+        };
+
+        if self.found < MAX_SPRITES {
+            let data = oam[self.n * 4 + self.m];
+            self.secondary_oam[self.found * 4 + self.m] = data;
+            if self.copying > 0 {
+                // copy tile, attributes and x of an in-range sprite
+                self.copying -= 1;
+                self.m += 1;
+                if self.copying == 0 {
+                    self.m = 0;
+                    self.found += 1;
+                    self.next_sprite();
+                }
+            } else if in_range(data) {
+                if self.n == 0 {
+                    self.sprite_zero_next = true;
+                }
+                self.copying = 3;
+                self.m = 1;
+            } else {
+                self.next_sprite();
+            }
+            false
+        } else {
+            // Secondary OAM is full. The hardware keeps searching for an
+            // overflow, but increments m together with n. This reads tile,
+            // attribute and x bytes as y and gives false positives and
+            // negatives.
+            let data = oam[self.n * 4 + self.m];
+            if in_range(data) {
+                self.done = true;
+                true
+            } else {
+                self.m = (self.m + 1) & 0x03;
+                self.next_sprite();
+                false
+            }
+        }
+    }
+
+    fn next_sprite(&mut self) {
+        self.n += 1;
+        if self.n == OAM_SIZE / 4 {
+            self.done = true;
+        }
+    }
+
+    // Fetch the pattern of sprite slot (dots 257-320, one slot per 8
+    // dots). Empty slots fetch tile 0xFF and stay transparent.
+    pub fn fetch<T: PPUMemory>(&mut self, mem: &T, slot: usize, scanline: u16, height: u16,
+        pattern_base: Addr) {
+        let entry = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attrib, x) = (entry[0], entry[1], Attributes::from_bits_truncate(entry[2]), entry[3]);
+
+        let mut row = (scanline as i32 - y as i32).max(0) as Addr & (height - 1);
+        if attrib.contains(Attributes::FLIP_VERTICAL) {
+            row = height - 1 - row;
+        }
+
+        // 8x16 sprites take the pattern table from bit 0 of the tile index
+        // and are made of two consecutive tiles
+        let addr = if height == 16 {
+            let table = (tile as Addr & 0x01) << 12;
+            let tile = (tile as Addr & 0xFE) + if row >= 8 { 1 } else { 0 };
+            table + (tile << 4) + (row & 0x07)
+        } else {
+            pattern_base + ((tile as Addr) << 4) + row
+        };
+        let mut pattern_lo = mem.readb_ppu(addr);
+        let mut pattern_hi = mem.readb_ppu(addr + 8);
+
+        if slot >= self.found {
+            pattern_lo = 0;
+            pattern_hi = 0;
+        } else if attrib.contains(Attributes::FLIP_HORIZONTAL) {
+            pattern_lo = pattern_lo.reverse_bits();
+            pattern_hi = pattern_hi.reverse_bits();
+        }
+        self.units[slot] = SpriteUnit { x, attrib, pattern_lo, pattern_hi };
+
+        if slot == MAX_SPRITES - 1 {
+            self.count = self.found;
+            self.sprite_zero_line = self.sprite_zero_next;
+        }
+    }
+
+    // The first opaque sprite pixel at x. Lower OAM index wins
+    pub fn pixel(&self, x: u16) -> Option<SpritePixel> {
+        for (i, unit) in self.units[..self.count].iter().enumerate() {
+            let offset = x as i32 - unit.x as i32;
+            if !(0..8).contains(&offset) {
+                continue
+            }
+            let bit = 7 - offset as Byte;
+            let pixel = ((unit.pattern_hi >> bit) & 0x01) << 1 | ((unit.pattern_lo >> bit) & 0x01);
+            if pixel == 0 {
+                continue
+            }
+            return Some(SpritePixel {
+                pixel,
+                palette: 4 + (unit.attrib.bits() & 0x03),
+                behind_bg: unit.attrib.contains(Attributes::BEHIND_BG),
+                sprite_zero: i == 0 && self.sprite_zero_line,
+            })
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // run a full evaluation for scanline. Returns the step that set the
+    // overflow flag
+    fn evaluate(sprites: &mut Sprites, oam: &[Byte; OAM_SIZE], scanline: u16) -> Option<usize> {
+        sprites.clear_secondary_oam();
+        sprites.start_evaluation();
+        let mut overflow = None;
+        for step in 0..96 {
+            if sprites.evaluate(oam, scanline, 8) && overflow.is_none() {
+                overflow = Some(step);
+            }
+        }
+        overflow
+    }
+
+    #[test]
+    fn test_evaluation_limit() {
+        // 9 sprites on line 10
+        let mut oam = [0xFF; OAM_SIZE];
+        for i in 0..9 {
+            oam[i * 4] = 10;
+            oam[i * 4 + 1] = i as Byte;
+        }
+        let mut sprites = Sprites::new();
+        assert!(evaluate(&mut sprites, &oam, 12).is_some());
+        assert_eq!(sprites.found, 8);
+        assert!(sprites.sprite_zero_next);
+        for i in 0..8 {
+            assert_eq!(sprites.secondary_oam[i * 4 + 1], i as Byte);
+        }
+
+        // out of range
+        assert!(evaluate(&mut sprites, &oam, 18).is_none());
+        assert_eq!(sprites.found, 0);
+        assert_eq!(sprites.secondary_oam[0], 0xFF);
+    }
+
+    #[test]
+    fn test_evaluation_overflow_bug() {
+        // 8 sprites on line 10, the 9th sprite is not on the line. Its
+        // y is read correctly (m = 0), then m advances with n: sprite 10
+        // is checked by its tile byte, which is in range
+        let mut oam = [0xF0; OAM_SIZE];
+        for i in 0..8 {
+            oam[i * 4] = 10;
+        }
+        oam[9 * 4 + 1] = 10;
+        let mut sprites = Sprites::new();
+        assert!(evaluate(&mut sprites, &oam, 10).is_some());
+
+        // a 9th sprite that is in range is missed if m is not 0
+        let mut oam = [0xF0; OAM_SIZE];
+        for i in 0..8 {
+            oam[i * 4] = 10;
+        }
+        oam[9 * 4] = 10;
+        assert!(evaluate(&mut sprites, &oam, 10).is_none());
+    }
+}
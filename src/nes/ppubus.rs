@@ -72,18 +72,22 @@ impl PPUBus {
             // nametable 2,3 are actually mirrored depending on the cartriges
             // mirror mode
             let mirror_mode = cartridge.borrow().get_mirror_mode();
-            let table_id = if mirror_mode == MirrorMode::VERTICAL {
-                match nametable_idx {
+            let table_id = match mirror_mode {
+                MirrorMode::Vertical => match nametable_idx {
                     0 | 2 => 0,
                     1 | 3 => 1,
-                    _ => unreachable!() 
-                }
-            } else {
-                match nametable_idx {
+                    _ => unreachable!()
+                },
+                MirrorMode::SingleScreenLower => 0,
+                MirrorMode::SingleScreenUpper => 1,
+                MirrorMode::Custom(tables) => tables[nametable_idx] & 0x01,
+                // four screen VRAM is answered by the cartridge. Falls back
+                // to horizontal if it doesn't
+                MirrorMode::Horizontal | MirrorMode::FourScreen => match nametable_idx {
                     0 | 1 => 0,
                     2 | 3 => 1,
-                    _ => unreachable!() 
-                }
+                    _ => unreachable!()
+                },
             };
             let rel_addr = addr % 0x400;
             return Some((table_id as usize, rel_addr as usize))
@@ -94,6 +98,17 @@ impl PPUBus {
 
 impl PPUMemory for PPUBus {
     fn readb_ppu(&self, addr: Addr) -> Byte {
+        let data = self.peekb_ppu(addr);
+        // the mapper sees the address after the data was read
+        if let Some(cartridge) = &self.cartridge {
+            if addr < PALETTE_ADDR_RANGE[0] {
+                cartridge.borrow_mut().ppu_bus(addr);
+            }
+        }
+        data
+    }
+
+    fn peekb_ppu(&self, addr: Addr) -> Byte {
         // Palette is never mapped to cartridge
         if PALETTE_ADDR_RANGE[0] <= addr && addr <= PALETTE_ADDR_RANGE[1] {
             let idx = self.map_palette_addr(addr);
@@ -129,7 +144,12 @@ impl PPUMemory for PPUBus {
 
         // give the cartridge a chance to handle the rest
         if let Some(cartridge) = &self.cartridge {
-            if cartridge.borrow_mut().writeb_ppu(addr, data) {
+            let mut cartridge = cartridge.borrow_mut();
+            let handled = cartridge.writeb_ppu(addr, data);
+            if addr < PALETTE_ADDR_RANGE[0] {
+                cartridge.ppu_bus(addr);
+            }
+            if handled {
                 return
             }
         }
@@ -150,6 +170,10 @@ impl PPUMemory for PPUBus {
 pub trait PPUMemory {
     fn readb_ppu(&self, addr: Addr) -> Byte;
     fn writeb_ppu(&mut self, addr: Addr, data: Byte);
+    // read without side effects on the cartridge, for debug views
+    fn peekb_ppu(&self, addr: Addr) -> Byte {
+        self.readb_ppu(addr)
+    }
 }
 
 #[cfg(test)]
@@ -226,7 +250,7 @@ mod tests {
 
     #[test]
     fn test_ppu_memory_nametable_rw() {
-        let mut mem = dummy_ppu_bus(MirrorMode::VERTICAL);
+        let mut mem = dummy_ppu_bus(MirrorMode::Vertical);
 
         // read/write something to nametable memory
         for (idx, addr) in (0x2000 .. 0x2FFF + 1).enumerate() {
@@ -242,7 +266,7 @@ mod tests {
 
     #[test]
     fn test_ppu_memory_nametable_not_overwritten() {
-        let mut mem = dummy_ppu_bus(MirrorMode::VERTICAL);
+        let mut mem = dummy_ppu_bus(MirrorMode::Vertical);
 
         // write some value to whole nametable space        
         for addr in 0x2000 .. 0x3EFF + 1 {
@@ -265,7 +289,7 @@ mod tests {
 
     #[test]
     fn test_ppu_memory_nametable_mirrormode_vertical() {
-        let mut mem = dummy_ppu_bus(MirrorMode::VERTICAL);
+        let mut mem = dummy_ppu_bus(MirrorMode::Vertical);
         // write something to nametable memory and check if vertical mirror
         // has the same data
         
@@ -300,7 +324,7 @@ mod tests {
 
     #[test]
     fn test_ppu_memory_nametable_mirrormode_horizontal() {
-        let mut mem = dummy_ppu_bus(MirrorMode::HORIZONTAL);
+        let mut mem = dummy_ppu_bus(MirrorMode::Horizontal);
         // write something to nametable memory and check if horizontal mirror
         // has the same data
         
@@ -333,9 +357,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_ppu_memory_nametable_mirrormode_single_screen() {
+        let mut lower = dummy_ppu_bus(MirrorMode::SingleScreenLower);
+        let mut upper = dummy_ppu_bus(MirrorMode::SingleScreenUpper);
+        lower.writeb_ppu(0x2C05, 0x11);
+        upper.writeb_ppu(0x2005, 0x22);
+        // all four nametables show the same 1K
+        for addr in [0x2005, 0x2405, 0x2805, 0x2C05].iter() {
+            assert_eq!(0x11, lower.readb_ppu(*addr));
+            assert_eq!(0x22, upper.readb_ppu(*addr));
+        }
+        // and it is the second one for upper
+        assert_eq!(0x22, upper.nametable_memory[1][0x05]);
+        assert_eq!(0x11, lower.nametable_memory[0][0x05]);
+    }
+
     #[test]
     fn test_ppu_memory_nametable_mirroring() {
-        let mut mem = dummy_ppu_bus(MirrorMode::VERTICAL);
+        let mut mem = dummy_ppu_bus(MirrorMode::Vertical);
 
         // read/write something to nametable memory
         for (idx, addr) in (0x2000 .. 0x27FF + 1).enumerate() {